@@ -0,0 +1,332 @@
+// Copyright (C) 2022 the ITK authors
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/./
+
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote, quote_spanned};
+use syn::{
+	parse_macro_input,
+	spanned::Spanned,
+	Attribute,
+	Data,
+	DeriveInput,
+	Fields,
+	Ident,
+	Type,
+	Visibility,
+};
+
+/// Primitive types with no GLSL equivalent
+const UNSUPPORTED_PRIMITIVES: &[&str] = &[
+	"bool", "char", "usize", "isize", "u64", "i64", "u128", "i128",
+];
+
+struct Field {
+	vis: Visibility,
+	ident: Ident,
+	/// Doc comments of the field, copied to the generated structs
+	docs: Vec<Attribute>,
+	/// Type the field is stored as, which is `ty` unless set with `as`
+	stored_ty: Type,
+	/// Set if the field is converted to `stored_ty` with `as`
+	cast: bool,
+	normalized: bool,
+}
+
+/// Derive `DrawableData`, generating `<Name>Packed` and `<Name>Aligned`
+///
+/// Fields may be annotated with `#[drawable(...)]`:
+/// * `normalized`: read integers as normalized floats in vertex attributes
+/// * `as = <type>`: store the field as `<type>`, converting it with `as`
+#[proc_macro_derive(DrawableData, attributes(drawable))]
+pub fn derive_drawable_data(tokens: proc_macro::TokenStream) -> proc_macro::TokenStream {
+	let input = parse_macro_input!(tokens as DeriveInput);
+
+	match derive(input) {
+		Ok(x) => x.into(),
+		Err(e) => e.to_compile_error().into(),
+	}
+}
+
+fn derive(input: DeriveInput) -> syn::Result<TokenStream> {
+	if !input.generics.params.is_empty() || input.generics.where_clause.is_some() {
+		return Err(syn::Error::new(
+			input.generics.span(),
+			"DrawableData cannot be derived for generic structs",
+		))
+	}
+
+	let fields = match &input.data {
+		Data::Struct(data) => match &data.fields {
+			Fields::Named(fields) => &fields.named,
+			_ =>
+				return Err(syn::Error::new(
+					input.ident.span(),
+					"DrawableData can only be derived for structs with named fields",
+				)),
+		},
+		_ =>
+			return Err(syn::Error::new(
+				input.ident.span(),
+				"DrawableData can only be derived for structs",
+			)),
+	};
+
+	if fields.is_empty() {
+		return Err(syn::Error::new(input.ident.span(), "DrawableData needs at least one field"))
+	}
+
+	let fields = fields.iter().map(parse_field).collect::<syn::Result<Vec<_>>>()?;
+
+	let vis = &input.vis;
+	let name = &input.ident;
+	let packed = format_ident!("{name}Packed");
+	let aligned = format_ident!("{name}Aligned");
+	let packed_doc = format!("Vertex attribute layout of [`{name}`]");
+	let aligned_doc = format!("std430 layout of [`{name}`]");
+
+	let field_count = fields.len();
+	let field_trait = fields
+		.iter()
+		.map(|f| {
+			let ty = &f.stored_ty;
+			quote_spanned!(ty.span()=> <#ty as ::gl_painter::drawable::DrawableField>)
+		})
+		.collect::<Vec<_>>();
+
+	let packed_fields = fields.iter().zip(&field_trait).map(|(f, field_trait)| {
+		let (vis, ident, docs) = (&f.vis, &f.ident, &f.docs);
+		quote!(#(#docs)* #vis #ident: #field_trait::Packed)
+	});
+
+	let aligned_fields =
+		fields.iter().zip(&field_trait).enumerate().map(|(i, (f, field_trait))| {
+			let (vis, ident, docs) = (&f.vis, &f.ident, &f.docs);
+			let padding = format_ident!("_padding{i}");
+			quote! {
+				#padding: [u8; #aligned::PADDING[#i]],
+				#(#docs)* #vis #ident: #field_trait::Aligned
+			}
+		});
+
+	let members = field_trait.iter().map(|field_trait| {
+		quote!((::core::mem::size_of::<#field_trait::Aligned>(), #field_trait::STD430_ALIGN))
+	});
+	let members = quote!(&[#(#members),*]);
+
+	let attributes = fields.iter().zip(&field_trait).map(|(f, field_trait)| {
+		let kind = match f.normalized {
+			true => quote!(::core::option::Option::Some(
+				::gl_painter::upload::attribute::AttributeKind::Normalized
+			)),
+			false => quote!(::core::option::Option::None),
+		};
+		quote!((#field_trait::ATTRIBUTES, #kind))
+	});
+	let attribute_count =
+		field_trait.iter().map(|field_trait| quote!(#field_trait::ATTRIBUTES.len()));
+
+	let converted = fields.iter().zip(&field_trait).map(|(f, field_trait)| {
+		let (ident, ty) = (&f.ident, &f.stored_ty);
+		let value = match f.cast {
+			true => quote!(r.#ident as #ty),
+			false => quote!(r.#ident),
+		};
+		(ident, field_trait, value)
+	});
+	let into_packed = converted
+		.clone()
+		.map(|(ident, field_trait, value)| quote!(#ident: #field_trait::into_packed(#value)));
+	let into_aligned = converted.map(
+		|(ident, field_trait, value)| quote!(aligned.#ident = #field_trait::into_aligned(#value);),
+	);
+
+	let last_padding = format_ident!("_padding{field_count}");
+
+	Ok(quote! {
+		#[doc = #packed_doc]
+		#[derive(
+			::core::marker::Copy,
+			::core::clone::Clone,
+			::core::fmt::Debug,
+			::gl_painter::bytemuck::Pod,
+			::gl_painter::bytemuck::Zeroable,
+		)]
+		#[bytemuck(crate = "::gl_painter::bytemuck")]
+		#[repr(C, packed)]
+		#vis struct #packed {
+			#(#packed_fields),*
+		}
+
+		#[doc = #aligned_doc]
+		#[derive(
+			::core::marker::Copy,
+			::core::clone::Clone,
+			::gl_painter::bytemuck::Pod,
+			::gl_painter::bytemuck::Zeroable,
+		)]
+		#[bytemuck(crate = "::gl_painter::bytemuck")]
+		#[repr(C)]
+		#vis struct #aligned {
+			#(#aligned_fields,)*
+			#last_padding: [u8; #aligned::PADDING[#field_count]],
+		}
+
+		impl #aligned {
+			/// Padding before each field to its std430 offset, and after the last field
+			const PADDING: [usize; #field_count + 1] =
+				::gl_painter::drawable::field::std430_padding(#members);
+		}
+
+		impl ::gl_painter::drawable::VertexPassable for #packed {
+			const VERTEX_ATTRIBUTES: &'static [::gl_painter::upload::VertexAttribute] =
+				&::gl_painter::drawable::field::concat_attributes::<{ 0 #(+ #attribute_count)* }>(
+					&[#(#attributes),*],
+				);
+		}
+
+		impl ::gl_painter::drawable::DrawableData for #name {
+			type Ssbo = #aligned;
+			type Compat = #packed;
+
+			#[inline]
+			fn into_ssbo(self) -> Self::Ssbo {
+				self.into()
+			}
+
+			#[inline]
+			fn into_compat(self) -> Self::Compat {
+				self.into()
+			}
+		}
+
+		impl ::gl_painter::drawable::DrawableField for #name {
+			type Packed = #packed;
+			type Aligned = #aligned;
+
+			const ATTRIBUTES: &'static [::gl_painter::upload::VertexAttribute] =
+				<#packed as ::gl_painter::drawable::VertexPassable>::VERTEX_ATTRIBUTES;
+			const STD430_ALIGN: usize = ::gl_painter::drawable::field::std430_align(#members);
+
+			#[inline(always)]
+			fn into_packed(self) -> Self::Packed {
+				self.into()
+			}
+
+			#[inline(always)]
+			fn into_aligned(self) -> Self::Aligned {
+				self.into()
+			}
+		}
+
+		impl ::gl_painter::drawable::ArrayField for #name {
+			type Element = #aligned;
+
+			const ELEMENT_KIND: ::gl_painter::drawable::field::ElementKind =
+				::gl_painter::drawable::field::ElementKind::Other;
+
+			#[inline(always)]
+			fn into_element(self) -> Self::Element {
+				self.into()
+			}
+		}
+
+		impl ::core::convert::From<#name> for #packed {
+			#[inline(always)]
+			fn from(r: #name) -> Self {
+				Self { #(#into_packed),* }
+			}
+		}
+
+		impl ::core::convert::From<#name> for #aligned {
+			#[inline(always)]
+			fn from(r: #name) -> Self {
+				let mut aligned = <Self as ::gl_painter::bytemuck::Zeroable>::zeroed();
+				#(#into_aligned)*
+				aligned
+			}
+		}
+	})
+}
+
+fn parse_field(field: &syn::Field) -> syn::Result<Field> {
+	let mut stored_ty = None;
+	let mut normalized = false;
+
+	for attribute in field.attrs.iter().filter(|a| a.path().is_ident("drawable")) {
+		attribute.parse_nested_meta(|meta| {
+			if meta.path.is_ident("normalized") {
+				match normalized {
+					true => Err(meta.error("`normalized` is already set")),
+					false => {
+						normalized = true;
+						Ok(())
+					},
+				}
+			} else if meta.path.is_ident("as") {
+				match stored_ty {
+					Some(_) => Err(meta.error("`as` is already set")),
+					None => {
+						stored_ty = Some(meta.value()?.parse::<Type>()?);
+						Ok(())
+					},
+				}
+			} else {
+				Err(meta.error("expected `normalized` or `as`"))
+			}
+		})?;
+	}
+
+	let cast = stored_ty.is_some();
+	let stored_ty = stored_ty.unwrap_or_else(|| field.ty.clone());
+	check_type(&stored_ty)?;
+
+	Ok(Field {
+		vis: field.vis.clone(),
+		// only named fields are parsed
+		ident: field.ident.clone().unwrap(),
+		docs: field.attrs.iter().filter(|a| a.path().is_ident("doc")).cloned().collect(),
+		stored_ty,
+		cast,
+		normalized,
+	})
+}
+
+/// Reject types that can never be stored in drawable data
+///
+/// Other unsupported types are caught by the `DrawableField` bound.
+fn check_type(ty: &Type) -> syn::Result<()> {
+	match ty {
+		Type::Array(array) => match &*array.elem {
+			Type::Array(_) => Err(syn::Error::new(
+				array.span(),
+				"arrays of arrays are not supported in drawable data, \
+				 wrap the inner array in a struct deriving `DrawableData`",
+			)),
+			elem => check_type(elem),
+		},
+		Type::Group(group) => check_type(&group.elem),
+		Type::Paren(paren) => check_type(&paren.elem),
+		Type::Path(path) if path.qself.is_none() => {
+			let ident = path.path.get_ident().map(|ident| ident.to_string());
+
+			match ident.as_deref() {
+				Some(ty) if UNSUPPORTED_PRIMITIVES.contains(&ty) => Err(syn::Error::new(
+					path.span(),
+					format!(
+						"`{ty}` has no GLSL equivalent, convert it with \
+							 `#[drawable(as = u32)]` or another supported type"
+					),
+				)),
+				_ => Ok(()),
+			}
+		},
+		_ => Err(syn::Error::new(
+			ty.span(),
+			"unsupported drawable data field, expected a scalar, vector, \
+			 type deriving `DrawableData`, or array of them",
+		)),
+	}
+}
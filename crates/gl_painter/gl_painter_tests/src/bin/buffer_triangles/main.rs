@@ -8,11 +8,17 @@
 
 use gl_painter::{drawable::ColoredTriangle, upload, upload::Uploader};
 
+/// Number of frames between printing upload statistics
+const STATS_INTERVAL: u32 = 120;
+
 fn main() {
 	gl_painter_tests::view_window(true, || {
-		let mut uploader = unsafe { upload::ssbo::SsboUploader::new() };
+		let mut uploader = unsafe { upload::new::<ColoredTriangle>() }
+			.expect("could not create triangle uploader");
 
 		let mut anim_t = 0.0;
+		let mut frame = 0u32;
+		let mut last_stats = uploader.stats();
 
 		// loop
 		move || {
@@ -50,6 +56,27 @@ fn main() {
 
 				uploader.upload();
 			}
+
+			frame += 1;
+			if frame % STATS_INTERVAL == 0 {
+				let stats = uploader.stats();
+				let since = stats.since(&last_stats);
+				let buffers = since.buffer_total();
+
+				println!(
+					"{STATS_INTERVAL} frames: {} draw calls, {} vertices, {} bytes uploaded, \
+					 {} allocations, {:?} fence wait, {:?} frame wait, {} bytes in buffers",
+					since.draws.draw_calls,
+					since.draws.vertices,
+					buffers.bytes_uploaded,
+					buffers.allocations,
+					buffers.fence_wait,
+					since.frame_wait,
+					buffers.size,
+				);
+
+				last_stats = stats;
+			}
 		}
 	});
 }
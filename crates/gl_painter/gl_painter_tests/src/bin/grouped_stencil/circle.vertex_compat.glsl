@@ -13,11 +13,13 @@ flat out vec3 f_color;
 flat out uint f_stencil;
 
 void main() {
-	f_pos = v_pos;
+	// v_pos is a corner of the unit quad
+	vec2 pos = v_origin + v_pos * v_radius;
+	f_pos = pos;
 	f_origin = v_origin;
 	f_radius = v_radius;
 	f_color = v_color;
 	f_stencil = v_stencil;
 
-	gl_Position = vec4(v_pos, 0.0, 1.0);
+	gl_Position = vec4(pos, 0.0, 1.0);
 }
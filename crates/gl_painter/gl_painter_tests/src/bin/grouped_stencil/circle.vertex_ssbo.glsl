@@ -6,7 +6,7 @@ layout(location = 1) in uint s_index;
 struct DrawableSSBO {
 	vec2 origin;
 	float radius;
-	vec4 color;
+	vec3 color;
 	uint stencil;
 };
 
@@ -22,11 +22,13 @@ flat out uint f_stencil;
 
 void main() {
 	DrawableSSBO v_ssbo = ssbo[s_index];
-	f_pos = v_pos;
+	// v_pos is a corner of the unit quad
+	vec2 pos = v_ssbo.origin + v_pos * v_ssbo.radius;
+	f_pos = pos;
 	f_origin = v_ssbo.origin;
 	f_radius = v_ssbo.radius;
-	f_color = v_ssbo.color.rgb;
+	f_color = v_ssbo.color;
 	f_stencil = v_ssbo.stencil;
 
-	gl_Position = vec4(v_pos, 0.0, 1.0);
+	gl_Position = vec4(pos, 0.0, 1.0);
 }
@@ -14,31 +14,33 @@ use std::ffi::CString;
 
 use gl::types::GLenum;
 use gl_painter::{
-	drawable::{Drawable, ShaderSource},
-	drawable_data,
+	drawable::{Drawable, DrawableData, InstancedDrawable, ShaderSource, StageSource, Vec2, Vec3},
+	glsl_origin,
 	upload::{self, Uploader},
 };
 use rand::Rng;
 
 fn main() {
 	gl_painter_tests::view_window(true, || {
-		let mut triangle_uploader = unsafe { upload::compat::CompatUploader::<Triangle>::new() };
-		let mut circle_uploader = unsafe { upload::ssbo::SsboUploader::<Circle>::new() };
+		let mut triangle_uploader = unsafe { upload::compat::CompatUploader::<Triangle>::new() }
+			.expect("could not create triangle uploader");
+		let mut circle_uploader = unsafe { upload::instanced::InstancedUploader::<Circle>::new() }
+			.expect("could not create circle uploader");
 
 		struct StencilGroup {
 			stencil: u16,
-			color: [f32; 3],
+			color: Vec3,
 		}
 
 		let stencil_groups = (1..4)
 			.into_iter()
 			.map(|i| StencilGroup {
 				stencil: i,
-				color: [
+				color: Vec3::new(
 					rand::thread_rng().gen_range(0.0..1.0),
 					rand::thread_rng().gen_range(0.0..1.0),
 					rand::thread_rng().gen_range(0.0..1.0),
-				],
+				),
 			})
 			.collect::<Vec<_>>();
 
@@ -46,18 +48,18 @@ fn main() {
 			.iter()
 			.map(|group| MovingShape {
 				shape: Circle {
-					origin: [
+					origin: Vec2::new(
 						rand::thread_rng().gen_range(-0.5..0.5),
 						rand::thread_rng().gen_range(-0.5..0.5),
-					],
+					),
 					radius: rand::thread_rng().gen_range(0.2..0.4),
 					color: group.color,
 					stencil: group.stencil,
 				},
-				movement: [
+				movement: Vec2::new(
 					rand::thread_rng().gen_range(-0.2..0.2),
 					rand::thread_rng().gen_range(-0.2..0.2),
-				],
+				),
 				bounce: true,
 				offscreen: false,
 			})
@@ -66,7 +68,7 @@ fn main() {
 		let mut triangles = Vec::<MovingShape<Triangle>>::new();
 
 		unsafe fn upload_circles(
-			uploader: &mut upload::ssbo::SsboUploader<Circle>,
+			uploader: &mut upload::instanced::InstancedUploader<Circle>,
 			circles: &[MovingShape<Circle>],
 		) {
 			uploader.prepare_write();
@@ -223,15 +225,15 @@ fn main() {
 						&stencil_groups[rand::thread_rng().gen_range(0..stencil_groups.len())];
 					triangles.push(MovingShape {
 						shape: Triangle {
-							position: [
+							position: Vec2::new(
 								rand::thread_rng().gen_range(-1.0 + size..(1.0 - size * 2.0)),
 								-1.0 - size,
-							],
+							),
 							size,
 							color: group.color,
 							stencil: group.stencil,
 						},
-						movement: [0.0, rand::thread_rng().gen_range(0.05..0.2)],
+						movement: Vec2::new(0.0, rand::thread_rng().gen_range(0.05..0.2)),
 						bounce: false,
 						offscreen: false,
 					});
@@ -244,40 +246,48 @@ fn main() {
 	});
 }
 
-drawable_data!(CircleData {
+#[derive(Clone, DrawableData)]
+pub struct CircleData {
 	origin: Vec2,
 	radius: f32,
 	color: Vec3,
 	stencil: u32,
-});
+}
 
-drawable_data!(CircleVertex { position: Vec2 });
+#[derive(Clone, DrawableData)]
+pub struct CircleVertex {
+	position: Vec2,
+}
 
-drawable_data!(TriangleData {
+#[derive(Clone, DrawableData)]
+pub struct TriangleData {
 	color: Vec3,
 	stencil: u32,
-});
+}
 
-drawable_data!(TriangleVertex { position: Vec2 });
+#[derive(Clone, DrawableData)]
+pub struct TriangleVertex {
+	position: Vec2,
+}
 
 trait Shape {
-	fn position(&mut self) -> &mut [f32; 2];
-	fn shape(&self) -> [[f32; 2]; 2];
+	fn position(&mut self) -> &mut Vec2;
+	fn shape(&self) -> [Vec2; 2];
 }
 
 #[derive(Clone)]
 struct Circle {
-	origin: [f32; 2],
+	origin: Vec2,
 	radius: f32,
-	color: [f32; 3],
+	color: Vec3,
 	stencil: u16,
 }
 
 #[derive(Clone)]
 struct Triangle {
-	position: [f32; 2],
+	position: Vec2,
 	size: f32,
-	color: [f32; 3],
+	color: Vec3,
 	stencil: u16,
 }
 
@@ -287,16 +297,31 @@ impl Drawable for Circle {
 
 	const GL_TYPE: GLenum = gl::TRIANGLES;
 	const SHADER_SOURCE: ShaderSource = ShaderSource {
-		vertex_compat: include_str!("circle.vertex_compat.glsl"),
-		vertex_ssbo: include_str!("circle.vertex_ssbo.glsl"),
-		fragment: include_str!("circle.fragment.glsl"),
+		vertex_compat: StageSource {
+			source: include_str!("circle.vertex_compat.glsl"),
+			origin: Some(glsl_origin! {
+				shader: vert "src/bin/grouped_stencil/circle.vertex_compat.glsl",
+			}),
+		},
+		vertex_ssbo: StageSource {
+			source: include_str!("circle.vertex_ssbo.glsl"),
+			origin: Some(glsl_origin! {
+				shader: vert "src/bin/grouped_stencil/circle.vertex_ssbo.glsl",
+			}),
+		},
+		fragment: StageSource {
+			source: include_str!("circle.fragment.glsl"),
+			origin: Some(glsl_origin! {
+				shader: frag "src/bin/grouped_stencil/circle.fragment.glsl",
+			}),
+		},
 	};
 
 	fn drawable_data(&self) -> Self::Drawable {
 		CircleData {
-			origin: self.origin.into(),
+			origin: self.origin,
 			radius: self.radius,
-			color: self.color.into(),
+			color: self.color,
 			stencil: self.stencil as u32,
 		}
 	}
@@ -311,15 +336,17 @@ impl Drawable for Circle {
 		>,
 		impl IntoIterator<Item = u32, IntoIter = impl ExactSizeIterator<Item = u32> + 's>,
 	) {
+		Self::instance_geometry()
+	}
+}
+
+impl InstancedDrawable for Circle {
+	fn instance_geometry() -> (Vec<Self::Vertex>, Vec<u32>) {
 		(
-			[(-1, -1), (-1, 1), (1, -1), (1, 1)].map(|(x, y)| CircleVertex {
-				position: [
-					self.origin[0] + (self.radius * x as f32),
-					self.origin[1] + (self.radius * y as f32),
-				]
-				.into(),
-			}),
-			[0, 1, 2, 1, 2, 3],
+			[[-1.0, -1.0], [-1.0, 1.0], [1.0, -1.0], [1.0, 1.0]]
+				.map(|p| CircleVertex { position: p.into() })
+				.to_vec(),
+			vec![0, 1, 2, 1, 2, 3],
 		)
 	}
 }
@@ -330,14 +357,29 @@ impl Drawable for Triangle {
 
 	const GL_TYPE: GLenum = gl::TRIANGLES;
 	const SHADER_SOURCE: ShaderSource = ShaderSource {
-		vertex_compat: include_str!("triangle.vertex_compat.glsl"),
-		vertex_ssbo: include_str!("triangle.vertex_ssbo.glsl"),
-		fragment: include_str!("triangle.fragment.glsl"),
+		vertex_compat: StageSource {
+			source: include_str!("triangle.vertex_compat.glsl"),
+			origin: Some(glsl_origin! {
+				shader: vert "src/bin/grouped_stencil/triangle.vertex_compat.glsl",
+			}),
+		},
+		vertex_ssbo: StageSource {
+			source: include_str!("triangle.vertex_ssbo.glsl"),
+			origin: Some(glsl_origin! {
+				shader: vert "src/bin/grouped_stencil/triangle.vertex_ssbo.glsl",
+			}),
+		},
+		fragment: StageSource {
+			source: include_str!("triangle.fragment.glsl"),
+			origin: Some(glsl_origin! {
+				shader: frag "src/bin/grouped_stencil/triangle.fragment.glsl",
+			}),
+		},
 	};
 
 	fn drawable_data(&self) -> Self::Drawable {
 		TriangleData {
-			color: self.color.into(),
+			color: self.color,
 			stencil: self.stencil as u32,
 		}
 	}
@@ -354,48 +396,40 @@ impl Drawable for Triangle {
 	) {
 		(
 			[
-				[self.position[0], self.position[1] + self.size],
-				[self.position[0] - self.size, self.position[1] - self.size],
-				[self.position[0] + self.size, self.position[1] - self.size],
+				self.position + Vec2::new(0.0, self.size),
+				self.position - Vec2::splat(self.size),
+				self.position + Vec2::new(self.size, -self.size),
 			]
 			.into_iter()
-			.map(|p| TriangleVertex { position: p.into() }),
+			.map(|position| TriangleVertex { position }),
 			[0, 1, 2],
 		)
 	}
 }
 
 impl Shape for Circle {
-	fn position(&mut self) -> &mut [f32; 2] {
+	fn position(&mut self) -> &mut Vec2 {
 		&mut self.origin
 	}
 
-	#[rustfmt::skip]
-	fn shape(&self) -> [[f32; 2]; 2] {
-		[
-			[-self.radius, -self.radius],
-			[self.radius, self.radius],
-		]
+	fn shape(&self) -> [Vec2; 2] {
+		[Vec2::splat(-self.radius), Vec2::splat(self.radius)]
 	}
 }
 
 impl Shape for Triangle {
-	fn position(&mut self) -> &mut [f32; 2] {
+	fn position(&mut self) -> &mut Vec2 {
 		&mut self.position
 	}
 
-	#[rustfmt::skip]
-	fn shape(&self) -> [[f32; 2]; 2] {
-		[
-			[-self.size, -self.size],
-			[self.size, self.size],
-		]
+	fn shape(&self) -> [Vec2; 2] {
+		[Vec2::splat(-self.size), Vec2::splat(self.size)]
 	}
 }
 
 struct MovingShape<S: Shape> {
 	shape: S,
-	movement: [f32; 2],
+	movement: Vec2,
 	bounce: bool,
 	offscreen: bool,
 }
@@ -405,7 +439,7 @@ impl<S: Shape> MovingShape<S> {
 		let m = delta.as_secs_f32() * 10.0;
 
 		let pos = *self.shape.position();
-		let mut newpos = [pos[0] + self.movement[0] * m, pos[1] + self.movement[1] * m];
+		let mut newpos = pos + self.movement * m;
 
 		let bounds = self.shape.shape();
 		if self.bounce {
@@ -4,7 +4,7 @@ layout(location = 0) in vec2 v_pos;
 layout(location = 1) in uint s_index;
 
 struct DrawableSSBO {
-	vec4 color;
+	vec3 color;
 	uint stencil;
 };
 
@@ -18,7 +18,7 @@ flat out uint f_stencil;
 
 void main() {
 	DrawableSSBO v_ssbo = ssbo[s_index];
-	f_color = v_ssbo.color.rgb;
+	f_color = v_ssbo.color;
 	f_stencil_pos = (v_pos + 1.0) / 2.0;
 	f_stencil = v_ssbo.stencil;
 
@@ -18,7 +18,6 @@ use syn::{
 	Token,
 };
 
-mod preprocessor;
 mod validate;
 
 struct PreprocessData {
@@ -135,6 +134,8 @@ impl Parse for PreprocessData {
 	}
 }
 
+/// Preprocess and validate a shader stage at build time, expanding to a
+/// `gl_painter::drawable::StageSource` with its origin set
 #[proc_macro]
 pub fn preprocess_glsl(tokens: proc_macro::TokenStream) -> proc_macro::TokenStream {
 	let preprocess_data = parse_macro_input!(tokens as PreprocessData);
@@ -165,11 +166,11 @@ pub fn preprocess_glsl(tokens: proc_macro::TokenStream) -> proc_macro::TokenStre
 			.map(|(k, v)| (k.to_string(), v.to_string()))
 			.collect::<HashMap<String, String>>();
 
-		let (src, line_mapping) = preprocessor::preprocess(
+		let (src, line_mapping) = glsl_preprocessor::preprocess(
 			&shader_source,
 			filepath.parent().unwrap(),
 			Path::new(&manifest_dir),
-			defines,
+			defines.clone(),
 		)
 		.map_err(|e| syn::Error::new(Span::call_site(), format!("error in shader: {e:#}")))?;
 
@@ -180,7 +181,26 @@ pub fn preprocess_glsl(tokens: proc_macro::TokenStream) -> proc_macro::TokenStre
 			)
 		})?;
 
-		Ok(format!("{src:?}").parse().unwrap())
+		let mut define_pairs = defines.iter().collect::<Vec<_>>();
+		define_pairs.sort();
+		let define_pairs = define_pairs
+			.into_iter()
+			.map(|(k, v)| format!("({k:?}, {v:?}),"))
+			.collect::<String>();
+
+		Ok(format!(
+			"::gl_painter::drawable::StageSource {{
+				source: {src:?},
+				origin: ::core::option::Option::Some(::gl_painter::drawable::StageOrigin {{
+					path: {path:?},
+					basedir: {manifest_dir:?},
+					defines: &[{define_pairs}],
+				}}),
+			}}",
+			path = filepath.to_string_lossy(),
+		)
+		.parse()
+		.unwrap())
 	})();
 
 	match r {
@@ -6,7 +6,7 @@
 
 use std::{collections::HashMap, io::Write};
 
-use crate::preprocessor::LineId;
+use glsl_preprocessor::LineId;
 
 pub fn validate_shader(
 	source: &str,
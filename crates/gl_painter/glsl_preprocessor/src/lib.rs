@@ -0,0 +1,13 @@
+// Copyright (C) 2022 the ITK authors
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/./
+
+//! GLSL preprocessor used by `glsl_preprocess::preprocess_glsl!`
+//!
+//! Kept separate from the proc macro crate so it can also be run at runtime.
+
+mod preprocessor;
+
+pub use preprocessor::*;
@@ -4,7 +4,15 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/./
 
-use std::{borrow::Cow, collections::HashMap, fmt::Debug, fs, io, path::Path, rc::Rc};
+use std::{
+	borrow::Cow,
+	collections::HashMap,
+	fmt::Debug,
+	fs,
+	io,
+	path::{Path, PathBuf},
+	rc::Rc,
+};
 
 #[cfg(test)]
 mod test;
@@ -63,6 +71,31 @@ impl Debug for LineId {
 	}
 }
 
+impl LineId {
+	/// The `@include` argument this line was included with,
+	/// or `None` if it is part of the root file
+	pub fn file(&self) -> Option<&str> {
+		self.file.as_deref().map(|f| f.as_str())
+	}
+
+	pub fn line(&self) -> usize {
+		self.line
+	}
+}
+
+/// Resolve the path of an `@include` argument
+///
+/// Paths starting with `/` are relative to `basedir`,
+/// all others are relative to `dir`.
+pub fn resolve_include(include: &str, dir: &Path, basedir: &Path) -> PathBuf {
+	let p = include.trim();
+	if p.starts_with('/') {
+		basedir.join(Path::new(&p[1..]))
+	} else {
+		dir.join(Path::new(p))
+	}
+}
+
 pub fn preprocess(
 	source: &str,
 	dir: &Path,
@@ -290,14 +323,7 @@ pub fn preprocess(
 				"include" => {
 					let (file, filename) = match args {
 						Some(x) => {
-							let path = {
-								let p = x.trim();
-								if p.starts_with('/') {
-									basedir.join(Path::new(&p[1..]))
-								} else {
-									dir.join(Path::new(p))
-								}
-							};
+							let path = resolve_include(x, dir, basedir);
 
 							(
 								fs::read_to_string(&path).map_err(|e| PreprocError {
@@ -0,0 +1,276 @@
+// Copyright (C) 2022 the ITK authors
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/./
+
+//! Detection of what the current GL context supports
+
+use std::{cell::RefCell, collections::HashSet, ffi::CStr, rc::Rc};
+
+use gl::types::{GLenum, GLint, GLint64};
+
+#[cfg(test)]
+mod test;
+
+thread_local! {
+	static THREAD_CAPABILITIES: RefCell<Option<Rc<GlCapabilities>>> = const { RefCell::new(None) };
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct GlVersion {
+	pub major: u32,
+	pub minor: u32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GlProfile {
+	Core,
+	Compatibility,
+	/// GLES or pre-3.2 contexts, which have no profiles
+	None,
+}
+
+#[derive(Debug, Default)]
+pub struct GlLimits {
+	/// `GL_MAX_SHADER_STORAGE_BLOCK_SIZE`, 0 without SSBO support
+	pub max_shader_storage_block_size: u64,
+	/// `GL_MAX_VERTEX_SHADER_STORAGE_BLOCKS`, 0 without SSBO support
+	pub max_vertex_shader_storage_blocks: u32,
+	/// `GL_SHADER_STORAGE_BUFFER_OFFSET_ALIGNMENT`, 0 without SSBO support
+	pub shader_storage_buffer_offset_alignment: u32,
+	pub max_vertex_attribs: u32,
+	pub max_texture_size: u32,
+}
+
+/// Extensions relevant to gl_painter
+///
+/// Extensions promoted to core are only listed here if the driver advertises
+/// them, use the `GlCapabilities` methods to also account for the GL version.
+#[derive(Debug, Default)]
+pub struct GlExtensions {
+	pub arb_buffer_storage: bool,
+	pub arb_direct_state_access: bool,
+	pub arb_draw_elements_base_vertex: bool,
+	pub arb_es3_compatibility: bool,
+	pub arb_get_program_binary: bool,
+	pub arb_instanced_arrays: bool,
+	pub arb_multi_draw_indirect: bool,
+	pub arb_shader_draw_parameters: bool,
+	pub arb_shader_storage_buffer_object: bool,
+	pub arb_vertex_attrib_64bit: bool,
+	pub arb_vertex_type_2_10_10_10_rev: bool,
+	pub khr_debug: bool,
+	/// `KHR_parallel_shader_compile` or `ARB_parallel_shader_compile`
+	pub parallel_shader_compile: bool,
+}
+
+/// Capabilities of a GL context
+///
+/// Querying capabilities is slow, so they are cached per thread
+/// (see `GlCapabilities::get`).
+#[derive(Debug)]
+pub struct GlCapabilities {
+	pub version: GlVersion,
+	/// `None` if the driver returned a malformed version string
+	pub glsl_version: Option<GlVersion>,
+	pub profile: GlProfile,
+	pub is_gles: bool,
+	pub vendor: String,
+	pub renderer: String,
+	pub version_string: String,
+	pub limits: GlLimits,
+	pub extensions: GlExtensions,
+}
+
+impl GlCapabilities {
+	/// Get the capabilities of this thread's context, querying them on first use
+	///
+	/// Capabilities are cached per thread, not per context. Call `invalidate`
+	/// after making another context current on this thread, or the previous
+	/// context's capabilities are returned.
+	///
+	/// # SAFETY
+	/// * must be called from GL thread
+	pub unsafe fn get() -> Rc<GlCapabilities> {
+		THREAD_CAPABILITIES
+			.with(|c| c.borrow_mut().get_or_insert_with(|| Rc::new(Self::query())).clone())
+	}
+
+	/// Forget this thread's cached capabilities
+	pub fn invalidate() {
+		THREAD_CAPABILITIES.with(|c| c.replace(None));
+	}
+
+	/// Query the current context's capabilities
+	///
+	/// # SAFETY
+	/// * must be called from GL thread
+	pub unsafe fn query() -> Self {
+		let version_string = get_string(gl::VERSION);
+		let is_gles = version_string.starts_with("OpenGL ES");
+
+		let (mut major, mut minor) = (0 as GLint, 0 as GLint);
+		gl::GetIntegerv(gl::MAJOR_VERSION, &mut major);
+		gl::GetIntegerv(gl::MINOR_VERSION, &mut minor);
+
+		let version = match major {
+			// MAJOR_VERSION is only queryable from 3.0
+			0 => parse_version(&version_string).unwrap_or(GlVersion { major: 0, minor: 0 }),
+			_ => GlVersion {
+				major: major as u32,
+				minor: minor as u32,
+			},
+		};
+
+		let profile = match is_gles || version < GlVersion::new(3, 2) {
+			true => GlProfile::None,
+			false => {
+				let mut mask = 0 as GLint;
+				gl::GetIntegerv(gl::CONTEXT_PROFILE_MASK, &mut mask);
+
+				match mask as GLenum & gl::CONTEXT_CORE_PROFILE_BIT {
+					0 => GlProfile::Compatibility,
+					_ => GlProfile::Core,
+				}
+			},
+		};
+
+		let mut extension_count = 0;
+		gl::GetIntegerv(gl::NUM_EXTENSIONS, &mut extension_count);
+
+		let extension_names = (0..extension_count)
+			.map(|i| {
+				CStr::from_ptr(gl::GetStringi(gl::EXTENSIONS, i as u32) as *const i8)
+					.to_string_lossy()
+					.into_owned()
+			})
+			.collect::<HashSet<_>>();
+		let has = |name: &str| extension_names.contains(name);
+
+		let extensions = GlExtensions {
+			arb_buffer_storage: has("GL_ARB_buffer_storage"),
+			arb_direct_state_access: has("GL_ARB_direct_state_access"),
+			arb_draw_elements_base_vertex: has("GL_ARB_draw_elements_base_vertex"),
+			arb_es3_compatibility: has("GL_ARB_ES3_compatibility"),
+			arb_get_program_binary: has("GL_ARB_get_program_binary"),
+			arb_instanced_arrays: has("GL_ARB_instanced_arrays"),
+			arb_multi_draw_indirect: has("GL_ARB_multi_draw_indirect"),
+			arb_shader_draw_parameters: has("GL_ARB_shader_draw_parameters"),
+			arb_shader_storage_buffer_object: has("GL_ARB_shader_storage_buffer_object"),
+			arb_vertex_attrib_64bit: has("GL_ARB_vertex_attrib_64bit"),
+			arb_vertex_type_2_10_10_10_rev: has("GL_ARB_vertex_type_2_10_10_10_rev"),
+			khr_debug: has("GL_KHR_debug"),
+			parallel_shader_compile: has("GL_KHR_parallel_shader_compile")
+				|| has("GL_ARB_parallel_shader_compile"),
+		};
+
+		let mut capabilities = Self {
+			version,
+			glsl_version: parse_version(&get_string(gl::SHADING_LANGUAGE_VERSION)),
+			profile,
+			is_gles,
+			vendor: get_string(gl::VENDOR),
+			renderer: get_string(gl::RENDERER),
+			version_string,
+			limits: GlLimits::default(),
+			extensions,
+		};
+
+		let get_u32 = |name: GLenum| {
+			let mut value = 0 as GLint;
+			gl::GetIntegerv(name, &mut value);
+			value.max(0) as u32
+		};
+
+		capabilities.limits.max_vertex_attribs = get_u32(gl::MAX_VERTEX_ATTRIBS);
+		capabilities.limits.max_texture_size = get_u32(gl::MAX_TEXTURE_SIZE);
+
+		if capabilities.has_shader_storage_buffers() {
+			// may not fit in a GLint
+			let mut block_size = 0 as GLint64;
+			gl::GetInteger64v(gl::MAX_SHADER_STORAGE_BLOCK_SIZE, &mut block_size);
+
+			capabilities.limits.max_shader_storage_block_size = block_size.max(0) as u64;
+			capabilities.limits.max_vertex_shader_storage_blocks =
+				get_u32(gl::MAX_VERTEX_SHADER_STORAGE_BLOCKS);
+			capabilities.limits.shader_storage_buffer_offset_alignment =
+				get_u32(gl::SHADER_STORAGE_BUFFER_OFFSET_ALIGNMENT);
+		}
+
+		capabilities
+	}
+
+	/// Check if the context is at least the given desktop GL version
+	pub fn is_gl(&self, major: u32, minor: u32) -> bool {
+		!self.is_gles && self.version >= GlVersion::new(major, minor)
+	}
+
+	pub fn has_buffer_storage(&self) -> bool {
+		self.is_gl(4, 4) || self.extensions.arb_buffer_storage
+	}
+
+	pub fn has_direct_state_access(&self) -> bool {
+		self.is_gl(4, 5) || self.extensions.arb_direct_state_access
+	}
+
+	pub fn has_shader_storage_buffers(&self) -> bool {
+		self.is_gl(4, 3) || self.extensions.arb_shader_storage_buffer_object
+	}
+
+	pub fn has_multi_draw_indirect(&self) -> bool {
+		self.is_gl(4, 3) || self.extensions.arb_multi_draw_indirect
+	}
+
+	/// `GL_PRIMITIVE_RESTART_FIXED_INDEX`, as opposed to `glPrimitiveRestartIndex`
+	pub fn has_primitive_restart_fixed_index(&self) -> bool {
+		self.is_gl(4, 3) || self.extensions.arb_es3_compatibility
+	}
+
+	pub fn has_program_binary(&self) -> bool {
+		self.is_gl(4, 1) || self.extensions.arb_get_program_binary
+	}
+
+	pub fn has_vertex_attrib_64bit(&self) -> bool {
+		self.is_gl(4, 1) || self.extensions.arb_vertex_attrib_64bit
+	}
+
+	pub fn has_vertex_type_2_10_10_10_rev(&self) -> bool {
+		self.is_gl(3, 3) || self.extensions.arb_vertex_type_2_10_10_10_rev
+	}
+
+	pub fn has_parallel_shader_compile(&self) -> bool {
+		self.extensions.parallel_shader_compile
+	}
+}
+
+impl GlVersion {
+	pub const fn new(major: u32, minor: u32) -> Self {
+		Self { major, minor }
+	}
+}
+
+/// # SAFETY
+/// * must be called from GL thread
+unsafe fn get_string(name: GLenum) -> String {
+	let string = gl::GetString(name);
+
+	match string.is_null() {
+		true => String::new(),
+		false => CStr::from_ptr(string as *const i8).to_string_lossy().into_owned(),
+	}
+}
+
+/// Parse the first `major.minor` pair of a GL or GLSL version string
+///
+/// Accepts strings like `4.6.0 NVIDIA 535.54`, `OpenGL ES 3.2 Mesa 23.1`
+/// and `OpenGL ES GLSL ES 3.20`.
+fn parse_version(string: &str) -> Option<GlVersion> {
+	let version = string.split(' ').find(|s| s.starts_with(|c: char| c.is_ascii_digit()))?;
+	let mut parts = version.split('.');
+
+	let major = parts.next()?.parse().ok()?;
+	let minor = parts.next()?.split(|c: char| !c.is_ascii_digit()).next()?.parse().ok()?;
+
+	Some(GlVersion { major, minor })
+}
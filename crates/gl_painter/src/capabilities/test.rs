@@ -0,0 +1,12 @@
+use super::{parse_version, GlVersion};
+
+#[test]
+fn test_parse_version() {
+	assert_eq!(parse_version("4.6.0 NVIDIA 535.54.03"), Some(GlVersion::new(4, 6)));
+	assert_eq!(parse_version("4.60 NVIDIA"), Some(GlVersion::new(4, 60)));
+	assert_eq!(parse_version("OpenGL ES 3.2 Mesa 23.1.3"), Some(GlVersion::new(3, 2)));
+	assert_eq!(parse_version("OpenGL ES GLSL ES 3.20"), Some(GlVersion::new(3, 20)));
+	assert_eq!(parse_version("3.3 (Core Profile) Mesa 23.1.3"), Some(GlVersion::new(3, 3)));
+	assert_eq!(parse_version("garbage"), None);
+	assert_eq!(parse_version(""), None);
+}
@@ -4,17 +4,20 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/./
 
-use std::error::Error;
-
 use gl::types::GLenum;
+pub use gl_painter_derive::DrawableData;
 
+pub use self::field::{ArrayField, DrawableField};
 use crate::{
-	shader::{Shader, ShaderProgram, ShaderType},
+	shader::{pending::PendingProgram, ProgramCreateError, ShaderProgram, ShaderVariant},
 	upload::VertexAttribute,
 };
 
 pub mod colored_triangle;
 pub use colored_triangle::*;
+pub mod field;
+pub mod vec;
+pub use vec::*;
 
 pub trait Drawable {
 	type Drawable: DrawableData;
@@ -22,8 +25,21 @@ pub trait Drawable {
 
 	const GL_TYPE: GLenum;
 	const SHADER_SOURCE: ShaderSource;
+	/// Set if `GL_TYPE` is a strip, loop or fan, which uploaders
+	/// separate with primitive restart when batching
+	const IS_STRIP: bool = is_strip_primitive(Self::GL_TYPE);
+	/// Unset if vertices are drawn in order without an index list,
+	/// in which case the indices returned by `drawable_vertices` are ignored
+	const INDEXED: bool = true;
 
 	fn drawable_data(&self) -> Self::Drawable;
+	/// Area covered by the drawable, used by `DrawList` to reorder
+	/// drawables that do not overlap
+	///
+	/// `None` is treated as overlapping everything.
+	fn bounding_box(&self) -> Option<BoundingBox> {
+		None
+	}
 	/// # Note
 	///
 	/// Remember to pray to the LLVM gods. Make everything possible
@@ -40,77 +56,182 @@ pub trait Drawable {
 	);
 }
 
+/// Drawable whose vertices and indices are the same for every drawable,
+/// allowing it to be drawn with `InstancedUploader`
+///
+/// `drawable_vertices` should return the same geometry as `instance_geometry`,
+/// with anything that differs between drawables computed by the vertex shader
+/// from the drawable data. The compat shader variant is used for instancing,
+/// with the drawable data attributes advancing per instance.
+pub trait InstancedDrawable: Drawable {
+	/// Vertices and indices shared by every instance
+	fn instance_geometry() -> (Vec<Self::Vertex>, Vec<u32>);
+}
+
+/// Check if primitives of the given type share vertices with their neighbours
+pub const fn is_strip_primitive(mode: GLenum) -> bool {
+	matches!(
+		mode,
+		gl::LINE_STRIP
+			| gl::LINE_LOOP
+			| gl::LINE_STRIP_ADJACENCY
+			| gl::TRIANGLE_STRIP
+			| gl::TRIANGLE_FAN
+			| gl::TRIANGLE_STRIP_ADJACENCY
+	)
+}
+
+/// Axis aligned rectangle in the same space as a drawable's vertex positions
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BoundingBox {
+	pub min: [f32; 2],
+	pub max: [f32; 2],
+}
+
+impl BoundingBox {
+	/// Smallest box containing every point, or `None` if there are none
+	pub fn from_points(points: impl IntoIterator<Item = [f32; 2]>) -> Option<Self> {
+		let mut points = points.into_iter();
+		let first = points.next()?;
+
+		Some(points.fold(
+			Self {
+				min: first,
+				max: first,
+			},
+			|bounds, [x, y]| Self {
+				min: [bounds.min[0].min(x), bounds.min[1].min(y)],
+				max: [bounds.max[0].max(x), bounds.max[1].max(y)],
+			},
+		))
+	}
+
+	/// Smallest box containing both boxes
+	pub fn union(&self, other: &Self) -> Self {
+		Self {
+			min: [self.min[0].min(other.min[0]), self.min[1].min(other.min[1])],
+			max: [self.max[0].max(other.max[0]), self.max[1].max(other.max[1])],
+		}
+	}
+
+	/// Check if the boxes intersect, counting shared edges
+	/// since antialiasing may touch pixels on both sides
+	pub fn overlaps(&self, other: &Self) -> bool {
+		self.min[0] <= other.max[0]
+			&& other.min[0] <= self.max[0]
+			&& self.min[1] <= other.max[1]
+			&& other.min[1] <= self.max[1]
+	}
+}
+
 pub trait VertexPassable {
 	const VERTEX_ATTRIBUTES: &'static [VertexAttribute];
 }
 
 pub struct ShaderSource {
-	pub vertex_compat: &'static str,
-	pub vertex_ssbo: &'static str,
-	pub fragment: &'static str,
+	pub vertex_compat: StageSource,
+	pub vertex_ssbo: StageSource,
+	pub fragment: StageSource,
+}
+
+/// Source of a shader stage
+///
+/// Usually created with `glsl_preprocess::preprocess_glsl!`,
+/// which also sets its origin.
+#[derive(Clone, Copy)]
+pub struct StageSource {
+	pub source: &'static str,
+	/// File the source was preprocessed from, used to reload it at runtime
+	pub origin: Option<StageOrigin>,
+}
+
+/// Source file and defines a shader stage was preprocessed with
+///
+/// Created by `glsl_preprocess::preprocess_glsl!`, or with `glsl_origin!`
+/// for stages that are not preprocessed at build time.
+#[derive(Clone, Copy)]
+pub struct StageOrigin {
+	/// Absolute path of the source file
+	pub path: &'static str,
+	/// Directory `/`-prefixed includes are resolved from
+	pub basedir: &'static str,
+	pub defines: &'static [(&'static str, &'static str)],
+}
+
+impl StageSource {
+	/// Stage without an origin, which is not reloaded at runtime
+	pub const fn new(source: &'static str) -> Self {
+		Self {
+			source,
+			origin: None,
+		}
+	}
 }
 
 impl ShaderSource {
-	pub fn create_program(&self, use_ssbo: bool) -> ShaderProgram {
-		let vertex_src = match use_ssbo {
-			true => self.vertex_ssbo,
-			false => self.vertex_compat,
-		};
-
-		(|| -> Result<ShaderProgram, Box<dyn Error>> {
-			Ok(ShaderProgram::link(
-				&Shader::compile(ShaderType::Vertex, vertex_src)?,
-				&Shader::compile(ShaderType::Fragment, self.fragment)?,
-			)?)
-		})()
-		.expect("Predefined shader could not be compiled")
+	pub fn vertex_stage(&self, variant: ShaderVariant) -> &StageSource {
+		match variant {
+			ShaderVariant::Ssbo => &self.vertex_ssbo,
+			ShaderVariant::Compat => &self.vertex_compat,
+		}
+	}
+
+	pub fn vertex(&self, variant: ShaderVariant) -> &'static str {
+		self.vertex_stage(variant).source
+	}
+
+	/// Check if both shaders are made of the same stage sources
+	pub fn same_sources(&self, other: &ShaderSource) -> bool {
+		[
+			(self.vertex_compat.source, other.vertex_compat.source),
+			(self.vertex_ssbo.source, other.vertex_ssbo.source),
+			(self.fragment.source, other.fragment.source),
+		]
+		.iter()
+		// the sources of drawables sharing a shader are usually the same statics
+		.all(|(a, b)| std::ptr::eq(*a, *b) || a == b)
+	}
+
+	/// Compile and link the given variant of this shader
+	pub fn create_program(
+		&self,
+		variant: ShaderVariant,
+	) -> Result<ShaderProgram, ProgramCreateError> {
+		ShaderProgram::create(variant, self.vertex(variant), self.fragment.source)
+	}
+
+	/// Submit the given variant of this shader for compilation
+	/// without waiting for it to finish
+	pub fn submit_program(
+		&self,
+		variant: ShaderVariant,
+	) -> Result<PendingProgram<'static>, ProgramCreateError> {
+		PendingProgram::submit(variant, self.vertex(variant), self.fragment.source)
 	}
 }
 
-macro_rules! define_vec {
-	(align($align:literal) $name:ident[$ty:ident; $count:literal]) => {
-		::seq_macro::seq!(N in 0..$count {
-			::paste::paste! {
-				#[derive(Clone, Debug)]
-				pub struct $name(#($ty,)*);
-
-				#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
-				#[repr(C, packed)]
-				pub struct [<$name Packed>](#($ty,)*);
-
-				#[derive(Copy, Clone, bytemuck::AnyBitPattern)]
-				#[repr(C, align($align))]
-				pub struct [<$name Aligned>](#($ty,)*);
-
-				impl From<[$ty; $count]> for $name {
-					#[inline(always)]
-					fn from(v: [$ty; $count]) -> Self {
-						Self(#(v[N],)*)
-					}
-				}
-
-				impl From<$name> for [<$name Packed>] {
-					#[inline(always)]
-					fn from(r: $name) -> Self {
-						Self(#(r.N,)*)
-					}
-				}
-
-				impl From<$name> for [<$name Aligned>] {
-					#[inline(always)]
-					fn from(r: $name) -> Self {
-						Self(#(r.N,)*)
-					}
-				}
-			}
-		});
+/// Create a `StageOrigin` for a shader stage
+///
+/// Takes the same arguments as `glsl_preprocess::preprocess_glsl!`, with an
+/// optional define block for stages that are not preprocessed.
+#[macro_export]
+macro_rules! glsl_origin {
+	(shader: $ty:ident $path:literal $(,)?) => {
+		$crate::drawable::glsl_origin! { shader: $ty $path, define: {} }
+	};
+	(shader: $ty:ident $path:literal, define: { $($key:ident: $value:ident),* $(,)? } $(,)?) => {
+		$crate::drawable::StageOrigin {
+			path: concat!(env!("CARGO_MANIFEST_DIR"), "/", $path),
+			basedir: env!("CARGO_MANIFEST_DIR"),
+			defines: &[$((stringify!($key), stringify!($value))),*],
+		}
 	};
 }
 
-define_vec!(align(8) Vec2[f32; 2]);
-define_vec!(align(16) Vec3[f32; 3]);
-define_vec!(align(16) Vec4[f32; 4]);
+pub use glsl_origin;
 
+/// Data uploaded for each drawable or vertex, usually implemented with
+/// `#[derive(DrawableData)]` as described in `field`
 pub trait DrawableData {
 	type Ssbo: bytemuck::AnyBitPattern;
 	type Compat: bytemuck::Pod + VertexPassable + std::fmt::Debug;
@@ -118,93 +239,3 @@ pub trait DrawableData {
 	fn into_ssbo(self) -> Self::Ssbo;
 	fn into_compat(self) -> Self::Compat;
 }
-
-#[macro_export]
-macro_rules! drawable_data {
-	($name:ident { $($field:ident: $type:ident),* $(,)? }) => {
-		::paste::paste! {
-			#[derive(Clone)]
-			pub struct $name {
-				$($field: $crate::drawable::drawable_data!(|vr| $type)),*
-			}
-
-			#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, Debug)]
-			#[repr(C, packed)]
-			pub struct [<$name Packed>] {
-				$($field: $crate::drawable::drawable_data!(|vp| $type)),*
-			}
-
-			#[derive(Copy, Clone, bytemuck::AnyBitPattern)]
-			#[repr(C)]
-			pub struct [<$name Aligned>] {
-				$($field: $crate::drawable::drawable_data!(|va| $type)),*
-			}
-
-			impl $crate::drawable::VertexPassable for [<$name Packed>] {
-				const VERTEX_ATTRIBUTES: &'static [$crate::upload::attribute::VertexAttribute] = &[
-					$($crate::drawable::drawable_data!(|atr| $type)),*
-				];
-			}
-
-			impl $crate::drawable::DrawableData for $name {
-				type Ssbo = [<$name Aligned>];
-				type Compat = [<$name Packed>];
-
-				#[inline]
-				fn into_ssbo(self) -> Self::Ssbo {
-					self.into()
-				}
-
-				#[inline]
-				fn into_compat(self) -> Self::Compat {
-					self.into()
-				}
-			}
-
-			impl From<$name> for [<$name Packed>] {
-				#[inline(always)]
-				fn from(r: $name) -> Self {
-					Self {
-						$($field: $crate::drawable::drawable_data!(|i| r.$field: $type)),*
-					}
-				}
-			}
-
-			impl From<$name> for [<$name Aligned>] {
-				#[inline(always)]
-				fn from(r: $name) -> Self {
-					Self {
-						$($field: $crate::drawable::drawable_data!(|i| r.$field: $type)),*
-					}
-				}
-			}
-		}
-	};
-
-	(|vr| Vec2) => { $crate::drawable::Vec2 };
-	(|vr| Vec3) => { $crate::drawable::Vec3 };
-	(|vr| Vec4) => { $crate::drawable::Vec4 };
-	(|vr| $type:ident) => { $type };
-
-	(|vp| Vec2) => { $crate::drawable::Vec2Packed };
-	(|vp| Vec3) => { $crate::drawable::Vec3Packed };
-	(|vp| Vec4) => { $crate::drawable::Vec4Packed };
-	(|vp| $type:ident) => { $type };
-
-	(|va| Vec2) => { $crate::drawable::Vec2Aligned };
-	(|va| Vec3) => { $crate::drawable::Vec3Aligned };
-	(|va| Vec4) => { $crate::drawable::Vec4Aligned };
-	(|va| $type:ident) => { $type };
-
-	(|atr| Vec2) => { $crate::upload::attribute::VertexAttribute::new::<f32>(2) };
-	(|atr| Vec3) => { $crate::upload::attribute::VertexAttribute::new::<f32>(3) };
-	(|atr| Vec4) => { $crate::upload::attribute::VertexAttribute::new::<f32>(4) };
-	(|atr| $type:ident) => { $crate::upload::attribute::VertexAttribute::new::<$type>(1) };
-
-	(|i| $v:ident.$name:ident: Vec2) => { $v.$name.into() };
-	(|i| $v:ident.$name:ident: Vec3) => { $v.$name.into() };
-	(|i| $v:ident.$name:ident: Vec4) => { $v.$name.into() };
-	(|i| $v:ident.$name:ident: $type:ident) => { $v.$name };
-}
-
-pub use drawable_data;
@@ -6,7 +6,16 @@
 
 use gl::types::GLenum;
 
-use super::{drawable_data, Drawable, ShaderSource, Vec2, Vec4};
+use super::{
+	glsl_origin,
+	BoundingBox,
+	Drawable,
+	DrawableData,
+	ShaderSource,
+	StageSource,
+	Vec2,
+	Vec4,
+};
 
 pub struct ColoredTriangle {
 	pub points: [Vec2; 3],
@@ -31,13 +40,20 @@ impl Drawable for ColoredTriangle {
 				use_ssbo: ssbo,
 			},
 		},
-		fragment: include_str!("fragment.glsl"),
+		fragment: StageSource {
+			source: include_str!("fragment.glsl"),
+			origin: Some(glsl_origin! {
+				shader: frag "src/drawable/colored_triangle/fragment.glsl",
+			}),
+		},
 	};
 
 	fn drawable_data(&self) -> Self::Drawable {
-		ColoredTriangleData {
-			color: self.color.clone(),
-		}
+		ColoredTriangleData { color: self.color }
+	}
+
+	fn bounding_box(&self) -> Option<BoundingBox> {
+		BoundingBox::from_points(self.points.iter().map(|p| [p.0, p.1]))
 	}
 
 	#[inline(always)]
@@ -50,10 +66,16 @@ impl Drawable for ColoredTriangle {
 		>,
 		impl IntoIterator<Item = u32, IntoIter = impl ExactSizeIterator<Item = u32> + 's>,
 	) {
-		(self.points.iter().map(|p| ColoredTriangleVertex { pos: p.clone() }), [0, 1, 2])
+		(self.points.iter().map(|p| ColoredTriangleVertex { pos: *p }), [0, 1, 2])
 	}
 }
 
-drawable_data!(ColoredTriangleData { color: Vec4 });
+#[derive(Clone, DrawableData)]
+pub struct ColoredTriangleData {
+	color: Vec4,
+}
 
-drawable_data!(ColoredTriangleVertex { pos: Vec2 });
+#[derive(Clone, DrawableData)]
+pub struct ColoredTriangleVertex {
+	pos: Vec2,
+}
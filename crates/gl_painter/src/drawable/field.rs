@@ -0,0 +1,442 @@
+// Copyright (C) 2022 the ITK authors
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/./
+
+//! Field types of `#[derive(DrawableData)]` structs
+//!
+//! The derive generates a `<Name>Packed` struct, read as vertex attributes
+//! by the compat shader variant, and a `<Name>Aligned` struct laid out with
+//! std430 rules for storage blocks. Std430 offsets are computed at compile
+//! time from each field's `DrawableField` implementation, and filled with
+//! explicit padding fields.
+//!
+//! Supported fields are:
+//! * scalars: `f32`, `f64`, `Half`, `u32`, `i32`, and `u16`, `u8`, `i16`
+//!   and `i8`, which storage blocks hold as 32 bit integers
+//! * vectors: `Vec2`, `Vec3`, `Vec4`, their `IVec` and `UVec` integer
+//!   variants, `Color`, and arrays of 2 to 4 scalars
+//! * `Rgba8` colors, which storage blocks hold as a `uint`
+//! * matrices: arrays of 2 to 4 vectors, each read from its own location
+//! * packed vectors: `Int2_10_10_10Rev` and `UInt2_10_10_10Rev`, which
+//!   storage blocks hold as a `uint`
+//! * other types deriving `DrawableData`, as nested structs
+//! * arrays of any of the above that are not vectors or matrices
+//!
+//! Arrays are laid out with a stride of their element's size rounded up to
+//! its base alignment in storage blocks, and read as the element's vertex
+//! attributes repeated for every element, up to `MAX_ARRAY_ATTRIBUTES`.
+//! Arrays of arrays are not supported, but arrays of structs holding arrays are.
+//!
+//! Fields marked `#[drawable(normalized)]` are read as normalized floats
+//! by vertex attributes, but are not changed in storage blocks. Fields marked
+//! `#[drawable(as = <type>)]` are converted to `<type>` with `as`.
+
+use std::{fmt::Debug, marker::PhantomData};
+
+use super::vec::{
+	Color,
+	ColorPacked,
+	IVec2,
+	IVec2Packed,
+	IVec3,
+	IVec3Packed,
+	IVec4,
+	IVec4Packed,
+	Rgba8,
+	UVec2,
+	UVec2Packed,
+	UVec3,
+	UVec3Packed,
+	UVec4,
+	UVec4Packed,
+	Vec2,
+	Vec2Packed,
+	Vec3,
+	Vec3Packed,
+	Vec4,
+	Vec4Packed,
+};
+use crate::upload::attribute::{
+	AttributeKind,
+	Half,
+	Int2_10_10_10Rev,
+	UInt2_10_10_10Rev,
+	VertexAttribute,
+};
+
+#[cfg(test)]
+mod test;
+
+/// Type usable as a field of a `#[derive(DrawableData)]` struct
+///
+/// In storage blocks the field starts at a multiple of `STD430_ALIGN`
+/// and takes `size_of::<Aligned>()` bytes.
+#[diagnostic::on_unimplemented(
+	message = "`{Self}` cannot be used as a field of drawable data",
+	note = "fields must be scalars, vectors, types deriving `DrawableData`, or arrays of them"
+)]
+pub trait DrawableField {
+	/// Representation in vertex attributes
+	type Packed: bytemuck::Pod + Debug;
+	/// Representation in storage blocks, with an alignment of at most `STD430_ALIGN`
+	type Aligned: bytemuck::Pod;
+
+	/// Vertex attributes read from `Packed`, in location order
+	const ATTRIBUTES: &'static [VertexAttribute];
+	/// std430 base alignment
+	const STD430_ALIGN: usize;
+
+	fn into_packed(self) -> Self::Packed;
+	fn into_aligned(self) -> Self::Aligned;
+}
+
+/// Most vertex attributes an array that is not a vector or matrix may have
+///
+/// Every driver supports at least 16 vertex attributes.
+pub const MAX_ARRAY_ATTRIBUTES: usize = 16;
+
+/// Field type usable as an array element
+///
+/// Arrays of 2 to 4 scalars are vectors, and arrays of 2 to 4 vectors
+/// are matrices with a column per vector. Any other array is a std430 array.
+#[diagnostic::on_unimplemented(
+	message = "`{Self}` cannot be an element of a drawable data array",
+	note = "array elements must be scalars, vectors, packed vectors, `Rgba8`, or types deriving \
+	        `DrawableData`"
+)]
+pub trait ArrayField: DrawableField {
+	/// How arrays of this type are laid out
+	const ELEMENT_KIND: ElementKind;
+
+	/// Representation as an array element in storage blocks,
+	/// padded to a multiple of `STD430_ALIGN`
+	type Element: bytemuck::Pod;
+
+	fn into_element(self) -> Self::Element;
+}
+
+/// Kind of an `ArrayField`, deciding what arrays of it form
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ElementKind {
+	/// Arrays of 2 to 4 elements are vectors
+	Scalar,
+	/// Arrays of 2 to 4 elements are matrices
+	Vector,
+	/// Arrays are always std430 arrays
+	Other,
+}
+
+impl<T: ArrayField, const N: usize> DrawableField for [T; N] {
+	type Aligned = [T::Element; N];
+	type Packed = [T::Packed; N];
+
+	const ATTRIBUTES: &'static [VertexAttribute] = match (T::ELEMENT_KIND, N) {
+		(ElementKind::Scalar | ElementKind::Vector, 2..=4) => &[{
+			let element = T::ATTRIBUTES[0];
+
+			match T::ELEMENT_KIND {
+				ElementKind::Scalar => VertexAttribute {
+					count: element.count * N,
+					column_size: element.column_size * N,
+					..element
+				},
+				_ => VertexAttribute {
+					columns: N,
+					..element
+				},
+			}
+		}],
+		_ => ArrayAttributes::<T, N>::ATTRIBUTES,
+	};
+	const STD430_ALIGN: usize = match (T::ELEMENT_KIND, N) {
+		(_, 0) => panic!("drawable data arrays must not be empty"),
+		(ElementKind::Scalar, 2) => T::STD430_ALIGN * 2,
+		(ElementKind::Scalar, 3..=4) => T::STD430_ALIGN * 4,
+		// arrays and matrices have the base alignment of their elements
+		_ => T::STD430_ALIGN,
+	};
+
+	#[inline(always)]
+	fn into_packed(self) -> Self::Packed {
+		self.map(T::into_packed)
+	}
+
+	#[inline(always)]
+	fn into_aligned(self) -> Self::Aligned {
+		self.map(T::into_element)
+	}
+}
+
+/// Vertex attributes of an array that is not a vector or matrix
+struct ArrayAttributes<T, const N: usize>(PhantomData<T>);
+
+impl<T: ArrayField, const N: usize> ArrayAttributes<T, N> {
+	/// The element's attributes repeated for every element
+	const ALL: &'static [VertexAttribute; MAX_ARRAY_ATTRIBUTES] = &{
+		let mut attributes = [VertexAttribute::new::<u8>(0); MAX_ARRAY_ATTRIBUTES];
+
+		let mut i = 0;
+		while i < Self::LEN {
+			attributes[i] = T::ATTRIBUTES[i % T::ATTRIBUTES.len()];
+			i += 1;
+		}
+
+		attributes
+	};
+	const ATTRIBUTES: &'static [VertexAttribute] = Self::ALL.split_at(Self::LEN).0;
+	const LEN: usize = {
+		let len = T::ATTRIBUTES.len() * N;
+		assert!(
+			len <= MAX_ARRAY_ATTRIBUTES,
+			"drawable data arrays must have at most 16 vertex attributes"
+		);
+		len
+	};
+}
+
+macro_rules! scalar_fields {
+	($($type:ident => $aligned:ident;)*) => {
+		$(
+			impl DrawableField for $type {
+				type Aligned = $aligned;
+				type Packed = $type;
+
+				const ATTRIBUTES: &'static [VertexAttribute] = &[VertexAttribute::new::<$type>(1)];
+				const STD430_ALIGN: usize = std::mem::size_of::<$aligned>();
+
+				#[inline(always)]
+				fn into_packed(self) -> Self::Packed {
+					self
+				}
+
+				#[inline(always)]
+				fn into_aligned(self) -> Self::Aligned {
+					self.into()
+				}
+			}
+
+			impl ArrayField for $type {
+				type Element = $aligned;
+
+				const ELEMENT_KIND: ElementKind = ElementKind::Scalar;
+
+				#[inline(always)]
+				fn into_element(self) -> Self::Element {
+					self.into()
+				}
+			}
+		)*
+	}
+}
+
+scalar_fields! {
+	f64 => f64;
+	f32 => f32;
+	Half => f32;
+
+	u32 => u32;
+	u16 => u32;
+	u8 => u32;
+
+	i32 => i32;
+	i16 => i32;
+	i8 => i32;
+}
+
+macro_rules! packed_fields {
+	($($type:ident;)*) => {
+		$(
+			impl DrawableField for $type {
+				type Aligned = u32;
+				type Packed = $type;
+
+				const ATTRIBUTES: &'static [VertexAttribute] = &[VertexAttribute::new::<$type>(1)];
+				const STD430_ALIGN: usize = 4;
+
+				#[inline(always)]
+				fn into_packed(self) -> Self::Packed {
+					self
+				}
+
+				#[inline(always)]
+				fn into_aligned(self) -> Self::Aligned {
+					self.0
+				}
+			}
+
+			impl ArrayField for $type {
+				type Element = u32;
+
+				const ELEMENT_KIND: ElementKind = ElementKind::Other;
+
+				#[inline(always)]
+				fn into_element(self) -> Self::Element {
+					self.0
+				}
+			}
+		)*
+	}
+}
+
+packed_fields! {
+	Int2_10_10_10Rev;
+	UInt2_10_10_10Rev;
+}
+
+macro_rules! vector_fields {
+	($(
+		$type:ident[$scalar:ident; $count:literal] align($align:literal)
+			=> $element:ty, |$v:ident| $element_expr:expr;
+	)*) => {
+		$(
+			::paste::paste! {
+				impl DrawableField for $type {
+					type Aligned = [<$type Packed>];
+					type Packed = [<$type Packed>];
+
+					const ATTRIBUTES: &'static [VertexAttribute] =
+						&[VertexAttribute::new::<$scalar>($count)];
+					const STD430_ALIGN: usize = $align;
+
+					#[inline(always)]
+					fn into_packed(self) -> Self::Packed {
+						self.into()
+					}
+
+					#[inline(always)]
+					fn into_aligned(self) -> Self::Aligned {
+						self.into()
+					}
+				}
+
+				impl ArrayField for $type {
+					type Element = $element;
+
+					const ELEMENT_KIND: ElementKind = ElementKind::Vector;
+
+					#[inline(always)]
+					fn into_element(self) -> Self::Element {
+						let $v = self;
+						$element_expr
+					}
+				}
+			}
+		)*
+	}
+}
+
+vector_fields! {
+	Vec2[f32; 2] align(8) => Vec2Packed, |v| v.into();
+	// vec3 columns are padded to the alignment of a vec4
+	Vec3[f32; 3] align(16) => Vec4Packed, |v| Vec4Packed(v.0, v.1, v.2, 0.0);
+	Vec4[f32; 4] align(16) => Vec4Packed, |v| v.into();
+
+	IVec2[i32; 2] align(8) => IVec2Packed, |v| v.into();
+	IVec3[i32; 3] align(16) => IVec4Packed, |v| IVec4Packed(v.0, v.1, v.2, 0);
+	IVec4[i32; 4] align(16) => IVec4Packed, |v| v.into();
+
+	UVec2[u32; 2] align(8) => UVec2Packed, |v| v.into();
+	UVec3[u32; 3] align(16) => UVec4Packed, |v| UVec4Packed(v.0, v.1, v.2, 0);
+	UVec4[u32; 4] align(16) => UVec4Packed, |v| v.into();
+
+	Color[f32; 4] align(16) => ColorPacked, |v| v.into();
+}
+
+impl DrawableField for Rgba8 {
+	type Aligned = u32;
+	type Packed = Rgba8;
+
+	const ATTRIBUTES: &'static [VertexAttribute] = &[VertexAttribute::new::<u8>(4).normalized()];
+	const STD430_ALIGN: usize = 4;
+
+	#[inline(always)]
+	fn into_packed(self) -> Self::Packed {
+		self
+	}
+
+	#[inline(always)]
+	fn into_aligned(self) -> Self::Aligned {
+		self.to_bits()
+	}
+}
+
+impl ArrayField for Rgba8 {
+	type Element = u32;
+
+	const ELEMENT_KIND: ElementKind = ElementKind::Other;
+
+	#[inline(always)]
+	fn into_element(self) -> Self::Element {
+		self.to_bits()
+	}
+}
+
+/// Padding before each of a std430 struct's members to reach its offset,
+/// followed by the padding after the last member
+///
+/// Members are given as their size and base alignment.
+#[doc(hidden)]
+pub const fn std430_padding<const N: usize>(members: &[(usize, usize)]) -> [usize; N] {
+	assert!(members.len() + 1 == N);
+
+	let mut padding = [0; N];
+	let mut offset: usize = 0;
+
+	let mut i = 0;
+	while i < members.len() {
+		let (size, align) = members[i];
+		padding[i] = offset.next_multiple_of(align) - offset;
+		offset += padding[i] + size;
+		i += 1;
+	}
+
+	padding[N - 1] = offset.next_multiple_of(std430_align(members)) - offset;
+	padding
+}
+
+/// Base alignment of a std430 struct, the largest of its members
+#[doc(hidden)]
+pub const fn std430_align(members: &[(usize, usize)]) -> usize {
+	let mut align = 1;
+
+	let mut i = 0;
+	while i < members.len() {
+		if members[i].1 > align {
+			align = members[i].1;
+		}
+		i += 1;
+	}
+
+	align
+}
+
+/// Attributes of every field in order, with the kind of fields
+/// with `Some` kind replaced
+#[doc(hidden)]
+pub const fn concat_attributes<const N: usize>(
+	fields: &[(&[VertexAttribute], Option<AttributeKind>)],
+) -> [VertexAttribute; N] {
+	let mut attributes = [VertexAttribute::new::<u8>(0); N];
+	let mut len = 0;
+
+	let mut i = 0;
+	while i < fields.len() {
+		let (field_attributes, kind) = fields[i];
+
+		let mut j = 0;
+		while j < field_attributes.len() {
+			attributes[len] = match kind {
+				Some(kind) => field_attributes[j].with_kind(kind),
+				None => field_attributes[j],
+			};
+			len += 1;
+			j += 1;
+		}
+		i += 1;
+	}
+
+	assert!(len == N);
+	attributes
+}
@@ -0,0 +1,167 @@
+use std::mem::{offset_of, size_of};
+
+use super::{std430_padding, DrawableField};
+use crate::{
+	drawable::{DrawableData, Vec2, Vec3, VertexPassable},
+	upload::attribute::{AttributeKind, Int2_10_10_10Rev},
+};
+
+#[derive(Clone, DrawableData)]
+struct Inner {
+	offset: Vec2,
+	/// Layer the shape is drawn on
+	#[drawable(as = u16)]
+	layer: usize,
+}
+
+#[derive(Clone, DrawableData)]
+struct Outer {
+	color: Vec3,
+	stencil: u32,
+	#[drawable(normalized)]
+	tint: [u8; 4],
+	transform: [Vec3; 3],
+	normal: Int2_10_10_10Rev,
+	inner: Inner,
+	scale: f64,
+}
+
+#[derive(Clone, DrawableData)]
+struct Arrays {
+	weights: [f32; 5],
+	points: [Vec3; 6],
+	inners: [Inner; 2],
+	scale: f32,
+}
+
+#[test]
+fn test_std430_padding() {
+	assert_eq!(std430_padding::<3>(&[(12, 16), (4, 4)]), [0, 0, 0]);
+	assert_eq!(std430_padding::<3>(&[(4, 4), (12, 16)]), [0, 12, 4]);
+	assert_eq!(std430_padding::<2>(&[(2, 2)]), [0, 0]);
+}
+
+#[test]
+fn test_aligned_layout() {
+	// scalars follow a vec3 without padding
+	assert_eq!(offset_of!(OuterAligned, color), 0);
+	assert_eq!(offset_of!(OuterAligned, stencil), 12);
+	// `[u8; 4]` is a uvec4
+	assert_eq!(offset_of!(OuterAligned, tint), 16);
+	// mat3 columns are padded to 16 bytes
+	assert_eq!(offset_of!(OuterAligned, transform), 32);
+	assert_eq!(offset_of!(OuterAligned, normal), 80);
+	// nested structs are aligned to their largest member
+	assert_eq!(offset_of!(OuterAligned, inner), 88);
+	assert_eq!(size_of::<InnerAligned>(), 16);
+	assert_eq!(offset_of!(OuterAligned, scale), 104);
+	// the struct is padded to its alignment
+	assert_eq!(<Outer as DrawableField>::STD430_ALIGN, 16);
+	assert_eq!(size_of::<OuterAligned>(), 112);
+}
+
+#[test]
+fn test_packed_layout() {
+	assert_eq!(size_of::<OuterPacked>(), 12 + 4 + 4 + 36 + 4 + (8 + 2) + 8);
+
+	let attributes = OuterPacked::VERTEX_ATTRIBUTES
+		.iter()
+		.map(|a| (a.ty, a.count, a.columns, a.kind))
+		.collect::<Vec<_>>();
+
+	assert_eq!(attributes, [
+		(gl::FLOAT, 3, 1, AttributeKind::Float),
+		(gl::UNSIGNED_INT, 1, 1, AttributeKind::Integer),
+		(gl::UNSIGNED_BYTE, 4, 1, AttributeKind::Normalized),
+		(gl::FLOAT, 3, 3, AttributeKind::Float),
+		(gl::INT_2_10_10_10_REV, 4, 1, AttributeKind::Normalized),
+		(gl::FLOAT, 2, 1, AttributeKind::Float),
+		(gl::UNSIGNED_SHORT, 1, 1, AttributeKind::Integer),
+		(gl::DOUBLE, 1, 1, AttributeKind::Double),
+	]);
+}
+
+#[test]
+fn test_conversion() {
+	let outer = Outer {
+		color: [0.1, 0.2, 0.3].into(),
+		stencil: 7,
+		tint: [1, 2, 3, 4],
+		transform: [
+			[1.0, 0.0, 0.0].into(),
+			[0.0, 1.0, 0.0].into(),
+			[0.0, 0.0, 1.0].into(),
+		],
+		normal: Int2_10_10_10Rev(5),
+		inner: Inner {
+			offset: [0.5, 0.25].into(),
+			layer: 3,
+		},
+		scale: 2.0,
+	};
+
+	let aligned = outer.clone().into_ssbo();
+	assert_eq!(aligned.tint, [1, 2, 3, 4]);
+	assert_eq!(bytemuck::cast::<_, [f32; 12]>(aligned.transform)[4..8], [0.0, 1.0, 0.0, 0.0]);
+	assert_eq!(aligned.inner.layer, 3);
+	assert_eq!(aligned.scale, 2.0);
+
+	let packed = outer.into_compat();
+	assert_eq!({ packed.tint }, [1, 2, 3, 4]);
+	assert_eq!({ { packed.inner }.layer }, 3);
+	assert_eq!({ packed.scale }, 2.0);
+	assert_eq!(bytemuck::bytes_of(&packed)[..12], *bytemuck::bytes_of(&[0.1f32, 0.2, 0.3]));
+	assert_eq!({ packed.stencil }, 7);
+}
+
+#[test]
+fn test_arrays() {
+	// float arrays have a stride of 4 bytes
+	assert_eq!(offset_of!(ArraysAligned, weights), 0);
+	// vec3 arrays have a stride of 16 bytes
+	assert_eq!(offset_of!(ArraysAligned, points), 32);
+	assert_eq!(offset_of!(ArraysAligned, inners), 128);
+	assert_eq!(offset_of!(ArraysAligned, scale), 160);
+	assert_eq!(size_of::<ArraysAligned>(), 176);
+
+	assert_eq!(size_of::<ArraysPacked>(), 20 + 72 + 20 + 4);
+
+	// each element is read as its own attributes
+	let attributes = ArraysPacked::VERTEX_ATTRIBUTES
+		.iter()
+		.map(|a| (a.ty, a.count, a.columns))
+		.collect::<Vec<_>>();
+
+	let inner = [(gl::FLOAT, 2, 1), (gl::UNSIGNED_SHORT, 1, 1)];
+	assert_eq!(
+		attributes,
+		[
+			[(gl::FLOAT, 1, 1); 5].as_slice(),
+			&[(gl::FLOAT, 3, 1); 6],
+			&inner,
+			&inner,
+			&[(gl::FLOAT, 1, 1)],
+		]
+		.concat()
+	);
+
+	let arrays = Arrays {
+		weights: [1.0, 2.0, 3.0, 4.0, 5.0],
+		points: [[0.0, 1.0, 2.0].into(); 6],
+		inners: [0, 1].map(|layer| Inner {
+			offset: [0.5, 0.25].into(),
+			layer,
+		}),
+		scale: 2.0,
+	};
+
+	let aligned = arrays.clone().into_ssbo();
+	assert_eq!(aligned.weights, [1.0, 2.0, 3.0, 4.0, 5.0]);
+	assert_eq!(bytemuck::cast::<_, [f32; 24]>(aligned.points)[4..8], [0.0, 1.0, 2.0, 0.0]);
+	assert_eq!(aligned.inners[1].layer, 1);
+
+	let packed = arrays.into_compat();
+	let inners = packed.inners;
+	assert_eq!({ inners[1].layer }, 1);
+	assert_eq!({ packed.scale }, 2.0);
+}
@@ -0,0 +1,525 @@
+// Copyright (C) 2022 the ITK authors
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/./
+
+//! Vector and color types of drawable data
+//!
+//! Each type has a `Packed` form without padding, used for vertex
+//! attributes, and an `Aligned` form with the alignment of the matching
+//! GLSL vector. Arithmetic operators work per component, with either
+//! another vector or a scalar.
+//!
+//! With the `mint` and `glam` features, vectors convert to and from the
+//! equivalent types of those crates. Vectors and colors implement bytemuck's
+//! `Pod` without a feature, as bytemuck is already required for the `Packed`
+//! and `Aligned` forms used by `DrawableField`.
+
+use std::ops::{
+	Add,
+	AddAssign,
+	Div,
+	DivAssign,
+	Index,
+	IndexMut,
+	Mul,
+	MulAssign,
+	Neg,
+	Sub,
+	SubAssign,
+};
+
+#[cfg(test)]
+mod test;
+
+macro_rules! define_vec {
+	(
+		$(#[$attr:meta])*
+		align($align:literal) $name:ident[$ty:ident; $count:literal] { $($field:ident: $idx:tt),* }
+	) => {
+		::paste::paste! {
+			$(#[$attr])*
+			#[derive(Copy, Clone, Debug, Default, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+			#[repr(C)]
+			pub struct $name($(pub define_vec!(|ty| $ty $field)),*);
+
+			#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+			#[repr(C, packed)]
+			pub struct [<$name Packed>]($(pub define_vec!(|ty| $ty $field)),*);
+
+			#[derive(Copy, Clone, bytemuck::AnyBitPattern)]
+			#[repr(C, align($align))]
+			pub struct [<$name Aligned>]($(pub define_vec!(|ty| $ty $field)),*);
+		}
+
+		impl $name {
+			pub const ZERO: Self = Self::splat(0 as $ty);
+			pub const ONE: Self = Self::splat(1 as $ty);
+
+			#[inline(always)]
+			pub const fn new($($field: $ty),*) -> Self {
+				Self($($field),*)
+			}
+
+			/// Vector with every component set to `v`
+			#[inline(always)]
+			pub const fn splat(v: $ty) -> Self {
+				Self($(define_vec!(|splat| v $field)),*)
+			}
+
+			$(
+				#[inline(always)]
+				pub const fn $field(&self) -> $ty {
+					self.$idx
+				}
+			)*
+
+			#[inline(always)]
+			pub const fn to_array(self) -> [$ty; $count] {
+				[$(self.$idx),*]
+			}
+
+			/// Apply `f` to every component
+			#[inline(always)]
+			pub fn map(self, mut f: impl FnMut($ty) -> $ty) -> Self {
+				Self($(f(self.$idx)),*)
+			}
+
+			/// Smallest of each component
+			#[inline(always)]
+			pub fn min(self, rhs: Self) -> Self {
+				Self($(self.$idx.min(rhs.$idx)),*)
+			}
+
+			/// Largest of each component
+			#[inline(always)]
+			pub fn max(self, rhs: Self) -> Self {
+				Self($(self.$idx.max(rhs.$idx)),*)
+			}
+		}
+
+		impl From<[$ty; $count]> for $name {
+			#[inline(always)]
+			fn from(v: [$ty; $count]) -> Self {
+				Self($(v[$idx]),*)
+			}
+		}
+
+		impl From<$name> for [$ty; $count] {
+			#[inline(always)]
+			fn from(v: $name) -> Self {
+				v.to_array()
+			}
+		}
+
+		::paste::paste! {
+			impl From<$name> for [<$name Packed>] {
+				#[inline(always)]
+				fn from(r: $name) -> Self {
+					Self($(r.$idx),*)
+				}
+			}
+
+			impl From<$name> for [<$name Aligned>] {
+				#[inline(always)]
+				fn from(r: $name) -> Self {
+					Self($(r.$idx),*)
+				}
+			}
+		}
+
+		impl Index<usize> for $name {
+			type Output = $ty;
+
+			#[inline(always)]
+			fn index(&self, index: usize) -> &$ty {
+				match index {
+					$($idx => &self.$idx,)*
+					_ => panic!(concat!("index out of bounds for ", stringify!($name))),
+				}
+			}
+		}
+
+		impl IndexMut<usize> for $name {
+			#[inline(always)]
+			fn index_mut(&mut self, index: usize) -> &mut $ty {
+				match index {
+					$($idx => &mut self.$idx,)*
+					_ => panic!(concat!("index out of bounds for ", stringify!($name))),
+				}
+			}
+		}
+
+		define_vec!(|op| $name $ty { $($idx),* } Add add AddAssign add_assign +);
+		define_vec!(|op| $name $ty { $($idx),* } Sub sub SubAssign sub_assign -);
+		define_vec!(|op| $name $ty { $($idx),* } Mul mul MulAssign mul_assign *);
+		define_vec!(|op| $name $ty { $($idx),* } Div div DivAssign div_assign /);
+	};
+
+	(|ty| $ty:ident $field:ident) => { $ty };
+	(|splat| $v:ident $field:ident) => { $v };
+
+	(|op| $name:ident $ty:ident { $($idx:tt),* }
+		$trait:ident $fn:ident $assign_trait:ident $assign_fn:ident $op:tt) => {
+		impl $trait for $name {
+			type Output = Self;
+
+			#[inline(always)]
+			fn $fn(self, rhs: Self) -> Self {
+				Self($(self.$idx $op rhs.$idx),*)
+			}
+		}
+
+		impl $trait<$ty> for $name {
+			type Output = Self;
+
+			#[inline(always)]
+			fn $fn(self, rhs: $ty) -> Self {
+				Self($(self.$idx $op rhs),*)
+			}
+		}
+
+		impl $trait<$name> for $ty {
+			type Output = $name;
+
+			#[inline(always)]
+			fn $fn(self, rhs: $name) -> $name {
+				$name($(self $op rhs.$idx),*)
+			}
+		}
+
+		impl $assign_trait for $name {
+			#[inline(always)]
+			fn $assign_fn(&mut self, rhs: Self) {
+				*self = *self $op rhs;
+			}
+		}
+
+		impl $assign_trait<$ty> for $name {
+			#[inline(always)]
+			fn $assign_fn(&mut self, rhs: $ty) {
+				*self = *self $op rhs;
+			}
+		}
+	};
+}
+
+/// Geometric operations of vectors
+macro_rules! vector_ops {
+	($name:ident[$ty:ident] { $($idx:tt),* }) => {
+		impl $name {
+			#[inline(always)]
+			pub fn dot(self, rhs: Self) -> $ty {
+				[$(self.$idx * rhs.$idx),*].into_iter().sum()
+			}
+
+			#[inline(always)]
+			pub fn length_squared(self) -> $ty {
+				self.dot(self)
+			}
+		}
+	};
+}
+
+/// Operations of vectors with float components
+macro_rules! float_ops {
+	($name:ident { $($idx:tt),* }) => {
+		impl $name {
+			#[inline(always)]
+			pub fn length(self) -> f32 {
+				self.length_squared().sqrt()
+			}
+
+			/// Vector with the same direction and a length of 1
+			///
+			/// Zero vectors have no direction and become NaN.
+			#[inline(always)]
+			pub fn normalize(self) -> Self {
+				self / self.length()
+			}
+
+			#[inline(always)]
+			pub fn distance(self, rhs: Self) -> f32 {
+				(rhs - self).length()
+			}
+
+			/// Linear interpolation from `self` at `t = 0` to `rhs` at `t = 1`
+			#[inline(always)]
+			pub fn lerp(self, rhs: Self, t: f32) -> Self {
+				self + (rhs - self) * t
+			}
+		}
+
+		impl Neg for $name {
+			type Output = Self;
+
+			#[inline(always)]
+			fn neg(self) -> Self {
+				Self($(-self.$idx),*)
+			}
+		}
+	};
+}
+
+/// Methods returning vectors of some of the components, in any order
+macro_rules! swizzles {
+	($name:ident { $($swizzle:ident: $target:ident($($idx:tt),*)),* $(,)? }) => {
+		impl $name {
+			$(
+				#[inline(always)]
+				pub const fn $swizzle(&self) -> $target {
+					$target($(self.$idx),*)
+				}
+			)*
+		}
+	};
+}
+
+define_vec!(align(8) Vec2[f32; 2] { x: 0, y: 1 });
+define_vec!(align(16) Vec3[f32; 3] { x: 0, y: 1, z: 2 });
+define_vec!(align(16) Vec4[f32; 4] { x: 0, y: 1, z: 2, w: 3 });
+
+define_vec!(align(8) IVec2[i32; 2] { x: 0, y: 1 });
+define_vec!(align(16) IVec3[i32; 3] { x: 0, y: 1, z: 2 });
+define_vec!(align(16) IVec4[i32; 4] { x: 0, y: 1, z: 2, w: 3 });
+
+define_vec!(align(8) UVec2[u32; 2] { x: 0, y: 1 });
+define_vec!(align(16) UVec3[u32; 3] { x: 0, y: 1, z: 2 });
+define_vec!(align(16) UVec4[u32; 4] { x: 0, y: 1, z: 2, w: 3 });
+
+define_vec!(
+	/// RGBA color, read as a `vec4`
+	align(16) Color[f32; 4] { r: 0, g: 1, b: 2, a: 3 }
+);
+
+/// RGBA color of 8 bit channels
+///
+/// Vertex attributes read it as a normalized `vec4`, and storage blocks
+/// hold it as a `uint` to be unpacked with `unpackUnorm4x8`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+pub struct Rgba8(pub u8, pub u8, pub u8, pub u8);
+
+impl Rgba8 {
+	#[inline(always)]
+	pub const fn new(r: u8, g: u8, b: u8, a: u8) -> Self {
+		Self(r, g, b, a)
+	}
+
+	/// Channels packed as read by `unpackUnorm4x8`, with red in the lowest bits
+	#[inline(always)]
+	pub const fn to_bits(self) -> u32 {
+		u32::from_le_bytes([self.0, self.1, self.2, self.3])
+	}
+}
+
+vector_ops!(Vec2[f32] { 0, 1 });
+vector_ops!(Vec3[f32] { 0, 1, 2 });
+vector_ops!(Vec4[f32] { 0, 1, 2, 3 });
+vector_ops!(IVec2[i32] { 0, 1 });
+vector_ops!(IVec3[i32] { 0, 1, 2 });
+vector_ops!(IVec4[i32] { 0, 1, 2, 3 });
+vector_ops!(UVec2[u32] { 0, 1 });
+vector_ops!(UVec3[u32] { 0, 1, 2 });
+vector_ops!(UVec4[u32] { 0, 1, 2, 3 });
+
+float_ops!(Vec2 { 0, 1 });
+float_ops!(Vec3 { 0, 1, 2 });
+float_ops!(Vec4 { 0, 1, 2, 3 });
+
+swizzles!(Vec2 { yx: Vec2(1, 0) });
+swizzles!(Vec3 {
+	xy: Vec2(0, 1),
+	xz: Vec2(0, 2),
+	yz: Vec2(1, 2),
+	zyx: Vec3(2, 1, 0)
+});
+swizzles!(Vec4 {
+	xy: Vec2(0, 1),
+	zw: Vec2(2, 3),
+	xyz: Vec3(0, 1, 2),
+	wzyx: Vec4(3, 2, 1, 0)
+});
+swizzles!(IVec2 { yx: IVec2(1, 0) });
+swizzles!(IVec3 {
+	xy: IVec2(0, 1),
+	xz: IVec2(0, 2),
+	yz: IVec2(1, 2)
+});
+swizzles!(IVec4 {
+	xy: IVec2(0, 1),
+	zw: IVec2(2, 3),
+	xyz: IVec3(0, 1, 2)
+});
+swizzles!(UVec2 { yx: UVec2(1, 0) });
+swizzles!(UVec3 {
+	xy: UVec2(0, 1),
+	xz: UVec2(0, 2),
+	yz: UVec2(1, 2)
+});
+swizzles!(UVec4 {
+	xy: UVec2(0, 1),
+	zw: UVec2(2, 3),
+	xyz: UVec3(0, 1, 2)
+});
+
+impl Neg for IVec2 {
+	type Output = Self;
+
+	#[inline(always)]
+	fn neg(self) -> Self {
+		Self(-self.0, -self.1)
+	}
+}
+
+impl Neg for IVec3 {
+	type Output = Self;
+
+	#[inline(always)]
+	fn neg(self) -> Self {
+		Self(-self.0, -self.1, -self.2)
+	}
+}
+
+impl Neg for IVec4 {
+	type Output = Self;
+
+	#[inline(always)]
+	fn neg(self) -> Self {
+		Self(-self.0, -self.1, -self.2, -self.3)
+	}
+}
+
+impl Vec2 {
+	#[inline(always)]
+	pub const fn extend(self, z: f32) -> Vec3 {
+		Vec3(self.0, self.1, z)
+	}
+}
+
+impl Vec3 {
+	#[inline(always)]
+	pub const fn extend(self, w: f32) -> Vec4 {
+		Vec4(self.0, self.1, self.2, w)
+	}
+
+	#[inline(always)]
+	pub fn cross(self, rhs: Self) -> Self {
+		Self(
+			self.1 * rhs.2 - self.2 * rhs.1,
+			self.2 * rhs.0 - self.0 * rhs.2,
+			self.0 * rhs.1 - self.1 * rhs.0,
+		)
+	}
+}
+
+impl Color {
+	pub const BLACK: Self = Self::rgb(0.0, 0.0, 0.0);
+	pub const TRANSPARENT: Self = Self::ZERO;
+	pub const WHITE: Self = Self::rgb(1.0, 1.0, 1.0);
+
+	/// Opaque color
+	#[inline(always)]
+	pub const fn rgb(r: f32, g: f32, b: f32) -> Self {
+		Self(r, g, b, 1.0)
+	}
+
+	/// Color channels without alpha
+	#[inline(always)]
+	pub const fn to_rgb(self) -> Vec3 {
+		Vec3(self.0, self.1, self.2)
+	}
+
+	#[inline(always)]
+	pub const fn with_alpha(self, a: f32) -> Self {
+		Self(self.0, self.1, self.2, a)
+	}
+
+	/// Linear interpolation from `self` at `t = 0` to `rhs` at `t = 1`
+	#[inline(always)]
+	pub fn lerp(self, rhs: Self, t: f32) -> Self {
+		self + (rhs - self) * t
+	}
+}
+
+impl From<Rgba8> for Color {
+	#[inline(always)]
+	fn from(c: Rgba8) -> Self {
+		Self(c.0 as f32, c.1 as f32, c.2 as f32, c.3 as f32) / 255.0
+	}
+}
+
+impl From<Color> for Rgba8 {
+	/// Channels are clamped to `[0, 1]` and rounded
+	#[inline(always)]
+	fn from(c: Color) -> Self {
+		let [r, g, b, a] = c.map(|v| (v.clamp(0.0, 1.0) * 255.0).round()).to_array();
+		Self(r as u8, g as u8, b as u8, a as u8)
+	}
+}
+
+impl From<Vec4> for Color {
+	#[inline(always)]
+	fn from(v: Vec4) -> Self {
+		Self(v.0, v.1, v.2, v.3)
+	}
+}
+
+impl From<Color> for Vec4 {
+	#[inline(always)]
+	fn from(c: Color) -> Self {
+		Self(c.0, c.1, c.2, c.3)
+	}
+}
+
+/// Conversions to and from the equivalent vector types of other crates
+macro_rules! interop {
+	($feature:literal $($name:ident[$ty:ident; $count:literal] <=> $other:ty;)*) => {
+		$(
+			#[cfg(feature = $feature)]
+			impl From<$other> for $name {
+				#[inline(always)]
+				fn from(v: $other) -> Self {
+					<[$ty; $count]>::from(v).into()
+				}
+			}
+
+			#[cfg(feature = $feature)]
+			impl From<$name> for $other {
+				#[inline(always)]
+				fn from(v: $name) -> Self {
+					v.to_array().into()
+				}
+			}
+		)*
+	};
+}
+
+interop! {
+	"glam"
+	Vec2[f32; 2] <=> glam::Vec2;
+	Vec3[f32; 3] <=> glam::Vec3;
+	Vec4[f32; 4] <=> glam::Vec4;
+	IVec2[i32; 2] <=> glam::IVec2;
+	IVec3[i32; 3] <=> glam::IVec3;
+	IVec4[i32; 4] <=> glam::IVec4;
+	UVec2[u32; 2] <=> glam::UVec2;
+	UVec3[u32; 3] <=> glam::UVec3;
+	UVec4[u32; 4] <=> glam::UVec4;
+}
+
+interop! {
+	"mint"
+	Vec2[f32; 2] <=> mint::Vector2<f32>;
+	Vec3[f32; 3] <=> mint::Vector3<f32>;
+	Vec4[f32; 4] <=> mint::Vector4<f32>;
+	IVec2[i32; 2] <=> mint::Vector2<i32>;
+	IVec3[i32; 3] <=> mint::Vector3<i32>;
+	IVec4[i32; 4] <=> mint::Vector4<i32>;
+	UVec2[u32; 2] <=> mint::Vector2<u32>;
+	UVec3[u32; 3] <=> mint::Vector3<u32>;
+	UVec4[u32; 4] <=> mint::Vector4<u32>;
+	Vec2[f32; 2] <=> mint::Point2<f32>;
+	Vec3[f32; 3] <=> mint::Point3<f32>;
+}
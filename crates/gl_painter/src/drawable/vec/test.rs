@@ -0,0 +1,57 @@
+use super::{Color, IVec2, Rgba8, UVec3, Vec2, Vec3, Vec4};
+use crate::drawable::DrawableField;
+
+#[test]
+fn test_operators() {
+	let mut v = Vec2::new(1.0, 2.0) + Vec2::splat(1.0);
+	assert_eq!(v, Vec2(2.0, 3.0));
+	assert_eq!(v * 2.0, Vec2(4.0, 6.0));
+	assert_eq!(2.0 * v, v * 2.0);
+	assert_eq!(v - Vec2::ONE, Vec2(1.0, 2.0));
+	assert_eq!(-v, Vec2(-2.0, -3.0));
+
+	v /= Vec2(2.0, 3.0);
+	assert_eq!(v, Vec2::ONE);
+	v[1] = 5.0;
+	assert_eq!(v.y(), 5.0);
+
+	assert_eq!(IVec2::new(3, -4) * IVec2::new(2, 2), IVec2(6, -8));
+	assert_eq!(UVec3::splat(7) / 2, UVec3(3, 3, 3));
+}
+
+#[test]
+fn test_geometry() {
+	let v = Vec2::new(3.0, 4.0);
+	assert_eq!(v.dot(Vec2::ONE), 7.0);
+	assert_eq!(v.length(), 5.0);
+	assert_eq!(v.normalize(), Vec2(0.6, 0.8));
+	assert_eq!(Vec2::ZERO.distance(v), 5.0);
+	assert_eq!(Vec2::ZERO.lerp(v, 0.5), Vec2(1.5, 2.0));
+	assert_eq!(IVec2::new(2, 3).length_squared(), 13);
+
+	let x = Vec3::new(1.0, 0.0, 0.0);
+	let y = Vec3::new(0.0, 1.0, 0.0);
+	assert_eq!(x.cross(y), Vec3(0.0, 0.0, 1.0));
+}
+
+#[test]
+fn test_swizzles() {
+	const V: Vec4 = Vec4::new(1.0, 2.0, 3.0, 4.0);
+	const XYZ: Vec3 = V.xyz();
+
+	assert_eq!(XYZ, Vec3(1.0, 2.0, 3.0));
+	assert_eq!(V.zw(), Vec2(3.0, 4.0));
+	assert_eq!(V.wzyx(), Vec4(4.0, 3.0, 2.0, 1.0));
+	assert_eq!(XYZ.xy().yx().extend(0.0).extend(1.0), Vec4(2.0, 1.0, 0.0, 1.0));
+}
+
+#[test]
+fn test_color() {
+	let color = Color::rgb(1.0, 0.5, 0.0).with_alpha(0.25);
+	assert_eq!(Rgba8::from(color), Rgba8(255, 128, 0, 64));
+	assert_eq!(Color::from(Rgba8::new(255, 0, 51, 255)), Color::rgb(1.0, 0.0, 0.2));
+	assert_eq!(Rgba8::from(Color::WHITE * 2.0), Rgba8(255, 255, 255, 255));
+	assert_eq!(Color::BLACK.lerp(Color::WHITE, 0.5).to_rgb(), Vec3::splat(0.5));
+
+	assert_eq!(Rgba8(1, 2, 3, 4).into_aligned(), 0x04030201);
+}
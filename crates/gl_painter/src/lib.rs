@@ -10,30 +10,16 @@
 // if it hasn't come to stable by the time ITK is usable.
 #![feature(return_position_impl_trait_in_trait)]
 
-use std::ffi::CStr;
+// lets code generated by `gl_painter_derive` refer to `::gl_painter` within this crate
+extern crate self as gl_painter;
 
-pub mod shader;
-pub mod upload;
-
-#[derive(Debug)]
-pub struct GlExtensions {
-	arb_buffer_storage: bool,
-}
+pub use bytemuck;
 
-// TODO: cache this in some way where used
-pub fn check_gl_extensions() -> GlExtensions {
-	let mut extension_count = 0;
-	unsafe { gl::GetIntegerv(gl::NUM_EXTENSIONS, &mut extension_count) };
+pub use self::capabilities::GlCapabilities;
 
-	let extensions = (0..extension_count)
-		.map(|i| {
-			unsafe { CStr::from_ptr(gl::GetStringi(gl::EXTENSIONS, i as u32) as *const i8) }
-				.to_str()
-				.expect("OpenGL driver returned a non UTF8 string when requesting extensions")
-		})
-		.collect::<Vec<_>>();
-
-	GlExtensions {
-		arb_buffer_storage: extensions.contains(&"GL_ARB_buffer_storage"),
-	}
-}
+pub mod capabilities;
+pub mod drawable;
+#[cfg(test)]
+mod mock_gl;
+pub mod shader;
+pub mod upload;
@@ -0,0 +1,580 @@
+// Copyright (C) 2022 the ITK authors
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/./
+
+//! Recording mock of the GL functions used by uploaders and shader programs,
+//! for unit tests
+//!
+//! `install` loads the mock into the `gl` crate's function pointers, which
+//! are global, but all mock state is kept per thread so tests may run in
+//! parallel. Calls are recorded as strings such as
+//! `BufferSubData(ARRAY_BUFFER, 0, 72)`, with enums by name and pointers
+//! by address. Capability queries are answered but not recorded, and any
+//! GL function not mocked panics when called.
+
+use std::{cell::RefCell, ffi::c_void, ptr, sync::Once};
+
+use gl::types::{
+	GLbitfield,
+	GLboolean,
+	GLchar,
+	GLenum,
+	GLint,
+	GLint64,
+	GLintptr,
+	GLsizei,
+	GLsizeiptr,
+	GLsync,
+	GLubyte,
+	GLuint,
+	GLuint64,
+};
+
+use crate::{
+	drawable::{ColoredTriangle, Drawable},
+	upload::Uploader,
+	GlCapabilities,
+};
+
+thread_local! {
+	static STATE: RefCell<State> = const {
+		RefCell::new(State {
+			version: (0, 0),
+			calls: Vec::new(),
+			next_name: 1,
+			gpu_busy: false,
+			link_fails: false,
+			mapped: Vec::new(),
+		})
+	};
+}
+
+struct State {
+	version: (u32, u32),
+	calls: Vec<String>,
+	/// Next buffer, vertex array, shader, program or sync object name
+	next_name: usize,
+	/// Set if fences are only signalled by waiting on them
+	gpu_busy: bool,
+	/// Set if programs fail to link
+	link_fails: bool,
+	/// Memory returned by `glMapBufferRange`, never unmapped
+	mapped: Vec<Vec<u64>>,
+}
+
+/// Use the mock on this thread, as a core profile context of the given version
+/// without extensions
+///
+/// Resets recorded calls, object names and the thread's capabilities.
+pub fn install(major: u32, minor: u32) {
+	static LOAD: Once = Once::new();
+	LOAD.call_once(|| gl::load_with(load));
+
+	STATE.with(|state| {
+		*state.borrow_mut() = State {
+			version: (major, minor),
+			calls: Vec::new(),
+			next_name: 1,
+			gpu_busy: false,
+			link_fails: false,
+			mapped: Vec::new(),
+		}
+	});
+
+	GlCapabilities::invalidate();
+}
+
+/// Set whether fences are only signalled by waiting on them with a timeout,
+/// as if the GPU was still busy, or are always signalled
+pub fn set_gpu_busy(gpu_busy: bool) {
+	STATE.with(|state| state.borrow_mut().gpu_busy = gpu_busy);
+}
+
+/// Set whether programs fail to link, with an empty log
+pub fn set_link_fails(link_fails: bool) {
+	STATE.with(|state| state.borrow_mut().link_fails = link_fails);
+}
+
+/// Take every call recorded on this thread since the last `take_calls`
+pub fn take_calls() -> Vec<String> {
+	STATE.with(|state| std::mem::take(&mut state.borrow_mut().calls))
+}
+
+/// Triangle with its right angle at `(x, 0)`, for writing to uploaders
+pub fn triangle(x: f32) -> ColoredTriangle {
+	ColoredTriangle {
+		points: [[x, 0.0].into(), [x + 1.0, 0.0].into(), [x, 1.0].into()],
+		color: [1.0, 1.0, 1.0, 1.0].into(),
+	}
+}
+
+/// Write and draw `drawables` as one frame, returning the GL calls made
+pub fn frame<D: Drawable>(uploader: &mut impl Uploader<D>, drawables: &[D]) -> Vec<String> {
+	unsafe {
+		uploader.prepare_write();
+		uploader.clear();
+
+		for drawable in drawables {
+			uploader.write(drawable);
+		}
+
+		uploader.begin_flush();
+		uploader.upload();
+	}
+
+	take_calls()
+}
+
+fn record(call: String) {
+	STATE.with(|state| state.borrow_mut().calls.push(call));
+}
+
+fn next_name() -> usize {
+	STATE.with(|state| {
+		let mut state = state.borrow_mut();
+		state.next_name += 1;
+		state.next_name - 1
+	})
+}
+
+/// Name of the GL enums used by uploaders
+fn name(value: GLenum) -> String {
+	let name = match value {
+		gl::ARRAY_BUFFER => "ARRAY_BUFFER",
+		gl::ELEMENT_ARRAY_BUFFER => "ELEMENT_ARRAY_BUFFER",
+		gl::SHADER_STORAGE_BUFFER => "SHADER_STORAGE_BUFFER",
+		gl::DRAW_INDIRECT_BUFFER => "DRAW_INDIRECT_BUFFER",
+		gl::COPY_READ_BUFFER => "COPY_READ_BUFFER",
+		gl::COPY_WRITE_BUFFER => "COPY_WRITE_BUFFER",
+		gl::DYNAMIC_DRAW => "DYNAMIC_DRAW",
+		gl::TRIANGLES => "TRIANGLES",
+		gl::UNSIGNED_BYTE => "UNSIGNED_BYTE",
+		gl::UNSIGNED_SHORT => "UNSIGNED_SHORT",
+		gl::UNSIGNED_INT => "UNSIGNED_INT",
+		gl::INT => "INT",
+		gl::FLOAT => "FLOAT",
+		gl::DOUBLE => "DOUBLE",
+		gl::HALF_FLOAT => "HALF_FLOAT",
+		gl::INT_2_10_10_10_REV => "INT_2_10_10_10_REV",
+		gl::PRIMITIVE_RESTART => "PRIMITIVE_RESTART",
+		gl::PRIMITIVE_RESTART_FIXED_INDEX => "PRIMITIVE_RESTART_FIXED_INDEX",
+		gl::VERTEX_SHADER => "VERTEX_SHADER",
+		gl::FRAGMENT_SHADER => "FRAGMENT_SHADER",
+		_ => return format!("{value:#x}"),
+	};
+
+	name.to_string()
+}
+
+fn load(symbol: &str) -> *const c_void {
+	match symbol {
+		"glGetString" => get_string as *const c_void,
+		"glGetIntegerv" => get_integerv as *const c_void,
+		"glGetInteger64v" => get_integer64v as *const c_void,
+		"glGenBuffers" => gen_buffers as *const c_void,
+		"glDeleteBuffers" => delete_buffers as *const c_void,
+		"glBindBuffer" => bind_buffer as *const c_void,
+		"glBindBufferBase" => bind_buffer_base as *const c_void,
+		"glBindBufferRange" => bind_buffer_range as *const c_void,
+		"glBufferData" => buffer_data as *const c_void,
+		"glBufferSubData" => buffer_sub_data as *const c_void,
+		"glBufferStorage" => buffer_storage as *const c_void,
+		"glMapBufferRange" => map_buffer_range as *const c_void,
+		"glFlushMappedBufferRange" => flush_mapped_buffer_range as *const c_void,
+		"glGenVertexArrays" => gen_vertex_arrays as *const c_void,
+		"glDeleteVertexArrays" => delete_vertex_arrays as *const c_void,
+		"glBindVertexArray" => bind_vertex_array as *const c_void,
+		"glEnableVertexAttribArray" => enable_vertex_attrib_array as *const c_void,
+		"glVertexAttribPointer" => vertex_attrib_pointer as *const c_void,
+		"glVertexAttribIPointer" => vertex_attrib_i_pointer as *const c_void,
+		"glVertexAttribLPointer" => vertex_attrib_l_pointer as *const c_void,
+		"glVertexAttribDivisor" => vertex_attrib_divisor as *const c_void,
+		"glCreateShader" => create_shader as *const c_void,
+		"glShaderSource" => shader_source as *const c_void,
+		"glCompileShader" => compile_shader as *const c_void,
+		"glGetShaderiv" => get_shaderiv as *const c_void,
+		"glGetShaderInfoLog" => get_info_log as *const c_void,
+		"glDeleteShader" => delete_shader as *const c_void,
+		"glCreateProgram" => create_program as *const c_void,
+		"glAttachShader" => attach_shader as *const c_void,
+		"glDetachShader" => detach_shader as *const c_void,
+		"glLinkProgram" => link_program as *const c_void,
+		"glGetProgramiv" => get_programiv as *const c_void,
+		"glGetProgramInfoLog" => get_info_log as *const c_void,
+		"glUseProgram" => use_program as *const c_void,
+		"glDeleteProgram" => delete_program as *const c_void,
+		"glEnable" => enable as *const c_void,
+		"glDisable" => disable as *const c_void,
+		"glPrimitiveRestartIndex" => primitive_restart_index as *const c_void,
+		"glDrawArrays" => draw_arrays as *const c_void,
+		"glDrawElements" => draw_elements as *const c_void,
+		"glDrawElementsBaseVertex" => draw_elements_base_vertex as *const c_void,
+		"glMultiDrawArrays" => multi_draw_arrays as *const c_void,
+		"glMultiDrawElementsBaseVertex" => multi_draw_elements_base_vertex as *const c_void,
+		"glFenceSync" => fence_sync as *const c_void,
+		"glClientWaitSync" => client_wait_sync as *const c_void,
+		"glDeleteSync" => delete_sync as *const c_void,
+		_ => ptr::null(),
+	}
+}
+
+extern "system" fn get_string(_name: GLenum) -> *const GLubyte {
+	// read as an empty string
+	ptr::null()
+}
+
+extern "system" fn get_integerv(pname: GLenum, data: *mut GLint) {
+	let (major, minor) = STATE.with(|state| state.borrow().version);
+
+	let value = match pname {
+		gl::MAJOR_VERSION => major as GLint,
+		gl::MINOR_VERSION => minor as GLint,
+		gl::CONTEXT_PROFILE_MASK => gl::CONTEXT_CORE_PROFILE_BIT as GLint,
+		gl::MAX_VERTEX_ATTRIBS => 16,
+		gl::MAX_TEXTURE_SIZE => 16384,
+		gl::MAX_VERTEX_SHADER_STORAGE_BLOCKS => 16,
+		gl::SHADER_STORAGE_BUFFER_OFFSET_ALIGNMENT => 256,
+		_ => 0,
+	};
+
+	unsafe { *data = value };
+}
+
+extern "system" fn get_integer64v(pname: GLenum, data: *mut GLint64) {
+	let value = match pname {
+		gl::MAX_SHADER_STORAGE_BLOCK_SIZE => 1 << 27,
+		_ => 0,
+	};
+
+	unsafe { *data = value };
+}
+
+extern "system" fn gen_buffers(n: GLsizei, buffers: *mut GLuint) {
+	let names = (0..n).map(|_| next_name() as GLuint).collect::<Vec<_>>();
+	unsafe { ptr::copy_nonoverlapping(names.as_ptr(), buffers, names.len()) };
+	record(format!("GenBuffers({names:?})"));
+}
+
+extern "system" fn delete_buffers(n: GLsizei, buffers: *const GLuint) {
+	let names = unsafe { std::slice::from_raw_parts(buffers, n as usize) };
+	record(format!("DeleteBuffers({names:?})"));
+}
+
+extern "system" fn bind_buffer(target: GLenum, buffer: GLuint) {
+	record(format!("BindBuffer({}, {buffer})", name(target)));
+}
+
+extern "system" fn bind_buffer_base(target: GLenum, index: GLuint, buffer: GLuint) {
+	record(format!("BindBufferBase({}, {index}, {buffer})", name(target)));
+}
+
+extern "system" fn bind_buffer_range(
+	target: GLenum,
+	index: GLuint,
+	buffer: GLuint,
+	offset: GLintptr,
+	size: GLsizeiptr,
+) {
+	record(format!(
+		"BindBufferRange({}, {index}, {buffer}, {offset}, {size})",
+		name(target)
+	));
+}
+
+extern "system" fn buffer_data(
+	target: GLenum,
+	size: GLsizeiptr,
+	_data: *const c_void,
+	usage: GLenum,
+) {
+	record(format!("BufferData({}, {size}, {})", name(target), name(usage)));
+}
+
+extern "system" fn buffer_sub_data(
+	target: GLenum,
+	offset: GLintptr,
+	size: GLsizeiptr,
+	_data: *const c_void,
+) {
+	record(format!("BufferSubData({}, {offset}, {size})", name(target)));
+}
+
+extern "system" fn buffer_storage(
+	target: GLenum,
+	size: GLsizeiptr,
+	_data: *const c_void,
+	_flags: GLbitfield,
+) {
+	record(format!("BufferStorage({}, {size})", name(target)));
+}
+
+extern "system" fn map_buffer_range(
+	target: GLenum,
+	offset: GLintptr,
+	length: GLsizeiptr,
+	_access: GLbitfield,
+) -> *mut c_void {
+	record(format!("MapBufferRange({}, {offset}, {length})", name(target)));
+
+	let mut memory = vec![0u64; (length as usize).div_ceil(8)];
+	let pointer = memory.as_mut_ptr() as *mut c_void;
+	STATE.with(|state| state.borrow_mut().mapped.push(memory));
+	pointer
+}
+
+extern "system" fn flush_mapped_buffer_range(target: GLenum, offset: GLintptr, length: GLsizeiptr) {
+	record(format!("FlushMappedBufferRange({}, {offset}, {length})", name(target)));
+}
+
+extern "system" fn gen_vertex_arrays(n: GLsizei, arrays: *mut GLuint) {
+	let names = (0..n).map(|_| next_name() as GLuint).collect::<Vec<_>>();
+	unsafe { ptr::copy_nonoverlapping(names.as_ptr(), arrays, names.len()) };
+	record(format!("GenVertexArrays({names:?})"));
+}
+
+extern "system" fn delete_vertex_arrays(n: GLsizei, arrays: *const GLuint) {
+	let names = unsafe { std::slice::from_raw_parts(arrays, n as usize) };
+	record(format!("DeleteVertexArrays({names:?})"));
+}
+
+extern "system" fn bind_vertex_array(array: GLuint) {
+	record(format!("BindVertexArray({array})"));
+}
+
+extern "system" fn enable_vertex_attrib_array(index: GLuint) {
+	record(format!("EnableVertexAttribArray({index})"));
+}
+
+extern "system" fn vertex_attrib_pointer(
+	index: GLuint,
+	size: GLint,
+	ty: GLenum,
+	normalized: GLboolean,
+	stride: GLsizei,
+	pointer: *const c_void,
+) {
+	record(format!(
+		"VertexAttribPointer({index}, {size}, {}, {normalized}, {stride}, {})",
+		name(ty),
+		pointer as usize,
+	));
+}
+
+extern "system" fn vertex_attrib_i_pointer(
+	index: GLuint,
+	size: GLint,
+	ty: GLenum,
+	stride: GLsizei,
+	pointer: *const c_void,
+) {
+	record(format!(
+		"VertexAttribIPointer({index}, {size}, {}, {stride}, {})",
+		name(ty),
+		pointer as usize,
+	));
+}
+
+extern "system" fn vertex_attrib_l_pointer(
+	index: GLuint,
+	size: GLint,
+	ty: GLenum,
+	stride: GLsizei,
+	pointer: *const c_void,
+) {
+	record(format!(
+		"VertexAttribLPointer({index}, {size}, {}, {stride}, {})",
+		name(ty),
+		pointer as usize,
+	));
+}
+
+extern "system" fn vertex_attrib_divisor(index: GLuint, divisor: GLuint) {
+	record(format!("VertexAttribDivisor({index}, {divisor})"));
+}
+
+extern "system" fn create_shader(ty: GLenum) -> GLuint {
+	let shader = next_name();
+	record(format!("CreateShader({}, {shader})", name(ty)));
+	shader as GLuint
+}
+
+extern "system" fn shader_source(
+	shader: GLuint,
+	_count: GLsizei,
+	_string: *const *const GLchar,
+	_length: *const GLint,
+) {
+	record(format!("ShaderSource({shader})"));
+}
+
+extern "system" fn compile_shader(shader: GLuint) {
+	record(format!("CompileShader({shader})"));
+}
+
+extern "system" fn get_shaderiv(_shader: GLuint, pname: GLenum, params: *mut GLint) {
+	let value = match pname {
+		gl::COMPILE_STATUS => gl::TRUE as GLint,
+		// just the null terminator
+		gl::INFO_LOG_LENGTH => 1,
+		_ => 0,
+	};
+
+	unsafe { *params = value };
+}
+
+/// `glGetShaderInfoLog` and `glGetProgramInfoLog`, returning an empty log
+extern "system" fn get_info_log(
+	_object: GLuint,
+	_buf_size: GLsizei,
+	length: *mut GLsizei,
+	info_log: *mut GLchar,
+) {
+	unsafe {
+		*length = 1;
+		*info_log = 0;
+	}
+}
+
+extern "system" fn delete_shader(shader: GLuint) {
+	record(format!("DeleteShader({shader})"));
+}
+
+extern "system" fn create_program() -> GLuint {
+	let program = next_name();
+	record(format!("CreateProgram({program})"));
+	program as GLuint
+}
+
+extern "system" fn attach_shader(program: GLuint, shader: GLuint) {
+	record(format!("AttachShader({program}, {shader})"));
+}
+
+extern "system" fn detach_shader(program: GLuint, shader: GLuint) {
+	record(format!("DetachShader({program}, {shader})"));
+}
+
+extern "system" fn link_program(program: GLuint) {
+	record(format!("LinkProgram({program})"));
+}
+
+extern "system" fn get_programiv(_program: GLuint, pname: GLenum, params: *mut GLint) {
+	let link_fails = STATE.with(|state| state.borrow().link_fails);
+
+	let value = match pname {
+		gl::LINK_STATUS => (!link_fails) as GLint,
+		gl::INFO_LOG_LENGTH => 1,
+		_ => 0,
+	};
+
+	unsafe { *params = value };
+}
+
+extern "system" fn use_program(program: GLuint) {
+	record(format!("UseProgram({program})"));
+}
+
+extern "system" fn delete_program(program: GLuint) {
+	record(format!("DeleteProgram({program})"));
+}
+
+extern "system" fn enable(cap: GLenum) {
+	record(format!("Enable({})", name(cap)));
+}
+
+extern "system" fn disable(cap: GLenum) {
+	record(format!("Disable({})", name(cap)));
+}
+
+extern "system" fn primitive_restart_index(index: GLuint) {
+	record(format!("PrimitiveRestartIndex({index})"));
+}
+
+extern "system" fn draw_arrays(mode: GLenum, first: GLint, count: GLsizei) {
+	record(format!("DrawArrays({}, {first}, {count})", name(mode)));
+}
+
+extern "system" fn draw_elements(mode: GLenum, count: GLsizei, ty: GLenum, indices: *const c_void) {
+	record(format!(
+		"DrawElements({}, {count}, {}, {})",
+		name(mode),
+		name(ty),
+		indices as usize
+	));
+}
+
+extern "system" fn draw_elements_base_vertex(
+	mode: GLenum,
+	count: GLsizei,
+	ty: GLenum,
+	indices: *const c_void,
+	base_vertex: GLint,
+) {
+	record(format!(
+		"DrawElementsBaseVertex({}, {count}, {}, {}, {base_vertex})",
+		name(mode),
+		name(ty),
+		indices as usize
+	));
+}
+
+extern "system" fn multi_draw_arrays(
+	mode: GLenum,
+	first: *const GLint,
+	count: *const GLsizei,
+	drawcount: GLsizei,
+) {
+	let (first, count) = unsafe {
+		(
+			std::slice::from_raw_parts(first, drawcount as usize),
+			std::slice::from_raw_parts(count, drawcount as usize),
+		)
+	};
+
+	record(format!("MultiDrawArrays({}, {first:?}, {count:?})", name(mode)));
+}
+
+extern "system" fn multi_draw_elements_base_vertex(
+	mode: GLenum,
+	count: *const GLsizei,
+	ty: GLenum,
+	indices: *const *const c_void,
+	drawcount: GLsizei,
+	base_vertex: *const GLint,
+) {
+	let (count, indices, base_vertex) = unsafe {
+		(
+			std::slice::from_raw_parts(count, drawcount as usize),
+			std::slice::from_raw_parts(indices, drawcount as usize),
+			std::slice::from_raw_parts(base_vertex, drawcount as usize),
+		)
+	};
+	let indices = indices.iter().map(|&i| i as usize).collect::<Vec<_>>();
+
+	record(format!(
+		"MultiDrawElementsBaseVertex({}, {count:?}, {}, {indices:?}, {base_vertex:?})",
+		name(mode),
+		name(ty),
+	));
+}
+
+extern "system" fn fence_sync(_condition: GLenum, _flags: GLbitfield) -> GLsync {
+	let sync = next_name();
+	record(format!("FenceSync({sync})"));
+	sync as GLsync
+}
+
+extern "system" fn client_wait_sync(sync: GLsync, _flags: GLbitfield, timeout: GLuint64) -> GLenum {
+	record(format!("ClientWaitSync({}, {timeout})", sync as usize));
+
+	match (STATE.with(|state| state.borrow().gpu_busy), timeout) {
+		(true, 0) => gl::TIMEOUT_EXPIRED,
+		(true, _) => gl::CONDITION_SATISFIED,
+		(false, _) => gl::ALREADY_SIGNALED,
+	}
+}
+
+extern "system" fn delete_sync(sync: GLsync) {
+	record(format!("DeleteSync({})", sync as usize));
+}
@@ -7,11 +7,30 @@
 use gl::types::{GLenum, GLint, GLuint};
 use thiserror::Error;
 
+use self::pending::PendingProgram;
+
+pub mod cache;
+#[cfg(feature = "hot-reload")]
+pub mod hot_reload;
+pub mod pending;
+pub mod registry;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ShaderType {
 	Vertex,
 	Fragment,
 }
 
+/// Variant of a drawable's shader program
+///
+/// The compat variant receives drawable data as vertex attributes,
+/// the SSBO variant reads it from a shader storage buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ShaderVariant {
+	Compat,
+	Ssbo,
+}
+
 pub struct Shader {
 	ty: ShaderType,
 	shader_object: GLuint,
@@ -21,6 +40,15 @@ pub struct ShaderProgram {
 	pub program_object: GLuint,
 }
 
+/// Copyable reference to a program object, which does not keep it alive
+///
+/// Programs relinked by the registry get a new program object,
+/// so handles should be fetched again rather than stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ProgramHandle {
+	pub program_object: GLuint,
+}
+
 impl ShaderType {
 	#[inline]
 	pub fn gl_type(&self) -> GLenum {
@@ -49,8 +77,47 @@ pub enum ShaderLinkError {
 	Link(String),
 }
 
+/// Error creating a shader program from a `ShaderSource`
+#[derive(Debug, Error)]
+pub enum ProgramCreateError {
+	#[error("could not compile {ty:?} shader for {variant:?} program: {error}")]
+	Compile {
+		variant: ShaderVariant,
+		ty: ShaderType,
+		/// Preprocessed source passed to the driver
+		shader_source: String,
+		error: ShaderCompileError,
+	},
+	#[error("could not link {variant:?} program: {error}")]
+	Link {
+		variant: ShaderVariant,
+		/// Preprocessed vertex source passed to the driver
+		vertex_source: String,
+		/// Preprocessed fragment source passed to the driver
+		fragment_source: String,
+		error: ShaderLinkError,
+	},
+}
+
+impl ProgramCreateError {
+	pub fn variant(&self) -> ShaderVariant {
+		match self {
+			Self::Compile { variant, .. } | Self::Link { variant, .. } => *variant,
+		}
+	}
+}
+
 impl Shader {
 	pub fn compile(ty: ShaderType, source: &str) -> Result<Self, ShaderCompileError> {
+		let shader = Self::submit(ty, source)?;
+		shader.check()?;
+		Ok(shader)
+	}
+
+	/// Submit a shader for compilation without waiting for the result
+	///
+	/// Call `check` to get the result.
+	pub fn submit(ty: ShaderType, source: &str) -> Result<Self, ShaderCompileError> {
 		unsafe {
 			let shader = gl::CreateShader(ty.gl_type());
 			if shader == 0 {
@@ -63,16 +130,26 @@ impl Shader {
 
 			gl::CompileShader(shader);
 
+			Ok(Shader {
+				shader_object: shader,
+				ty,
+			})
+		}
+	}
+
+	/// Get the compilation result, waiting for it if necessary
+	pub fn check(&self) -> Result<(), ShaderCompileError> {
+		unsafe {
 			let mut compile_status = 0 as GLint;
-			gl::GetShaderiv(shader, gl::COMPILE_STATUS, &mut compile_status);
+			gl::GetShaderiv(self.shader_object, gl::COMPILE_STATUS, &mut compile_status);
 
 			if compile_status != gl::TRUE as i32 {
 				let mut log_length = 0 as GLint;
-				gl::GetShaderiv(shader, gl::INFO_LOG_LENGTH, &mut log_length);
+				gl::GetShaderiv(self.shader_object, gl::INFO_LOG_LENGTH, &mut log_length);
 
 				let mut log = Vec::<u8>::with_capacity(log_length as usize);
 				gl::GetShaderInfoLog(
-					shader,
+					self.shader_object,
 					log_length,
 					&mut log_length,
 					log.as_mut_ptr() as *mut i8,
@@ -90,10 +167,7 @@ impl Shader {
 				));
 			}
 
-			Ok(Shader {
-				shader_object: shader,
-				ty,
-			})
+			Ok(())
 		}
 	}
 }
@@ -108,7 +182,50 @@ impl Drop for Shader {
 }
 
 impl ShaderProgram {
+	/// Compile and link a program from source
+	///
+	/// If this thread has a program cache set, the program will be loaded
+	/// from it when possible, and stored in it otherwise.
+	pub fn create(
+		variant: ShaderVariant,
+		vertex_source: &str,
+		fragment_source: &str,
+	) -> Result<Self, ProgramCreateError> {
+		PendingProgram::submit(variant, vertex_source, fragment_source)?.finish()
+	}
+
 	pub fn link(vertex_shader: &Shader, fragment_shader: &Shader) -> Result<Self, ShaderLinkError> {
+		Self::link_impl(vertex_shader, fragment_shader, false)
+	}
+
+	/// Link a program with `PROGRAM_BINARY_RETRIEVABLE_HINT` set,
+	/// so its binary can be read back with `glGetProgramBinary`.
+	pub fn link_retrievable(
+		vertex_shader: &Shader,
+		fragment_shader: &Shader,
+	) -> Result<Self, ShaderLinkError> {
+		Self::link_impl(vertex_shader, fragment_shader, true)
+	}
+
+	fn link_impl(
+		vertex_shader: &Shader,
+		fragment_shader: &Shader,
+		retrievable: bool,
+	) -> Result<Self, ShaderLinkError> {
+		let program = Self::submit_link(vertex_shader, fragment_shader, retrievable)?;
+		program.check_link()?;
+		Ok(program)
+	}
+
+	/// Submit a program for linking without waiting for the result
+	///
+	/// The shaders do not need to have finished compiling.
+	/// Call `check_link` to get the result.
+	pub fn submit_link(
+		vertex_shader: &Shader,
+		fragment_shader: &Shader,
+		retrievable: bool,
+	) -> Result<Self, ShaderLinkError> {
 		match (&vertex_shader.ty, &fragment_shader.ty) {
 			(ShaderType::Vertex, ShaderType::Fragment) => {},
 			_ => return Err(ShaderLinkError::InvalidShader),
@@ -120,6 +237,14 @@ impl ShaderProgram {
 				return Err(ShaderLinkError::CouldNotCreate)
 			}
 
+			if retrievable {
+				gl::ProgramParameteri(
+					program,
+					gl::PROGRAM_BINARY_RETRIEVABLE_HINT,
+					gl::TRUE as GLint,
+				);
+			}
+
 			gl::AttachShader(program, vertex_shader.shader_object);
 			gl::AttachShader(program, fragment_shader.shader_object);
 
@@ -129,16 +254,25 @@ impl ShaderProgram {
 			gl::DetachShader(program, vertex_shader.shader_object);
 			gl::DetachShader(program, fragment_shader.shader_object);
 
+			Ok(ShaderProgram {
+				program_object: program,
+			})
+		}
+	}
+
+	/// Get the link result, waiting for it if necessary
+	pub fn check_link(&self) -> Result<(), ShaderLinkError> {
+		unsafe {
 			let mut link_status = 0;
-			gl::GetProgramiv(program, gl::LINK_STATUS, &mut link_status);
+			gl::GetProgramiv(self.program_object, gl::LINK_STATUS, &mut link_status);
 
 			if link_status != gl::TRUE as i32 {
 				let mut log_length = 0 as GLint;
-				gl::GetProgramiv(program, gl::INFO_LOG_LENGTH, &mut log_length);
+				gl::GetProgramiv(self.program_object, gl::INFO_LOG_LENGTH, &mut log_length);
 
 				let mut log = Vec::<u8>::with_capacity(log_length as usize);
 				gl::GetProgramInfoLog(
-					program,
+					self.program_object,
 					log_length,
 					&mut log_length,
 					log.as_mut_ptr() as *mut i8,
@@ -154,19 +288,35 @@ impl ShaderProgram {
 				));
 			}
 
-			Ok(ShaderProgram {
-				program_object: program,
-			})
+			Ok(())
 		}
 	}
 
 	pub fn bind(&self) {
 		unsafe { gl::UseProgram(self.program_object) };
 	}
+
+	pub fn handle(&self) -> ProgramHandle {
+		ProgramHandle {
+			program_object: self.program_object,
+		}
+	}
+}
+
+impl ProgramHandle {
+	pub fn bind(&self) {
+		unsafe { gl::UseProgram(self.program_object) };
+	}
 }
 
 impl Drop for ShaderProgram {
 	fn drop(&mut self) {
+		// placeholders and programs lost or leaked by the registry make no GL calls,
+		// as their context may be gone
+		if self.program_object == 0 {
+			return
+		}
+
 		// Reduces refcount for shader program.
 		// The OpenGL driver will only delete the backing shader program
 		// when it is not part of any renderin context.
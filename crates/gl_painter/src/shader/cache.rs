@@ -0,0 +1,247 @@
+// Copyright (C) 2022 the ITK authors
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/./
+
+//! On-disk cache of linked program binaries (`ARB_get_program_binary`)
+
+use std::{
+	cell::RefCell,
+	collections::hash_map::DefaultHasher,
+	ffi::c_void,
+	fs,
+	hash::{Hash, Hasher},
+	io,
+	path::{Path, PathBuf},
+};
+
+use gl::types::{GLenum, GLint, GLsizei};
+
+use super::{ShaderProgram, ShaderVariant};
+use crate::GlCapabilities;
+
+#[cfg(test)]
+mod test;
+
+/// Header of a cache entry, followed by the length of the entry's key,
+/// the key, the binary format and the binary itself
+const ENTRY_MAGIC: &[u8; 8] = b"ITKPROG\x02";
+
+thread_local! {
+	static THREAD_CACHE: RefCell<Option<ProgramCache>> = const { RefCell::new(None) };
+}
+
+/// Set the program cache used by `ShaderProgram::create` on this thread,
+/// returning the previous one
+///
+/// GL contexts are bound to a thread, so caches are as well.
+pub fn set_thread_cache(cache: Option<ProgramCache>) -> Option<ProgramCache> {
+	THREAD_CACHE.with(|c| c.replace(cache))
+}
+
+/// Run `f` with this thread's program cache
+pub fn with_thread_cache<R>(f: impl FnOnce(Option<&ProgramCache>) -> R) -> R {
+	THREAD_CACHE.with(|c| f(c.borrow().as_ref()))
+}
+
+/// Identifies a cached program
+#[derive(Hash)]
+pub struct ProgramKey<'a> {
+	pub variant: ShaderVariant,
+	pub vertex_source: &'a str,
+	pub fragment_source: &'a str,
+}
+
+/// Cache of program binaries stored in a directory
+///
+/// Entries are keyed by the program's sources and variant, as well as
+/// the driver's vendor, renderer and version strings, so a driver update
+/// will not try to load stale binaries.
+///
+/// # NOTES
+/// Entry file names are hashed with `DefaultHasher`, which is not guaranteed
+/// to be stable between Rust releases. Entries store their full key, so a
+/// changed hash or a hash collision only causes a cache miss.
+pub struct ProgramCache {
+	directory: PathBuf,
+	driver: String,
+}
+
+impl ProgramCache {
+	/// Create a program cache storing binaries in `directory`
+	///
+	/// Returns `None` if the driver does not support program binaries.
+	///
+	/// # NOTES
+	/// Must be called from the GL thread.
+	pub fn new(directory: impl Into<PathBuf>) -> Option<Self> {
+		let capabilities = unsafe { GlCapabilities::get() };
+		if !capabilities.has_program_binary() {
+			return None
+		}
+
+		let mut format_count = 0 as GLint;
+		unsafe { gl::GetIntegerv(gl::NUM_PROGRAM_BINARY_FORMATS, &mut format_count) };
+		if format_count == 0 {
+			return None
+		}
+
+		let driver = [
+			&capabilities.vendor,
+			&capabilities.renderer,
+			&capabilities.version_string,
+		]
+		.map(String::as_str)
+		.join("\n");
+
+		Some(Self {
+			directory: directory.into(),
+			driver,
+		})
+	}
+
+	pub fn directory(&self) -> &Path {
+		&self.directory
+	}
+
+	fn entry_path(&self, key: &ProgramKey) -> PathBuf {
+		let mut hasher = DefaultHasher::new();
+		self.driver.hash(&mut hasher);
+		key.hash(&mut hasher);
+
+		self.directory.join(format!("{:016x}.bin", hasher.finish()))
+	}
+
+	/// Full key stored in an entry, checked before loading its binary
+	fn entry_key(&self, key: &ProgramKey) -> Vec<u8> {
+		let variant = match key.variant {
+			ShaderVariant::Compat => "compat",
+			ShaderVariant::Ssbo => "ssbo",
+		};
+
+		let mut bytes = Vec::new();
+		for part in [
+			&self.driver[..],
+			variant,
+			key.vertex_source,
+			key.fragment_source,
+		] {
+			bytes.extend_from_slice(&(part.len() as u64).to_le_bytes());
+			bytes.extend_from_slice(part.as_bytes());
+		}
+
+		bytes
+	}
+
+	/// Load a cached program
+	///
+	/// Entries that are malformed or rejected by the driver are
+	/// removed from the cache.
+	pub fn load(&self, key: &ProgramKey) -> Option<ShaderProgram> {
+		let path = self.entry_path(key);
+		let entry = fs::read(&path).ok()?;
+
+		let program = match parse_entry(&entry) {
+			// another program whose entry has the same path
+			Some((stored_key, ..)) if stored_key != self.entry_key(key) => return None,
+			Some((_, format, binary)) => Self::program_from_binary(format, binary),
+			None => None,
+		};
+
+		if program.is_none() {
+			// will be replaced when the program is relinked
+			let _ = fs::remove_file(&path);
+		}
+
+		program
+	}
+
+	fn program_from_binary(format: GLenum, binary: &[u8]) -> Option<ShaderProgram> {
+		unsafe {
+			let program = gl::CreateProgram();
+			if program == 0 {
+				return None
+			}
+
+			gl::ProgramBinary(
+				program,
+				format,
+				binary.as_ptr() as *const c_void,
+				binary.len() as GLsizei,
+			);
+
+			let mut link_status = 0;
+			gl::GetProgramiv(program, gl::LINK_STATUS, &mut link_status);
+
+			if link_status != gl::TRUE as GLint {
+				gl::DeleteProgram(program);
+				return None
+			}
+
+			Some(ShaderProgram {
+				program_object: program,
+			})
+		}
+	}
+
+	/// Store a program's binary
+	///
+	/// The program should have been linked with `ShaderProgram::link_retrievable`.
+	pub fn store(&self, key: &ProgramKey, program: &ShaderProgram) -> io::Result<()> {
+		let mut binary_length = 0 as GLint;
+		unsafe {
+			gl::GetProgramiv(program.program_object, gl::PROGRAM_BINARY_LENGTH, &mut binary_length)
+		};
+
+		if binary_length == 0 {
+			return Err(io::Error::other("driver returned no program binary"))
+		}
+
+		let mut binary = vec![0u8; binary_length as usize];
+		let mut format = 0 as GLenum;
+		unsafe {
+			gl::GetProgramBinary(
+				program.program_object,
+				binary_length,
+				&mut binary_length,
+				&mut format,
+				binary.as_mut_ptr() as *mut c_void,
+			)
+		};
+		binary.truncate(binary_length as usize);
+
+		fs::create_dir_all(&self.directory)?;
+
+		// write to a temporary file first so a crash can't leave a partial entry
+		let path = self.entry_path(key);
+		let temp_path = path.with_extension("tmp");
+		fs::write(&temp_path, encode_entry(&self.entry_key(key), format, &binary))?;
+		fs::rename(temp_path, path)
+	}
+}
+
+fn encode_entry(key: &[u8], format: GLenum, binary: &[u8]) -> Vec<u8> {
+	[
+		&ENTRY_MAGIC[..],
+		&(key.len() as u64).to_le_bytes(),
+		key,
+		&format.to_le_bytes(),
+		binary,
+	]
+	.concat()
+}
+
+/// Split an entry into its key, binary format and binary
+fn parse_entry(entry: &[u8]) -> Option<(&[u8], GLenum, &[u8])> {
+	let entry = entry.strip_prefix(ENTRY_MAGIC)?;
+	let (key_len, entry) = entry.split_first_chunk::<8>()?;
+	let key_len = usize::try_from(u64::from_le_bytes(*key_len)).ok()?;
+	let (key, entry) = entry.split_at_checked(key_len)?;
+	let (format, binary) = entry.split_first_chunk::<4>()?;
+
+	match binary.is_empty() {
+		true => None,
+		false => Some((key, GLenum::from_le_bytes(*format), binary)),
+	}
+}
@@ -0,0 +1,18 @@
+use super::{encode_entry, parse_entry};
+
+#[test]
+fn test_entry_roundtrip() {
+	let entry = encode_entry(b"driver and sources", 0x8741, &[1, 2, 3]);
+
+	assert_eq!(parse_entry(&entry), Some((&b"driver and sources"[..], 0x8741, &[1, 2, 3][..])));
+}
+
+#[test]
+fn test_malformed_entry() {
+	let entry = encode_entry(b"key", 0x8741, &[1, 2, 3]);
+
+	assert_eq!(parse_entry(&entry[..entry.len() - 3]), None);
+	assert_eq!(parse_entry(&entry[..20]), None);
+	assert_eq!(parse_entry(&entry[1..]), None);
+	assert_eq!(parse_entry(&encode_entry(b"key", 0x8741, &[])), None);
+}
@@ -0,0 +1,183 @@
+// Copyright (C) 2022 the ITK authors
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/./
+
+//! Runtime shader reloading for development builds
+
+use std::{
+	any::TypeId,
+	collections::{HashMap, HashSet},
+	fs,
+	path::{Path, PathBuf},
+	sync::mpsc::{self, Receiver},
+};
+
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use super::{registry::ShaderRegistry, ProgramCreateError, ShaderProgram, ShaderVariant};
+use crate::drawable::StageOrigin;
+
+#[derive(Debug, thiserror::Error)]
+enum ReloadError {
+	#[error("could not read {0:?}: {1:#}")]
+	Read(PathBuf, std::io::Error),
+	#[error("error preprocessing {0:?}: {1:#}")]
+	Preprocess(PathBuf, glsl_preprocessor::PreprocError),
+	#[error(transparent)]
+	Create(#[from] ProgramCreateError),
+}
+
+/// Watches the source files of this thread's registered shader programs,
+/// relinking them in place when they change
+///
+/// Only programs whose vertex and fragment stages both have an origin
+/// are reloaded. If a changed source fails to preprocess, compile or link,
+/// the error is logged and the previous program is kept.
+pub struct HotReloader {
+	watcher: RecommendedWatcher,
+	events: Receiver<notify::Result<notify::Event>>,
+	watched_dirs: HashSet<PathBuf>,
+	/// Last sources each program was linked (or attempted to be linked) with
+	sources: HashMap<(TypeId, ShaderVariant), (String, String)>,
+	changed: bool,
+}
+
+impl HotReloader {
+	pub fn new() -> notify::Result<Self> {
+		let (sender, events) = mpsc::channel();
+
+		Ok(Self {
+			watcher: notify::recommended_watcher(sender)?,
+			events,
+			watched_dirs: HashSet::new(),
+			sources: HashMap::new(),
+			// picks up programs registered before the first poll
+			changed: true,
+		})
+	}
+
+	/// Reload programs whose sources changed since the last poll
+	///
+	/// # NOTES
+	/// Must be called from the GL thread, usually once per frame.
+	pub fn poll(&mut self) {
+		while let Ok(event) = self.events.try_recv() {
+			match event {
+				Ok(event) => match event.kind {
+					EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_) =>
+						self.changed = true,
+					_ => {},
+				},
+				Err(e) => log::warn!(target: "gl_painter", "shader watcher error: {e:#}"),
+			}
+		}
+
+		if !std::mem::take(&mut self.changed) {
+			return
+		}
+
+		let mut watch_paths = Vec::<PathBuf>::new();
+
+		ShaderRegistry::with_thread(|registry| {
+			let mut reloaded = Vec::new();
+
+			for (key, source) in registry.ready_programs() {
+				let vertex_stage = source.vertex_stage(key.1);
+				let (vertex_origin, fragment_origin) =
+					match (&vertex_stage.origin, &source.fragment.origin) {
+						(Some(vertex), Some(fragment)) => (vertex, fragment),
+						_ => continue,
+					};
+
+				let result = (|| -> Result<Option<ShaderProgram>, ReloadError> {
+					let vertex = preprocess_stage(vertex_origin, &mut watch_paths)?;
+					let fragment = preprocess_stage(fragment_origin, &mut watch_paths)?;
+
+					let last = self.sources.entry(key).or_insert_with(|| {
+						(vertex_stage.source.to_owned(), source.fragment.source.to_owned())
+					});
+
+					if (&vertex, &fragment) == (&last.0, &last.1) {
+						return Ok(None)
+					}
+
+					*last = (vertex, fragment);
+					Ok(Some(ShaderProgram::create(key.1, &last.0, &last.1)?))
+				})();
+
+				match result {
+					Ok(Some(new_program)) => {
+						let (vertex, fragment) = self.sources[&key].clone();
+						reloaded.push((key, vertex, fragment, new_program));
+						log::info!(
+							target: "gl_painter",
+							"reloaded {:?} shader from {:?}",
+							key.1,
+							vertex_origin.path,
+						);
+					},
+					Ok(None) => {},
+					Err(e) => log::error!(
+						target: "gl_painter",
+						"could not reload {:?} shader from {:?}, keeping previous program: {e}",
+						key.1,
+						vertex_origin.path,
+					),
+				}
+			}
+
+			for (key, vertex, fragment, program) in reloaded {
+				registry.replace_program(key, vertex, fragment, program);
+			}
+		});
+
+		for path in watch_paths {
+			if let Some(dir) = path.parent() {
+				self.watch_dir(dir);
+			}
+		}
+	}
+
+	fn watch_dir(&mut self, dir: &Path) {
+		if self.watched_dirs.contains(dir) {
+			return
+		}
+
+		match self.watcher.watch(dir, RecursiveMode::NonRecursive) {
+			Ok(()) => {
+				self.watched_dirs.insert(dir.to_owned());
+			},
+			Err(e) => log::warn!(target: "gl_painter", "could not watch {dir:?}: {e:#}"),
+		}
+	}
+}
+
+/// Preprocess a stage from disk, adding its source and included files to `paths`
+fn preprocess_stage(origin: &StageOrigin, paths: &mut Vec<PathBuf>) -> Result<String, ReloadError> {
+	let path = Path::new(origin.path);
+	let dir = path.parent().unwrap_or(Path::new(""));
+	let basedir = Path::new(origin.basedir);
+	paths.push(path.to_owned());
+
+	let source = fs::read_to_string(path).map_err(|e| ReloadError::Read(path.to_owned(), e))?;
+
+	let defines = origin
+		.defines
+		.iter()
+		.map(|&(k, v)| (k.to_owned(), v.to_owned()))
+		.collect::<HashMap<String, String>>();
+
+	let (source, line_mapping) = glsl_preprocessor::preprocess(&source, dir, basedir, defines)
+		.map_err(|e| ReloadError::Preprocess(path.to_owned(), e))?;
+
+	paths.extend(
+		line_mapping
+			.values()
+			.filter_map(|line| line.file())
+			.map(|include| glsl_preprocessor::resolve_include(include, dir, basedir)),
+	);
+
+	Ok(source)
+}
@@ -0,0 +1,202 @@
+// Copyright (C) 2022 the ITK authors
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/./
+
+//! Non-blocking program creation (`KHR_parallel_shader_compile`)
+
+use gl::types::{GLenum, GLint};
+
+use super::{
+	cache::{self, ProgramKey},
+	ProgramCreateError,
+	Shader,
+	ShaderProgram,
+	ShaderType,
+	ShaderVariant,
+};
+use crate::GlCapabilities;
+
+/// `GL_COMPLETION_STATUS_KHR`, shared with `ARB_parallel_shader_compile`
+const COMPLETION_STATUS_KHR: GLenum = 0x91b1;
+
+/// Program that has been submitted for compilation and linking,
+/// but may not have finished yet
+///
+/// Submitting every program before checking any of them lets the driver
+/// compile them in parallel, instead of stalling on each one in turn.
+pub struct PendingProgram<'s> {
+	variant: ShaderVariant,
+	vertex_source: &'s str,
+	fragment_source: &'s str,
+	state: PendingState,
+}
+
+enum PendingState {
+	Cached(ShaderProgram),
+	Submitted {
+		vertex_shader: Shader,
+		fragment_shader: Shader,
+		program: ShaderProgram,
+		can_poll: bool,
+		store_in_cache: bool,
+	},
+}
+
+impl<'s> PendingProgram<'s> {
+	/// Submit a program for compilation and linking
+	///
+	/// If this thread has a program cache set, the program will be loaded
+	/// from it when possible, and stored in it by `finish` otherwise.
+	///
+	/// Only errors creating GL objects are returned here,
+	/// compile and link errors are returned by `finish`.
+	pub fn submit(
+		variant: ShaderVariant,
+		vertex_source: &'s str,
+		fragment_source: &'s str,
+	) -> Result<Self, ProgramCreateError> {
+		let cached = cache::with_thread_cache(|cache| {
+			cache.map(|cache| {
+				cache.load(&ProgramKey {
+					variant,
+					vertex_source,
+					fragment_source,
+				})
+			})
+		});
+
+		let store_in_cache = match cached {
+			Some(Some(program)) =>
+				return Ok(Self {
+					variant,
+					vertex_source,
+					fragment_source,
+					state: PendingState::Cached(program),
+				}),
+			Some(None) => true,
+			None => false,
+		};
+
+		let submit = |ty: ShaderType, source: &str| {
+			Shader::submit(ty, source).map_err(|error| ProgramCreateError::Compile {
+				variant,
+				ty,
+				shader_source: source.to_owned(),
+				error,
+			})
+		};
+
+		let vertex_shader = submit(ShaderType::Vertex, vertex_source)?;
+		let fragment_shader = submit(ShaderType::Fragment, fragment_source)?;
+
+		let program = ShaderProgram::submit_link(&vertex_shader, &fragment_shader, store_in_cache)
+			.map_err(|error| ProgramCreateError::Link {
+				variant,
+				vertex_source: vertex_source.to_owned(),
+				fragment_source: fragment_source.to_owned(),
+				error,
+			})?;
+
+		Ok(Self {
+			variant,
+			vertex_source,
+			fragment_source,
+			state: PendingState::Submitted {
+				vertex_shader,
+				fragment_shader,
+				program,
+				can_poll: unsafe { GlCapabilities::get() }.has_parallel_shader_compile(),
+				store_in_cache,
+			},
+		})
+	}
+
+	pub fn variant(&self) -> ShaderVariant {
+		self.variant
+	}
+
+	/// Check if the program has finished compiling and linking,
+	/// so `finish` will not block
+	///
+	/// Always returns true if the driver does not support
+	/// `KHR_parallel_shader_compile`, in which case `finish` may block.
+	pub fn is_ready(&self) -> bool {
+		match &self.state {
+			PendingState::Cached(_) => true,
+			PendingState::Submitted {
+				program,
+				can_poll: true,
+				..
+			} => {
+				let mut completion_status = 0 as GLint;
+				unsafe {
+					gl::GetProgramiv(
+						program.program_object,
+						COMPLETION_STATUS_KHR,
+						&mut completion_status,
+					)
+				};
+
+				completion_status == gl::TRUE as GLint
+			},
+			PendingState::Submitted { .. } => true,
+		}
+	}
+
+	/// Get the linked program, waiting for it if necessary
+	pub fn finish(self) -> Result<ShaderProgram, ProgramCreateError> {
+		let (vertex_shader, fragment_shader, program, store_in_cache) = match self.state {
+			PendingState::Cached(program) => return Ok(program),
+			PendingState::Submitted {
+				vertex_shader,
+				fragment_shader,
+				program,
+				store_in_cache,
+				..
+			} => (vertex_shader, fragment_shader, program, store_in_cache),
+		};
+
+		if let Err(error) = program.check_link() {
+			// a failed compile also fails the link, so report it instead if there is one
+			for (ty, shader, source) in [
+				(ShaderType::Vertex, &vertex_shader, self.vertex_source),
+				(ShaderType::Fragment, &fragment_shader, self.fragment_source),
+			] {
+				if let Err(error) = shader.check() {
+					return Err(ProgramCreateError::Compile {
+						variant: self.variant,
+						ty,
+						shader_source: source.to_owned(),
+						error,
+					})
+				}
+			}
+
+			return Err(ProgramCreateError::Link {
+				variant: self.variant,
+				vertex_source: self.vertex_source.to_owned(),
+				fragment_source: self.fragment_source.to_owned(),
+				error,
+			})
+		}
+
+		if store_in_cache {
+			cache::with_thread_cache(|cache| {
+				if let Some(cache) = cache {
+					let key = ProgramKey {
+						variant: self.variant,
+						vertex_source: self.vertex_source,
+						fragment_source: self.fragment_source,
+					};
+
+					// failing to store the program only costs a recompile next time
+					let _ = cache.store(&key, &program);
+				}
+			});
+		}
+
+		Ok(program)
+	}
+}
@@ -0,0 +1,423 @@
+// Copyright (C) 2022 the ITK authors
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/./
+
+//! Shader programs shared between uploaders of the same drawable type
+
+use std::{
+	any::TypeId,
+	borrow::Cow,
+	cell::{Cell, RefCell},
+	collections::HashMap,
+	rc::Rc,
+};
+
+use super::{
+	pending::PendingProgram,
+	ProgramCreateError,
+	ProgramHandle,
+	ShaderProgram,
+	ShaderVariant,
+};
+use crate::drawable::Drawable;
+#[cfg(feature = "hot-reload")]
+use crate::drawable::ShaderSource;
+
+#[cfg(test)]
+mod test;
+
+/// Shader program that can be shared between uploaders
+///
+/// Programs are relinked in place by the registry, so holders
+/// always see the current program.
+#[derive(Clone)]
+pub struct SharedProgram(Rc<SharedProgramInner>);
+
+struct SharedProgramInner {
+	program: RefCell<ShaderProgram>,
+	state: Cell<ProgramState>,
+}
+
+/// State of a shared program
+///
+/// Only ready programs have a program object, the others have one of 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgramState {
+	/// Still compiling, finished by `ShaderRegistry::poll` or `get`
+	Pending,
+	Ready,
+	/// Compiling or linking failed, and the error was returned by the
+	/// registry call that finished the program. Retried by `get`,
+	/// `get_pending` and `recompile_all`.
+	Failed,
+	/// Lost by `ShaderRegistry::invalidate`, recreated by `get`,
+	/// `get_pending` and `recompile_all`
+	Invalid,
+}
+
+thread_local! {
+	static THREAD_REGISTRY: ThreadRegistry = ThreadRegistry(RefCell::new(ShaderRegistry::new()));
+}
+
+/// Registry leaking its programs on thread exit instead of deleting them,
+/// as the thread's context may already be destroyed by then
+struct ThreadRegistry(RefCell<ShaderRegistry>);
+
+impl Drop for ThreadRegistry {
+	fn drop(&mut self) {
+		self.0.get_mut().invalidate();
+	}
+}
+
+struct RegistryEntry {
+	#[cfg(feature = "hot-reload")]
+	source: fn() -> ShaderSource,
+	/// Sources the program is recreated from, replaced when it is hot reloaded
+	vertex_source: Cow<'static, str>,
+	fragment_source: Cow<'static, str>,
+	program: SharedProgram,
+	state: EntryState,
+}
+
+enum EntryState {
+	Ready,
+	/// Program is still compiling. The shared program is a placeholder
+	/// with a program object of 0 until it finishes.
+	Pending(PendingProgram<'static>),
+	Invalid,
+}
+
+/// Registry of shader programs keyed by drawable type and variant
+///
+/// Programs are created lazily when first requested.
+pub struct ShaderRegistry {
+	programs: HashMap<(TypeId, ShaderVariant), RegistryEntry>,
+}
+
+impl ShaderRegistry {
+	pub fn new() -> Self {
+		Self {
+			programs: HashMap::new(),
+		}
+	}
+
+	/// Run `f` with this thread's registry
+	///
+	/// GL contexts are bound to a thread, so registries are as well.
+	/// Uploaders get their programs from this registry.
+	///
+	/// Programs left in this registry when the thread exits are not deleted.
+	/// Call `destroy` before destroying the thread's context to delete them.
+	pub fn with_thread<R>(f: impl FnOnce(&mut ShaderRegistry) -> R) -> R {
+		THREAD_REGISTRY.with(|r| f(&mut r.0.borrow_mut()))
+	}
+
+	/// Get the program for `D`'s shader variant, creating it if it
+	/// does not exist or was invalidated
+	///
+	/// Waits for the program if it is pending.
+	pub fn get<D: Drawable + 'static>(
+		&mut self,
+		variant: ShaderVariant,
+	) -> Result<SharedProgram, ProgramCreateError> {
+		let key = (TypeId::of::<D>(), variant);
+
+		match self.programs.get_mut(&key) {
+			Some(entry) => {
+				let result = match std::mem::replace(&mut entry.state, EntryState::Invalid) {
+					EntryState::Ready => Ok(None),
+					EntryState::Pending(pending) => pending.finish().map(Some),
+					EntryState::Invalid => entry.create_program(variant).map(Some),
+				};
+
+				match result {
+					Ok(Some(program)) => entry.program.replace(program),
+					Ok(None) => {},
+					Err(e) => {
+						entry.program.set_state(ProgramState::Failed);
+						return Err(e)
+					},
+				}
+
+				entry.state = EntryState::Ready;
+				Ok(entry.program.clone())
+			},
+			None => {
+				let program = SharedProgram::new(D::SHADER_SOURCE.create_program(variant)?);
+
+				self.programs.insert(
+					key,
+					RegistryEntry::new::<D>(variant, program.clone(), EntryState::Ready),
+				);
+
+				Ok(program)
+			},
+		}
+	}
+
+	/// Get the program for `D`'s shader variant without waiting for it to compile
+	///
+	/// If the program does not exist, was invalidated or failed, it is submitted
+	/// for compilation, and the returned program will have a program object of 0
+	/// until `poll` finishes it.
+	pub fn get_pending<D: Drawable + 'static>(
+		&mut self,
+		variant: ShaderVariant,
+	) -> Result<SharedProgram, ProgramCreateError> {
+		let key = (TypeId::of::<D>(), variant);
+
+		if let Some(entry) = self.programs.get_mut(&key) {
+			if let EntryState::Invalid = entry.state {
+				match entry.submit_program(variant) {
+					Ok(state) => entry.state = state,
+					Err(e) => {
+						entry.program.set_state(ProgramState::Failed);
+						return Err(e)
+					},
+				}
+			}
+
+			return Ok(entry.program.clone())
+		}
+
+		let pending = D::SHADER_SOURCE.submit_program(variant)?;
+		let program = SharedProgram::placeholder(ProgramState::Pending);
+
+		self.programs.insert(
+			key,
+			RegistryEntry::new::<D>(variant, program.clone(), EntryState::Pending(pending)),
+		);
+
+		Ok(program)
+	}
+
+	/// Finish every pending program that has completed, without blocking
+	///
+	/// Programs that fail are marked as failed. The first error encountered is returned.
+	pub fn poll(&mut self) -> Result<(), ProgramCreateError> {
+		let mut result = Ok(());
+
+		for entry in self.programs.values_mut() {
+			if !matches!(&entry.state, EntryState::Pending(pending) if pending.is_ready()) {
+				continue
+			}
+
+			let pending = match std::mem::replace(&mut entry.state, EntryState::Invalid) {
+				EntryState::Pending(x) => x,
+				_ => unreachable!(),
+			};
+
+			match pending.finish() {
+				Ok(program) => {
+					entry.program.replace(program);
+					entry.state = EntryState::Ready;
+				},
+				Err(e) => {
+					entry.program.set_state(ProgramState::Failed);
+
+					if result.is_ok() {
+						result = Err(e);
+					}
+				},
+			}
+		}
+
+		result
+	}
+
+	/// Check if any program is still pending
+	pub fn has_pending(&self) -> bool {
+		self.programs
+			.values()
+			.any(|entry| matches!(entry.state, EntryState::Pending(_)))
+	}
+
+	/// Relink every program in place, from the sources it was last
+	/// linked or hot reloaded with
+	///
+	/// Programs that fail to relink keep their previous program if it is
+	/// still valid. Pending programs are left as is.
+	/// The first error encountered is returned.
+	pub fn recompile_all(&mut self) -> Result<(), ProgramCreateError> {
+		let mut result = Ok(());
+
+		for (&(_, variant), entry) in &mut self.programs {
+			if let EntryState::Pending(_) = entry.state {
+				continue
+			}
+
+			match entry.create_program(variant) {
+				Ok(program) => {
+					entry.program.replace(program);
+					entry.state = EntryState::Ready;
+				},
+				Err(e) => {
+					if let EntryState::Invalid = entry.state {
+						entry.program.set_state(ProgramState::Failed);
+					}
+
+					if result.is_ok() {
+						result = Err(e);
+					}
+				},
+			}
+		}
+
+		result
+	}
+
+	/// Mark every program as invalid without deleting its program object,
+	/// for use after a context loss
+	///
+	/// Invalid programs are recreated in place by `get` or `recompile_all`,
+	/// from the sources they were last linked or hot reloaded with.
+	pub fn invalidate(&mut self) {
+		for entry in self.programs.values_mut() {
+			if let EntryState::Pending(pending) =
+				std::mem::replace(&mut entry.state, EntryState::Invalid)
+			{
+				// the pending program's objects are lost as well
+				std::mem::forget(pending);
+			}
+
+			// the lost object is never deleted, as dropping a program of 0 makes no GL calls
+			entry.program.0.program.borrow_mut().program_object = 0;
+			entry.program.set_state(ProgramState::Invalid);
+		}
+	}
+
+	/// Iterate over every ready program with its key and embedded source
+	#[cfg(feature = "hot-reload")]
+	pub(super) fn ready_programs(
+		&self,
+	) -> impl Iterator<Item = ((TypeId, ShaderVariant), ShaderSource)> + '_ {
+		self.programs
+			.iter()
+			.filter(|(_, entry)| matches!(entry.state, EntryState::Ready))
+			.map(|(&key, entry)| (key, (entry.source)()))
+	}
+
+	/// Replace a ready program with one linked from new sources,
+	/// which are used to recreate it from then on
+	#[cfg(feature = "hot-reload")]
+	pub(super) fn replace_program(
+		&mut self,
+		key: (TypeId, ShaderVariant),
+		vertex_source: String,
+		fragment_source: String,
+		program: ShaderProgram,
+	) {
+		if let Some(entry) = self.programs.get_mut(&key) {
+			entry.vertex_source = Cow::Owned(vertex_source);
+			entry.fragment_source = Cow::Owned(fragment_source);
+			entry.program.replace(program);
+			entry.state = EntryState::Ready;
+		}
+	}
+
+	/// Delete every program object and empty the registry
+	///
+	/// Uploaders still holding a program see a program object of 0
+	/// and draw nothing.
+	///
+	/// # SAFETY
+	/// * must be called from GL thread, before its context is destroyed
+	pub unsafe fn destroy(&mut self) {
+		for (_, entry) in self.programs.drain() {
+			entry.program.replace(ShaderProgram { program_object: 0 });
+			entry.program.set_state(ProgramState::Invalid);
+		}
+	}
+
+	/// Remove programs that are not used outside of the registry
+	pub fn remove_unused(&mut self) {
+		self.programs.retain(|_, entry| Rc::strong_count(&entry.program.0) > 1);
+	}
+}
+
+impl SharedProgram {
+	/// Share a linked program
+	pub fn new(program: ShaderProgram) -> Self {
+		Self(Rc::new(SharedProgramInner {
+			program: RefCell::new(program),
+			state: Cell::new(ProgramState::Ready),
+		}))
+	}
+
+	fn placeholder(state: ProgramState) -> Self {
+		let program = Self::new(ShaderProgram { program_object: 0 });
+		program.set_state(state);
+		program
+	}
+
+	pub fn handle(&self) -> ProgramHandle {
+		self.0.program.borrow().handle()
+	}
+
+	pub fn bind(&self) {
+		self.0.program.borrow().bind()
+	}
+
+	pub fn state(&self) -> ProgramState {
+		self.0.state.get()
+	}
+
+	/// Replace the program, deleting the previous one, and mark it as ready
+	fn replace(&self, program: ShaderProgram) {
+		let previous = self.0.program.replace(program);
+		self.set_state(ProgramState::Ready);
+		drop(previous);
+	}
+
+	fn set_state(&self, state: ProgramState) {
+		self.0.state.set(state);
+	}
+}
+
+impl RegistryEntry {
+	fn new<D: Drawable>(variant: ShaderVariant, program: SharedProgram, state: EntryState) -> Self {
+		Self {
+			#[cfg(feature = "hot-reload")]
+			source: source::<D>,
+			vertex_source: Cow::Borrowed(D::SHADER_SOURCE.vertex(variant)),
+			fragment_source: Cow::Borrowed(D::SHADER_SOURCE.fragment.source),
+			program,
+			state,
+		}
+	}
+
+	fn create_program(&self, variant: ShaderVariant) -> Result<ShaderProgram, ProgramCreateError> {
+		ShaderProgram::create(variant, &self.vertex_source, &self.fragment_source)
+	}
+
+	/// Submit the program for compilation, marking it as pending
+	///
+	/// Hot reloaded sources are not `'static`, so they are compiled
+	/// without waiting for `poll`.
+	fn submit_program(&self, variant: ShaderVariant) -> Result<EntryState, ProgramCreateError> {
+		match (&self.vertex_source, &self.fragment_source) {
+			(Cow::Borrowed(vertex_source), Cow::Borrowed(fragment_source)) => {
+				let pending = PendingProgram::submit(variant, vertex_source, fragment_source)?;
+				self.program.set_state(ProgramState::Pending);
+				Ok(EntryState::Pending(pending))
+			},
+			_ => {
+				self.program.replace(self.create_program(variant)?);
+				Ok(EntryState::Ready)
+			},
+		}
+	}
+}
+
+#[cfg(feature = "hot-reload")]
+fn source<D: Drawable>() -> ShaderSource {
+	D::SHADER_SOURCE
+}
+
+impl Default for ShaderRegistry {
+	fn default() -> Self {
+		Self::new()
+	}
+}
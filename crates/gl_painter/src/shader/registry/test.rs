@@ -0,0 +1,44 @@
+use super::{ProgramState, ShaderRegistry};
+use crate::{drawable::ColoredTriangle, mock_gl, shader::ShaderVariant};
+
+#[test]
+fn test_pending_after_invalidate() {
+	mock_gl::install(4, 3);
+	let mut registry = ShaderRegistry::new();
+
+	let program = registry.get_pending::<ColoredTriangle>(ShaderVariant::Compat).unwrap();
+	registry.poll().unwrap();
+	assert_eq!(program.state(), ProgramState::Ready);
+
+	registry.invalidate();
+	assert_eq!(program.state(), ProgramState::Invalid);
+
+	// the invalid program is submitted again rather than returned as is
+	registry.get_pending::<ColoredTriangle>(ShaderVariant::Compat).unwrap();
+	assert_eq!(program.state(), ProgramState::Pending);
+	assert!(registry.has_pending());
+
+	registry.poll().unwrap();
+	assert_eq!(program.state(), ProgramState::Ready);
+	assert_ne!(program.handle().program_object, 0);
+}
+
+#[test]
+fn test_pending_after_failure() {
+	mock_gl::install(4, 3);
+	let mut registry = ShaderRegistry::new();
+
+	mock_gl::set_link_fails(true);
+	let program = registry.get_pending::<ColoredTriangle>(ShaderVariant::Compat).unwrap();
+	assert!(registry.poll().is_err());
+	assert_eq!(program.state(), ProgramState::Failed);
+
+	// the failed program is retried
+	mock_gl::set_link_fails(false);
+	registry.get_pending::<ColoredTriangle>(ShaderVariant::Compat).unwrap();
+	assert_eq!(program.state(), ProgramState::Pending);
+
+	registry.poll().unwrap();
+	assert_eq!(program.state(), ProgramState::Ready);
+	assert_ne!(program.handle().program_object, 0);
+}
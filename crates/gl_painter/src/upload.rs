@@ -4,13 +4,30 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/./
 
+use std::mem;
+
 pub use self::attribute::VertexAttribute;
-use crate::{drawable::Drawable, shader::ShaderProgram};
+use self::stats::UploaderStats;
+use crate::{
+	drawable::{Drawable, DrawableData},
+	shader::{registry::ProgramState, ProgramCreateError, ProgramHandle},
+	GlCapabilities,
+};
 
+pub mod alloc;
 pub mod attribute;
+mod batch;
 pub mod buffer;
 pub mod compat;
+pub mod draw_list;
+pub mod encoder;
+pub mod index;
+pub mod indirect;
+pub mod instanced;
+pub mod retained;
 pub mod ssbo;
+pub mod stats;
+pub mod sync;
 
 pub trait Uploader<D: Drawable> {
 	/// # SAFETY
@@ -33,13 +50,136 @@ pub trait Uploader<D: Drawable> {
 	/// # SAFETY
 	/// * must be called from GL thread, after sync_flush
 	unsafe fn upload(&mut self);
-	/// Should be called as soon as possible after
-	/// finishing buffer use.
+	/// Fence the commands reading this uploader's buffers, called by `upload`
+	///
+	/// Should be called as soon as possible after finishing buffer use
+	/// when drawing without `upload`. The next `prepare_write` waits until
+	/// fewer than the frame latency of fenced frames are still in flight.
 	///
 	/// # SAFETY
 	/// * must be called from GL thread
 	unsafe fn finish_use(&mut self);
+	/// Set the number of frames the CPU may prepare while the GPU is still
+	/// drawing earlier ones, at least 1
+	///
+	/// Defaults to `sync::DEFAULT_FRAME_LATENCY`.
+	fn set_frame_latency(&mut self, frames: usize);
 	/// Clear buffers
 	unsafe fn clear(&mut self);
-	fn shader_program(&self) -> &ShaderProgram;
+	/// Get the current program object of the uploader's shader
+	fn shader_program(&self) -> ProgramHandle;
+	/// Get a snapshot of draw and buffer statistics
+	fn stats(&self) -> UploaderStats;
+	/// Get the state of the shader program
+	///
+	/// Errors of failed programs are returned by the `ShaderRegistry` call
+	/// that finished them, usually `poll`.
+	fn program_state(&self) -> ProgramState;
+	/// Check if the shader program is ready to draw with
+	///
+	/// `upload` does nothing until it is.
+	fn is_ready(&self) -> bool {
+		self.program_state() == ProgramState::Ready
+	}
+}
+
+/// Environment variable forcing the backend used by `new`,
+/// either `compat` or `ssbo`
+///
+/// Any other value is ignored with a warning.
+pub const BACKEND_ENV_VAR: &str = "ITK_GL_UPLOADER";
+
+/// Minimum number of drawables the SSBO backend must be able to hold
+/// for `Backend::detect` to choose it
+const MIN_SSBO_DRAWABLES: u64 = 1 << 16;
+
+/// Uploader implementations selectable by `new`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Backend {
+	Compat,
+	Ssbo,
+}
+
+impl Backend {
+	/// Get the backend forced by `BACKEND_ENV_VAR`, if any
+	pub fn from_env() -> Option<Self> {
+		match std::env::var(BACKEND_ENV_VAR).ok()?.as_str() {
+			"compat" => Some(Self::Compat),
+			"ssbo" => Some(Self::Ssbo),
+			value => {
+				log::warn!(
+					target: "gl_painter",
+					"ignoring {BACKEND_ENV_VAR}={value:?}, expected compat or ssbo"
+				);
+				None
+			},
+		}
+	}
+
+	/// Choose the best backend for `D` supported by the current context
+	///
+	/// The SSBO backend needs GL 4.3 or `ARB_shader_storage_buffer_object`,
+	/// storage blocks in vertex shaders, and a block size large enough
+	/// for a reasonable number of `D`'s drawable data.
+	///
+	/// # SAFETY
+	/// * must be called from GL thread
+	pub unsafe fn detect<D: Drawable>() -> Self {
+		let capabilities = GlCapabilities::get();
+		if !capabilities.has_shader_storage_buffers() {
+			return Self::Compat
+		}
+
+		let limits = &capabilities.limits;
+		let drawable_size = mem::size_of::<<D::Drawable as DrawableData>::Ssbo>().max(1) as u64;
+
+		match limits.max_vertex_shader_storage_blocks > 0
+			&& limits.max_shader_storage_block_size / drawable_size >= MIN_SSBO_DRAWABLES
+		{
+			true => Self::Ssbo,
+			false => Self::Compat,
+		}
+	}
+}
+
+/// Create an uploader for `D`
+///
+/// Uses the backend forced by `BACKEND_ENV_VAR`, or else the one chosen
+/// by `Backend::detect`. If the SSBO backend was detected but its shader
+/// variant cannot be created, the error is logged and a `CompatUploader`
+/// is created instead.
+///
+/// # SAFETY
+/// * must be called from GL thread (shader creation)
+pub unsafe fn new<D: Drawable + 'static>() -> Result<Box<dyn Uploader<D>>, ProgramCreateError> {
+	if let Some(backend) = Backend::from_env() {
+		return new_with_backend::<D>(backend)
+	}
+
+	match Backend::detect::<D>() {
+		Backend::Ssbo => match ssbo::SsboUploader::<D>::new() {
+			Ok(uploader) => Ok(Box::new(uploader)),
+			Err(e) => {
+				log::warn!(
+					target: "gl_painter",
+					"could not create SSBO uploader, falling back to compat: {e}",
+				);
+				new_with_backend::<D>(Backend::Compat)
+			},
+		},
+		Backend::Compat => new_with_backend::<D>(Backend::Compat),
+	}
+}
+
+/// Create an uploader for `D` using the given backend
+///
+/// # SAFETY
+/// * must be called from GL thread (shader creation)
+pub unsafe fn new_with_backend<D: Drawable + 'static>(
+	backend: Backend,
+) -> Result<Box<dyn Uploader<D>>, ProgramCreateError> {
+	Ok(match backend {
+		Backend::Compat => Box::new(compat::CompatUploader::<D>::new()?),
+		Backend::Ssbo => Box::new(ssbo::SsboUploader::<D>::new()?),
+	})
 }
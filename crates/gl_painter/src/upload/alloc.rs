@@ -0,0 +1,140 @@
+// Copyright (C) 2022 the ITK authors
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/./
+
+use std::ops::Range;
+
+#[cfg(test)]
+mod test;
+
+/// First-fit allocator of element ranges within a growable buffer
+///
+/// Allocations that do not fit in a free range are placed at the end
+/// of the buffer, and freed ranges at the end of the buffer shrink it.
+#[derive(Debug, Default, Clone)]
+pub struct RangeAllocator {
+	/// Sorted, disjoint and never adjacent to each other or the end of the buffer
+	free: Vec<Range<usize>>,
+	len: usize,
+}
+
+impl RangeAllocator {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Length of the buffer, including free ranges
+	pub fn len(&self) -> usize {
+		self.len
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len == 0
+	}
+
+	/// Number of free elements before the end of the buffer
+	pub fn free_len(&self) -> usize {
+		self.free.iter().map(|r| r.len()).sum()
+	}
+
+	/// Ranges between the free ranges, in order
+	///
+	/// Adjacent allocations are merged into a single range.
+	pub fn allocated(&self) -> impl Iterator<Item = Range<usize>> + '_ {
+		let starts = [0].into_iter().chain(self.free.iter().map(|r| r.end));
+		let ends = self.free.iter().map(|r| r.start).chain([self.len]);
+
+		starts.zip(ends).map(|(start, end)| start..end).filter(|r| !r.is_empty())
+	}
+
+	/// Allocate `count` elements
+	pub fn alloc(&mut self, count: usize) -> Range<usize> {
+		self.alloc_aligned(count, 1)
+	}
+
+	/// Allocate `count` elements starting at a multiple of `alignment`
+	///
+	/// Padding skipped to align the allocation is left free.
+	pub fn alloc_aligned(&mut self, count: usize, alignment: usize) -> Range<usize> {
+		if count == 0 {
+			return 0..0
+		}
+
+		let found = self.free.iter().enumerate().find_map(|(i, r)| {
+			let start = align_up(r.start, alignment);
+			(start + count <= r.end).then_some((i, start))
+		});
+
+		match found {
+			Some((i, start)) => {
+				let free = self.free[i].clone();
+				let remaining = [free.start..start, start + count..free.end];
+
+				self.free.splice(i..=i, remaining.into_iter().filter(|r| r.start != r.end));
+
+				start..start + count
+			},
+			None => {
+				let start = align_up(self.len, alignment);
+
+				// the last free range never touches the end, so padding can't be merged
+				if start != self.len {
+					self.free.push(self.len..start);
+				}
+
+				self.len = start + count;
+				start..self.len
+			},
+		}
+	}
+
+	/// Free a range returned by `alloc`
+	pub fn free(&mut self, range: Range<usize>) {
+		if range.is_empty() {
+			return
+		}
+
+		let i = self.free.partition_point(|r| r.end <= range.start);
+		let merge_prev = i > 0 && self.free[i - 1].end == range.start;
+		let merge_next = i < self.free.len() && self.free[i].start == range.end;
+
+		let i = match (merge_prev, merge_next) {
+			(true, true) => {
+				self.free[i - 1].end = self.free.remove(i).end;
+				i - 1
+			},
+			(true, false) => {
+				self.free[i - 1].end = range.end;
+				i - 1
+			},
+			(false, true) => {
+				self.free[i].start = range.start;
+				i
+			},
+			(false, false) => {
+				self.free.insert(i, range);
+				i
+			},
+		};
+
+		if self.free[i].end == self.len {
+			self.len = self.free.remove(i).start;
+		}
+	}
+
+	/// Forget every allocation, resetting the buffer to `len` allocated elements
+	pub fn reset(&mut self, len: usize) {
+		self.free.clear();
+		self.len = len;
+	}
+}
+
+/// Round `value` up to a multiple of `alignment`
+pub fn align_up(value: usize, alignment: usize) -> usize {
+	match value % alignment {
+		0 => value,
+		rem => value + alignment - rem,
+	}
+}
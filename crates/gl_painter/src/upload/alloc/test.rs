@@ -0,0 +1,87 @@
+use super::{align_up, RangeAllocator};
+
+#[test]
+fn test_alloc() {
+	let mut alloc = RangeAllocator::new();
+
+	assert_eq!(alloc.alloc(4), 0..4);
+	assert_eq!(alloc.alloc(4), 4..8);
+	assert_eq!(alloc.alloc(4), 8..12);
+	assert_eq!(alloc.alloc(0), 0..0);
+
+	alloc.free(4..8);
+	assert_eq!(alloc.free_len(), 4);
+
+	// first fit
+	assert_eq!(alloc.alloc(2), 4..6);
+	assert_eq!(alloc.alloc(4), 12..16);
+	assert_eq!(alloc.alloc(2), 6..8);
+	assert_eq!(alloc.free_len(), 0);
+	assert_eq!(alloc.len(), 16);
+}
+
+#[test]
+fn test_free_merge() {
+	let mut alloc = RangeAllocator::new();
+
+	for _ in 0..4 {
+		alloc.alloc(4);
+	}
+
+	alloc.free(0..4);
+	alloc.free(8..12);
+	alloc.free(4..8);
+	assert_eq!(alloc.free_len(), 12);
+	assert_eq!(alloc.alloc(12), 0..12);
+
+	// freeing the end shrinks the buffer, including merged free ranges
+	alloc.free(4..8);
+	alloc.free(12..16);
+	assert_eq!(alloc.len(), 12);
+	alloc.free(8..12);
+	assert_eq!(alloc.len(), 4);
+	assert_eq!(alloc.free_len(), 0);
+}
+
+#[test]
+fn test_alloc_aligned() {
+	let mut alloc = RangeAllocator::new();
+
+	assert_eq!(alloc.alloc(3), 0..3);
+	assert_eq!(alloc.alloc_aligned(4, 8), 8..12);
+	// padding is left free
+	assert_eq!(alloc.free_len(), 5);
+	assert_eq!(alloc.alloc(2), 3..5);
+	assert_eq!(alloc.alloc_aligned(2, 4), 12..14);
+
+	// freeing the end shrinks the buffer past padding
+	alloc.free(12..14);
+	alloc.free(8..12);
+	assert_eq!(alloc.len(), 5);
+	assert_eq!(alloc.free_len(), 0);
+
+	alloc.free(0..3);
+	assert_eq!(alloc.alloc_aligned(2, 2), 0..2);
+	assert_eq!(alloc.free_len(), 1);
+
+	assert_eq!(align_up(0, 16), 0);
+	assert_eq!(align_up(17, 16), 32);
+}
+
+#[test]
+fn test_allocated() {
+	let mut alloc = RangeAllocator::new();
+	assert_eq!(alloc.allocated().count(), 0);
+
+	for _ in 0..4 {
+		alloc.alloc(4);
+	}
+
+	alloc.free(0..4);
+	alloc.free(8..12);
+	assert_eq!(alloc.allocated().collect::<Vec<_>>(), [4..8, 12..16]);
+
+	alloc.free(12..16);
+	alloc.alloc(2);
+	assert_eq!(alloc.allocated().collect::<Vec<_>>(), [0..2, 4..8]);
+}
@@ -4,58 +4,402 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/./
 
-use gl::types::GLenum;
+use std::ffi::c_void;
 
-#[derive(Debug)]
+use gl::types::{GLenum, GLintptr, GLsizei, GLuint};
+
+use crate::GlCapabilities;
+
+#[cfg(test)]
+mod test;
+
+/// Layout of a vertex shader input in interleaved vertex data
+///
+/// Matrices are stored as consecutive columns, each read from its own location.
+#[derive(Copy, Clone, Debug)]
 pub struct VertexAttribute {
 	pub ty: GLenum,
+	/// Components read from each location
 	pub count: usize,
-	pub ty_size: usize,
+	/// Size of the data read from each location, in bytes
+	pub column_size: usize,
+	/// Number of matrix columns, or 1 for scalars and vectors
+	pub columns: usize,
 	pub padding: usize,
-	pub is_integer: bool,
+	pub kind: AttributeKind,
+}
+
+/// How a vertex shader input reads its data
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AttributeKind {
+	/// Read as `float`/`vec`, converting integers to floats by value
+	Float,
+	/// Integers read as `float`/`vec`, mapped to `[0, 1]`, or `[-1, 1]` if signed
+	Normalized,
+	/// Read as `int`/`uint`/`ivec`/`uvec` with `glVertexAttribIPointer`
+	Integer,
+	/// Read as `double`/`dvec` with `glVertexAttribLPointer`
+	Double,
 }
 
 impl VertexAttribute {
+	/// Attribute of `count` consecutive values of `T`, after `padding` bytes
 	pub const fn new_padded<T: GLtype>(padding: usize, count: usize) -> Self {
 		VertexAttribute {
 			ty: T::GL_TYPE,
-			count,
-			ty_size: std::mem::size_of::<T>(),
+			count: count * T::COMPONENTS,
+			column_size: count * std::mem::size_of::<T>(),
+			columns: 1,
 			padding,
-			is_integer: T::IS_INTEGER,
+			kind: T::KIND,
 		}
 	}
 
 	pub const fn new<T: GLtype>(count: usize) -> Self {
 		Self::new_padded::<T>(0, count)
 	}
+
+	/// Column major matrix attribute of `columns` columns of `rows` values of `T`
+	pub const fn matrix<T: GLtype>(columns: usize, rows: usize) -> Self {
+		Self {
+			columns,
+			..Self::new::<T>(rows)
+		}
+	}
+
+	pub const fn with_kind(self, kind: AttributeKind) -> Self {
+		Self { kind, ..self }
+	}
+
+	/// Read integers as normalized floats
+	pub const fn normalized(self) -> Self {
+		self.with_kind(AttributeKind::Normalized)
+	}
+
+	/// Size of the attribute's data, not including padding
+	pub const fn size(&self) -> usize {
+		self.column_size * self.columns
+	}
+
+	/// Number of locations the attribute occupies
+	///
+	/// `dvec3` and `dvec4` take two locations per column.
+	pub const fn locations(&self) -> usize {
+		match matches!(self.kind, AttributeKind::Double) && self.count > 2 {
+			true => self.columns * 2,
+			false => self.columns,
+		}
+	}
+
+	/// Check if the context can read this attribute
+	pub fn is_supported(&self, capabilities: &GlCapabilities) -> bool {
+		let packed = matches!(self.ty, gl::INT_2_10_10_10_REV | gl::UNSIGNED_INT_2_10_10_10_REV);
+
+		(self.kind != AttributeKind::Double || capabilities.has_vertex_attrib_64bit())
+			&& (!packed || capabilities.has_vertex_type_2_10_10_10_rev())
+			&& (self.ty != gl::HALF_FLOAT || capabilities.is_gl(3, 0))
+	}
+}
+
+/// Number of locations used by consecutive attributes
+pub fn location_count<'a>(attributes: impl IntoIterator<Item = &'a VertexAttribute>) -> GLuint {
+	attributes.into_iter().map(|a| a.locations() as GLuint).sum()
 }
 
 pub trait GLtype: Sized {
 	const GL_TYPE: GLenum;
-	const IS_INTEGER: bool;
+	/// How attributes of this type are read unless set otherwise
+	const KIND: AttributeKind;
+	/// Number of components packed into each value
+	const COMPONENTS: usize = 1;
 }
 
 macro_rules! gl_types {
-	($($type:ident($gltype:expr, int: $int:literal);)*) => {
+	($($type:ident($gltype:expr, $kind:ident);)*) => {
 		$(
 			impl GLtype for $type {
 				const GL_TYPE: GLenum = $gltype;
-				const IS_INTEGER: bool = $int;
+				const KIND: AttributeKind = AttributeKind::$kind;
 			}
 		)*
 	}
 }
 
 gl_types! {
-	f64(gl::DOUBLE, int: false);
-	f32(gl::FLOAT, int: false);
+	f64(gl::DOUBLE, Double);
+	f32(gl::FLOAT, Float);
+	Half(gl::HALF_FLOAT, Float);
+
+	u32(gl::UNSIGNED_INT, Integer);
+	u16(gl::UNSIGNED_SHORT, Integer);
+	u8(gl::UNSIGNED_BYTE, Integer);
+
+	i32(gl::INT, Integer);
+	i16(gl::SHORT, Integer);
+	i8(gl::BYTE, Integer);
+}
+
+/// IEEE 754 half precision float, read as `GL_HALF_FLOAT`
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(transparent)]
+pub struct Half(pub u16);
+
+impl Half {
+	/// Convert `value`, rounding to the nearest half
+	pub fn from_f32(value: f32) -> Self {
+		let bits = value.to_bits();
+		let sign = ((bits >> 16) & 0x8000) as u16;
+		let exponent = ((bits >> 23) & 0xff) as i32;
+		let mantissa = bits & 0x7f_ffff;
+
+		if exponent == 0xff {
+			// infinity, or NaN kept quiet
+			let nan = if mantissa != 0 { 0x200 } else { 0 };
+			return Self(sign | 0x7c00 | nan);
+		}
+
+		let exponent = exponent - 127 + 15;
+
+		if exponent >= 0x1f {
+			return Self(sign | 0x7c00);
+		}
+
+		// subnormal halves shift the implicit leading bit into the mantissa
+		let (value, shift) = match exponent > 0 {
+			true => (((exponent as u32) << 23) | mantissa, 13),
+			false if exponent < -10 => return Self(sign),
+			false => (mantissa | 0x80_0000, (14 - exponent) as u32),
+		};
+
+		// round to nearest, ties to even, carrying into the exponent if needed
+		let remainder = value & ((1 << shift) - 1);
+		let halfway = 1 << (shift - 1);
+		let mut half = value >> shift;
+		if remainder > halfway || (remainder == halfway && half & 1 == 1) {
+			half += 1;
+		}
+
+		Self(sign | half as u16)
+	}
+
+	pub fn to_f32(self) -> f32 {
+		let sign = ((self.0 & 0x8000) as u32) << 16;
+		let exponent = ((self.0 >> 10) & 0x1f) as u32;
+		let mantissa = (self.0 & 0x3ff) as u32;
+
+		let bits = match exponent {
+			0 => (mantissa as f32 / (1 << 24) as f32).to_bits(),
+			0x1f => 0x7f80_0000 | (mantissa << 13),
+			_ => ((exponent + 127 - 15) << 23) | (mantissa << 13),
+		};
+
+		f32::from_bits(sign | bits)
+	}
+}
+
+impl From<f32> for Half {
+	fn from(value: f32) -> Self {
+		Self::from_f32(value)
+	}
+}
 
-	u32(gl::UNSIGNED_INT, int: true);
-	u16(gl::UNSIGNED_SHORT, int: true);
-	u8(gl::UNSIGNED_BYTE, int: true);
+impl From<Half> for f32 {
+	fn from(value: Half) -> Self {
+		value.to_f32()
+	}
+}
+
+macro_rules! packed_types {
+	($($(#[$attr:meta])* $name:ident($gltype:expr, $component:ident, $w:ident);)*) => {
+		$(
+			$(#[$attr])*
+			#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, bytemuck::Pod, bytemuck::Zeroable)]
+			#[repr(transparent)]
+			pub struct $name(pub u32);
+
+			impl $name {
+				/// Pack components, keeping only their low 10 bits, or 2 bits for `w`
+				pub const fn new(x: $component, y: $component, z: $component, w: $w) -> Self {
+					Self(
+						(x as u32 & 0x3ff)
+							| (y as u32 & 0x3ff) << 10
+							| (z as u32 & 0x3ff) << 20
+							| (w as u32 & 0x3) << 30,
+					)
+				}
+
+				/// Pack normalized components, clamped to the representable range
+				pub fn from_normalized(x: f32, y: f32, z: f32, w: f32) -> Self {
+					let (min, max) = match $gltype {
+						gl::INT_2_10_10_10_REV => (-1.0, 1.0),
+						_ => (0.0, 1.0),
+					};
+					let scale = |v: f32, bits: u32| {
+						let max_value = match $gltype {
+							gl::INT_2_10_10_10_REV => (1 << (bits - 1)) - 1,
+							_ => (1 << bits) - 1,
+						};
+						(v.clamp(min, max) * max_value as f32).round()
+					};
+
+					Self::new(
+						scale(x, 10) as $component,
+						scale(y, 10) as $component,
+						scale(z, 10) as $component,
+						scale(w, 2) as $w,
+					)
+				}
+			}
+
+			impl GLtype for $name {
+				const GL_TYPE: GLenum = $gltype;
+				const KIND: AttributeKind = AttributeKind::Normalized;
+				const COMPONENTS: usize = 4;
+			}
+		)*
+	}
+}
+
+packed_types! {
+	/// Signed 10 bit `x`, `y` and `z` and 2 bit `w` packed into a `u32`,
+	/// read as `GL_INT_2_10_10_10_REV`
+	Int2_10_10_10Rev(gl::INT_2_10_10_10_REV, i16, i8);
+	/// Unsigned 10 bit `x`, `y` and `z` and 2 bit `w` packed into a `u32`,
+	/// read as `GL_UNSIGNED_INT_2_10_10_10_REV`
+	UInt2_10_10_10Rev(gl::UNSIGNED_INT_2_10_10_10_REV, u16, u8);
+}
+
+/// Create a vertex array object
+///
+/// # SAFETY
+/// * must be called from GL thread
+pub unsafe fn create_vertex_array() -> GLuint {
+	let mut vao = 0;
+
+	match GlCapabilities::get().has_direct_state_access() {
+		true => gl::CreateVertexArrays(1, &mut vao),
+		false => gl::GenVertexArrays(1, &mut vao),
+	}
+
+	vao
+}
+
+/// Point attributes at consecutive locations starting at `first_location`
+/// to interleaved data starting `offset` bytes into `buffer`
+///
+/// With DSA, `buffer` is attached to `vao` at `binding` without changing any
+/// binding state. Otherwise `vao` and `buffer` are left bound.
+///
+/// # SAFETY
+/// * must be called from GL thread
+pub unsafe fn set_vertex_attributes<'a>(
+	vao: GLuint,
+	buffer: GLuint,
+	offset: usize,
+	binding: GLuint,
+	attributes: impl Iterator<Item = &'a VertexAttribute> + Clone,
+	first_location: GLuint,
+	divisor: GLuint,
+) {
+	let stride = attributes.clone().map(|a| a.padding + a.size()).sum::<usize>() as GLsizei;
+
+	let dsa = GlCapabilities::get().has_direct_state_access();
 
-	i32(gl::INT, int: true);
-	i16(gl::SHORT, int: true);
-	i8(gl::BYTE, int: true);
+	// divisors of new vertex arrays are already 0
+	if dsa {
+		gl::VertexArrayVertexBuffer(vao, binding, buffer, offset as GLintptr, stride);
+
+		if divisor != 0 {
+			gl::VertexArrayBindingDivisor(vao, binding, divisor);
+		}
+	} else {
+		gl::BindVertexArray(vao);
+		gl::BindBuffer(gl::ARRAY_BUFFER, buffer);
+	}
+
+	let mut location = first_location;
+	let mut attribute_offset = 0;
+	for attribute in attributes {
+		attribute_offset += attribute.padding;
+
+		// each matrix column is a separate attribute at the next free location
+		let column_locations = (attribute.locations() / attribute.columns) as GLuint;
+		for _ in 0..attribute.columns {
+			set_attribute_format(vao, location, attribute, offset, attribute_offset, stride, dsa);
+
+			if dsa {
+				gl::VertexArrayAttribBinding(vao, location, binding);
+				gl::EnableVertexArrayAttrib(vao, location);
+			} else {
+				if divisor != 0 {
+					gl::VertexAttribDivisor(location, divisor);
+				}
+
+				gl::EnableVertexAttribArray(location);
+			}
+
+			location += column_locations;
+			attribute_offset += attribute.column_size;
+		}
+	}
+}
+
+/// Set the format of one column of `attribute`, `relative_offset` bytes into
+/// each vertex of data starting `offset` bytes into the buffer
+///
+/// # SAFETY
+/// * must be called from GL thread
+unsafe fn set_attribute_format(
+	vao: GLuint,
+	location: GLuint,
+	attribute: &VertexAttribute,
+	offset: usize,
+	relative_offset: usize,
+	stride: GLsizei,
+	dsa: bool,
+) {
+	let count = attribute.count as GLsizei;
+	let ty = attribute.ty;
+
+	if dsa {
+		let offset = relative_offset as GLuint;
+
+		match attribute.kind {
+			AttributeKind::Float =>
+				gl::VertexArrayAttribFormat(vao, location, count, ty, gl::FALSE, offset),
+			AttributeKind::Normalized =>
+				gl::VertexArrayAttribFormat(vao, location, count, ty, gl::TRUE, offset),
+			AttributeKind::Integer =>
+				gl::VertexArrayAttribIFormat(vao, location, count, ty, offset),
+			AttributeKind::Double => gl::VertexArrayAttribLFormat(vao, location, count, ty, offset),
+		}
+	} else {
+		let pointer = (offset + relative_offset) as *const c_void;
+
+		match attribute.kind {
+			AttributeKind::Float =>
+				gl::VertexAttribPointer(location, count, ty, gl::FALSE, stride, pointer),
+			AttributeKind::Normalized =>
+				gl::VertexAttribPointer(location, count, ty, gl::TRUE, stride, pointer),
+			AttributeKind::Integer =>
+				gl::VertexAttribIPointer(location, count, ty, stride, pointer),
+			AttributeKind::Double => gl::VertexAttribLPointer(location, count, ty, stride, pointer),
+		}
+	}
+}
+
+/// Attach an element buffer to `vao`
+///
+/// Without DSA, `vao` and `buffer` are left bound.
+///
+/// # SAFETY
+/// * must be called from GL thread
+pub unsafe fn set_element_buffer(vao: GLuint, buffer: GLuint) {
+	match GlCapabilities::get().has_direct_state_access() {
+		true => gl::VertexArrayElementBuffer(vao, buffer),
+		false => {
+			gl::BindVertexArray(vao);
+			gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, buffer);
+		},
+	}
 }
@@ -0,0 +1,94 @@
+use super::{
+	location_count,
+	set_vertex_attributes,
+	AttributeKind,
+	Half,
+	Int2_10_10_10Rev,
+	UInt2_10_10_10Rev,
+	VertexAttribute,
+};
+use crate::mock_gl;
+
+#[test]
+fn test_half_conversion() {
+	assert_eq!(Half::from_f32(1.0), Half(0x3c00));
+	assert_eq!(Half::from_f32(-2.0), Half(0xc000));
+	assert_eq!(Half::from_f32(0.1), Half(0x2e66));
+	assert_eq!(Half::from_f32(65504.0), Half(0x7bff));
+	// rounds up past the largest half
+	assert_eq!(Half::from_f32(65520.0), Half(0x7c00));
+	assert_eq!(Half::from_f32(f32::NEG_INFINITY), Half(0xfc00));
+	assert_eq!(Half::from_f32(2f32.powi(-24)), Half(0x0001));
+	assert_eq!(Half::from_f32(2f32.powi(-26)), Half(0x0000));
+	// ties round to even
+	assert_eq!(Half::from_f32(1.0 + 2f32.powi(-11)), Half(0x3c00));
+	assert_eq!(Half::from_f32(1.0 + 3.0 * 2f32.powi(-11)), Half(0x3c02));
+	assert!(Half::from_f32(f32::NAN).to_f32().is_nan());
+
+	for value in [0.0, -0.5, 1.5, 2f32.powi(-20), 1024.0, f32::INFINITY] {
+		assert_eq!(Half::from_f32(value).to_f32(), value);
+	}
+}
+
+#[test]
+fn test_packed() {
+	assert_eq!(
+		Int2_10_10_10Rev::from_normalized(1.0, -1.0, 0.0, 1.0),
+		Int2_10_10_10Rev(0x1ff | 0x201 << 10 | 1 << 30),
+	);
+	assert_eq!(
+		UInt2_10_10_10Rev::from_normalized(1.0, 0.0, 2.0, -1.0),
+		UInt2_10_10_10Rev(0x3ff | 0x3ff << 20),
+	);
+
+	let attribute = VertexAttribute::new::<Int2_10_10_10Rev>(1);
+	assert_eq!((attribute.count, attribute.size()), (4, 4));
+	assert_eq!(attribute.kind, AttributeKind::Normalized);
+}
+
+#[test]
+fn test_locations() {
+	let mat4 = VertexAttribute::matrix::<f32>(4, 4);
+	assert_eq!((mat4.count, mat4.size(), mat4.locations()), (4, 64, 4));
+
+	// dvec3 and dvec4 columns take two locations
+	assert_eq!(VertexAttribute::matrix::<f64>(4, 4).locations(), 8);
+	assert_eq!(VertexAttribute::matrix::<f64>(3, 2).locations(), 3);
+	assert_eq!(VertexAttribute::new::<f64>(3).locations(), 2);
+	assert_eq!(VertexAttribute::new::<f32>(3).locations(), 1);
+}
+
+#[test]
+fn test_set_vertex_attributes() {
+	mock_gl::install(3, 3);
+
+	let attributes = [
+		VertexAttribute::new::<f32>(2),
+		VertexAttribute::new::<u8>(4).normalized(),
+		VertexAttribute::matrix::<f32>(2, 2),
+		VertexAttribute::new::<f64>(3),
+		VertexAttribute::new::<Int2_10_10_10Rev>(1),
+		VertexAttribute::new_padded::<u32>(4, 1),
+	];
+	assert_eq!(location_count(&attributes), 8);
+
+	unsafe { set_vertex_attributes(1, 2, 16, 0, attributes.iter(), 1, 0) };
+
+	let calls = mock_gl::take_calls();
+	let formats = calls.iter().filter(|call| call.contains("Pointer")).collect::<Vec<_>>();
+
+	assert_eq!(calls[..2], ["BindVertexArray(1)", "BindBuffer(ARRAY_BUFFER, 2)"]);
+	assert_eq!(formats, [
+		"VertexAttribPointer(1, 2, FLOAT, 0, 64, 16)",
+		"VertexAttribPointer(2, 4, UNSIGNED_BYTE, 1, 64, 24)",
+		"VertexAttribPointer(3, 2, FLOAT, 0, 64, 28)",
+		"VertexAttribPointer(4, 2, FLOAT, 0, 64, 36)",
+		"VertexAttribLPointer(5, 3, DOUBLE, 64, 44)",
+		"VertexAttribPointer(7, 4, INT_2_10_10_10_REV, 1, 64, 68)",
+		"VertexAttribIPointer(8, 1, UNSIGNED_INT, 64, 76)",
+	]);
+	assert_eq!(
+		calls.iter().filter(|call| call.starts_with("EnableVertexAttribArray")).count(),
+		7
+	);
+}
@@ -0,0 +1,133 @@
+// Copyright (C) 2022 the ITK authors
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/./
+
+use std::{borrow::Cow, ffi::c_void, ops::Range};
+
+use gl::types::{GLint, GLsizei};
+
+use super::index::{self, IndexBuffer, RESTART_INDEX};
+use crate::drawable::Drawable;
+
+/// Draw calls of drawables batched into a single vertex and index buffer
+///
+/// * indexed drawables are drawn with one `glDrawElements`, with strips
+///   separated by primitive restart
+/// * non-indexed drawables are drawn with one `glDrawArrays`, or one
+///   `glMultiDrawArrays` per drawable range for strips
+pub(super) struct DrawBatch {
+	/// Only used for non-indexed strips
+	array_firsts: Vec<GLint>,
+	array_counts: Vec<GLsizei>,
+}
+
+impl DrawBatch {
+	pub fn new() -> Self {
+		Self {
+			array_firsts: Vec::new(),
+			array_counts: Vec::new(),
+		}
+	}
+
+	/// Record a drawable whose vertices were written to `vertices`,
+	/// writing its indices rebased to them into `index_buffer`
+	///
+	/// # SAFETY
+	/// * must have previously called `prepare_write` on `index_buffer`
+	pub unsafe fn write<D: Drawable>(
+		&mut self,
+		index_buffer: &mut IndexBuffer,
+		vertices: Range<usize>,
+		indices: impl IntoIterator<IntoIter = impl ExactSizeIterator<Item = u32>>,
+	) {
+		if !D::INDEXED {
+			if D::IS_STRIP {
+				self.array_firsts.push(vertices.start as GLint);
+				self.array_counts.push(vertices.len() as GLsizei);
+			}
+
+			return
+		}
+
+		index_buffer.fit_vertices(vertices.end);
+		let mut index_buffer_len = index_buffer.len();
+
+		if D::IS_STRIP && index_buffer_len != 0 {
+			index_buffer.write(index_buffer_len, [RESTART_INDEX]);
+			index_buffer_len += 1;
+		}
+
+		let indices = indices.into_iter().map(|i| vertices.start as u32 + i);
+		index_buffer.write(index_buffer_len, indices);
+	}
+
+	/// Draw every recorded drawable, returning the number of vertices
+	/// or indices drawn
+	///
+	/// `base_vertex` is added to every vertex index, see `buffer::base_element`.
+	///
+	/// # SAFETY
+	/// * must be called from GL thread
+	/// * the VAO of the batch's buffers must be bound
+	pub unsafe fn draw<D: Drawable>(
+		&self,
+		index_buffer: &IndexBuffer,
+		vertex_count: usize,
+		base_vertex: GLint,
+	) -> usize {
+		match (D::INDEXED, D::IS_STRIP) {
+			(true, is_strip) => {
+				let index_type = index_buffer.index_type();
+
+				if is_strip {
+					index::enable_primitive_restart(index_type);
+				}
+
+				let count = index_buffer.len() as GLsizei;
+				let indices = index_buffer.backing_offset() as *const c_void;
+
+				match base_vertex {
+					0 => gl::DrawElements(D::GL_TYPE, count, index_type.gl_type(), indices),
+					_ => gl::DrawElementsBaseVertex(
+						D::GL_TYPE,
+						count,
+						index_type.gl_type(),
+						indices,
+						base_vertex,
+					),
+				}
+
+				if is_strip {
+					index::disable_primitive_restart();
+				}
+
+				index_buffer.len()
+			},
+			(false, false) => {
+				gl::DrawArrays(D::GL_TYPE, base_vertex, vertex_count as GLsizei);
+				vertex_count
+			},
+			(false, true) => {
+				let firsts = match base_vertex {
+					0 => Cow::Borrowed(&self.array_firsts[..]),
+					_ => self.array_firsts.iter().map(|first| first + base_vertex).collect(),
+				};
+
+				gl::MultiDrawArrays(
+					D::GL_TYPE,
+					firsts.as_ptr(),
+					self.array_counts.as_ptr(),
+					self.array_firsts.len() as GLsizei,
+				);
+				vertex_count
+			},
+		}
+	}
+
+	pub fn clear(&mut self) {
+		self.array_firsts.clear();
+		self.array_counts.clear();
+	}
+}
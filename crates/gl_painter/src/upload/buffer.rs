@@ -4,12 +4,25 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/./
 
-use std::ops::Range;
+use std::{mem, rc::Rc};
 
-use gl::types::{GLenum, GLuint};
+use gl::types::{GLenum, GLint, GLintptr, GLsizeiptr, GLuint};
 
+use self::{
+	dirty::DirtyRanges,
+	growth::{BufferStats, GrowthPolicy},
+	readback::Readback,
+};
+use super::sync::Fence;
+use crate::GlCapabilities;
+
+pub mod arena;
 pub mod compat;
+pub mod dirty;
+pub mod dsa;
+pub mod growth;
 pub mod persistent;
+pub mod readback;
 
 /// Buffer of data to upload to the GPU
 ///
@@ -40,8 +53,8 @@ pub trait GpuBuffer<T: bytemuck::AnyBitPattern> {
 	/// # SAFETY
 	/// * must be called from GL thread
 	///
-	///	# SIDE EFFECTS
-	///	* may or may not bind this buffer
+	/// # SIDE EFFECTS
+	/// * may or may not bind this buffer, except for `DsaBuffer`
 	unsafe fn begin_flush(&mut self);
 	/// Wait for flush to complete
 	///
@@ -64,22 +77,118 @@ pub trait GpuBuffer<T: bytemuck::AnyBitPattern> {
 	fn backing_buffer_changed(&self) -> bool;
 	/// Clear buffer changed flag
 	fn clear_buffer_changed(&mut self);
+	/// Mark the end of the GPU commands reading this buffer's current data
+	///
+	/// Buffers writing directly into memory read by the GPU keep `fence`
+	/// so they never overwrite data still in use. Others ignore it, as
+	/// the driver synchronizes their uploads.
+	///
+	/// # SAFETY
+	/// * must be called from GL thread
+	unsafe fn finish_use(&mut self, _fence: &Rc<Fence>) {}
+	/// Set the number of frames that may be in flight while this buffer is written
+	fn set_frame_latency(&mut self, _frames: usize) {}
+	/// Set how the backing buffer grows and shrinks
+	///
+	/// Takes effect on the next `begin_flush`.
+	fn set_growth_policy(&mut self, policy: GrowthPolicy);
+	/// Shrink the backing buffer to this buffer's length on the next `begin_flush`
+	fn shrink_to_fit(&mut self);
+	/// Get allocation statistics of the backing buffer
+	fn stats(&self) -> BufferStats;
 	fn backing_buffer(&self) -> GLuint;
+	/// Offset in bytes of this buffer's data within the backing buffer
+	///
+	/// Only buffers sharing a backing buffer, such as `ArenaBuffer`, or
+	/// rings such as `PersistentBuffer` have a nonzero offset. A ring's
+	/// offset moves every frame without changing its backing buffer.
+	fn backing_offset(&self) -> usize {
+		0
+	}
+	/// Bind this buffer's range of the backing buffer to an indexed target
+	///
+	/// Empty buffers bind the whole backing buffer.
+	///
+	/// # SAFETY
+	/// * must be called from GL thread
+	/// * must be flushed
+	unsafe fn bind_range(&self, target: GLenum, index: GLuint) {
+		match self.len() {
+			0 => gl::BindBufferBase(target, index, self.backing_buffer()),
+			len => gl::BindBufferRange(
+				target,
+				index,
+				self.backing_buffer(),
+				self.backing_offset() as GLintptr,
+				(len * mem::size_of::<T>()) as GLsizeiptr,
+			),
+		}
+	}
+	/// Read this buffer's data back from the GPU, blocking until it is available
+	///
+	/// Writes by shaders must be made visible first with
+	/// `glMemoryBarrier(GL_BUFFER_UPDATE_BARRIER_BIT)`.
+	///
+	/// # SAFETY
+	/// * must be called from GL thread
+	/// * must be flushed
+	unsafe fn read(&self) -> Vec<T> {
+		readback::read_gl_buffer(self.backing_buffer(), self.backing_offset(), self.len())
+	}
+	/// Start reading this buffer's data back from the GPU, without blocking
+	///
+	/// The data is a snapshot of the buffer at the time of the call.
+	/// See `read` for shader writes.
+	///
+	/// # SAFETY
+	/// * must be called from GL thread
+	/// * must be flushed
+	unsafe fn read_async(&self) -> Readback<T> {
+		Readback::new(self.backing_buffer(), self.backing_offset(), self.len())
+	}
 }
 
 /// Create a new GpuBuffer
 ///
 /// does NOT bind new buffer
+///
+/// `PersistentBuffer` is preferred as it is written directly by the CPU,
+/// followed by `DsaBuffer`, whose flushes never disturb binding state,
+/// then `CompatBuffer`.
+///
+/// # NOTES
+/// Must be called from the GL thread.
 pub fn new<T: bytemuck::AnyBitPattern>(buffer_type: GLenum) -> Box<dyn GpuBuffer<T>> {
-	match crate::check_gl_extensions().arb_buffer_storage {
-		true => Box::new(persistent::PersistentBuffer::new(buffer_type)),
-		false => Box::new(compat::CompatBuffer::new(buffer_type)),
+	let capabilities = unsafe { GlCapabilities::get() };
+
+	if capabilities.has_buffer_storage() {
+		Box::new(persistent::PersistentBuffer::new(buffer_type))
+	} else if capabilities.has_direct_state_access() {
+		Box::new(dsa::DsaBuffer::new(buffer_type))
+	} else {
+		Box::new(compat::CompatBuffer::new(buffer_type))
 	}
 }
 
+/// Offset in bytes to specify vertex attributes reading `buffer` at
+///
+/// Unlike `backing_offset`, this stays the same while the backing buffer
+/// does. Draw with `base_element` as the base vertex or instance to read
+/// the buffer's current data.
+pub fn attribute_offset<T: bytemuck::AnyBitPattern>(
+	buffer: &(impl GpuBuffer<T> + ?Sized),
+) -> usize {
+	buffer.backing_offset() % mem::size_of::<T>()
+}
+
+/// Index of `buffer`'s first element, relative to `attribute_offset`
+pub fn base_element<T: bytemuck::AnyBitPattern>(buffer: &(impl GpuBuffer<T> + ?Sized)) -> GLint {
+	(buffer.backing_offset() / mem::size_of::<T>()) as GLint
+}
+
 pub struct CpuBacker<T: bytemuck::AnyBitPattern> {
 	buffer: Vec<T>,
-	modified_range: Option<Range<usize>>,
+	modified: DirtyRanges,
 	reallocated: bool,
 }
 
@@ -87,11 +196,22 @@ impl<T: bytemuck::AnyBitPattern> CpuBacker<T> {
 	pub fn new() -> Self {
 		Self {
 			buffer: Vec::new(),
-			modified_range: None,
+			modified: DirtyRanges::default(),
 			reallocated: false,
 		}
 	}
 
+	/// Set the gap, in elements, below which modified ranges are merged
+	/// into a single upload
+	pub fn set_merge_gap(&mut self, merge_gap: usize) {
+		self.modified.set_merge_gap(merge_gap);
+	}
+
+	/// Get the current contents of the buffer
+	pub fn data(&self) -> &[T] {
+		&self.buffer
+	}
+
 	#[inline(always)]
 	pub fn write(
 		&mut self,
@@ -110,10 +230,6 @@ impl<T: bytemuck::AnyBitPattern> CpuBacker<T> {
 			iter.enumerate().for_each(|(i, v)| self.buffer[offset + i] = v);
 		}
 
-		self.modified_range = Some(match &self.modified_range {
-			Some(range) =>
-				usize::min(range.start, offset)..usize::max(range.end, offset + count as usize),
-			None => offset..offset + count,
-		});
+		self.modified.insert(offset..offset + count);
 	}
 }
@@ -0,0 +1,480 @@
+// Copyright (C) 2022 the ITK authors
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/./
+
+//! Sub-allocation of GPU buffers from a few large GL buffers
+
+use std::{cell::RefCell, ffi::c_void, mem, ops::Range, ptr, rc::Rc};
+
+use gl::types::{GLenum, GLintptr, GLsizeiptr, GLuint};
+
+use super::{
+	growth::{BufferCapacity, BufferStats, GrowthPolicy},
+	CpuBacker,
+	GpuBuffer,
+};
+use crate::{upload::alloc::RangeAllocator, GlCapabilities};
+
+/// Default size in bytes of the GL buffers created by a `BufferArena`
+pub const DEFAULT_BLOCK_SIZE: usize = 4 << 20;
+
+/// Minimum alignment in bytes of allocations, enough for any vertex or index type
+const MIN_ALIGNMENT: usize = 16;
+
+/// Arena carving `ArenaBuffer`s out of a few large GL buffers
+///
+/// Allocations are aligned to `GL_SHADER_STORAGE_BUFFER_OFFSET_ALIGNMENT`,
+/// so arena buffers can be bound as storage buffers with `GpuBuffer::bind_range`.
+/// Allocations larger than the block size get a block of their own.
+///
+/// Arenas are cheap to clone, and clones share the same GL buffers.
+/// GL buffers are deleted once the arena and all of its buffers are dropped.
+///
+/// Without DSA, the arena uses the `COPY_READ_BUFFER` and `COPY_WRITE_BUFFER`
+/// targets, which are not part of any VAO's state.
+#[derive(Clone)]
+pub struct BufferArena {
+	inner: Rc<RefCell<ArenaInner>>,
+}
+
+struct ArenaInner {
+	block_size: usize,
+	alignment: usize,
+	dsa: bool,
+	blocks: Vec<Block>,
+	/// Indexed by buffer id, `None` if the buffer has no allocation
+	allocations: Vec<Option<Allocation>>,
+	free_ids: Vec<usize>,
+}
+
+struct Block {
+	gl_buffer: GLuint,
+	size: usize,
+	allocator: RangeAllocator,
+}
+
+struct Allocation {
+	block: usize,
+	/// In bytes, the capacity of the buffer using it
+	range: Range<usize>,
+	/// Set when the allocation moves, cleared by `GpuBuffer::clear_buffer_changed`
+	changed: bool,
+}
+
+/// GPU buffer allocated from a `BufferArena`
+///
+/// The buffer's data starts `GpuBuffer::backing_offset` bytes into
+/// its backing buffer, which is shared with other buffers of the arena.
+pub struct ArenaBuffer<T: bytemuck::AnyBitPattern> {
+	arena: Rc<RefCell<ArenaInner>>,
+	id: usize,
+	buffer_type: GLenum,
+	backer: CpuBacker<T>,
+	capacity: BufferCapacity,
+}
+
+impl BufferArena {
+	/// Create an arena with `DEFAULT_BLOCK_SIZE` blocks
+	///
+	/// # SAFETY
+	/// * must be called from GL thread
+	pub unsafe fn new() -> Self {
+		Self::with_block_size(DEFAULT_BLOCK_SIZE)
+	}
+
+	/// Create an arena allocating GL buffers of `block_size` bytes
+	///
+	/// # SAFETY
+	/// * must be called from GL thread
+	pub unsafe fn with_block_size(block_size: usize) -> Self {
+		let capabilities = GlCapabilities::get();
+		let storage_alignment = capabilities.limits.shader_storage_buffer_offset_alignment;
+
+		Self {
+			inner: Rc::new(RefCell::new(ArenaInner {
+				block_size,
+				alignment: (storage_alignment as usize).max(MIN_ALIGNMENT),
+				dsa: capabilities.has_direct_state_access(),
+				blocks: Vec::new(),
+				allocations: Vec::new(),
+				free_ids: Vec::new(),
+			})),
+		}
+	}
+
+	/// Create a buffer in this arena
+	///
+	/// No space is allocated until the buffer is flushed.
+	pub fn buffer<T: bytemuck::AnyBitPattern>(&self, buffer_type: GLenum) -> ArenaBuffer<T> {
+		let mut inner = self.inner.borrow_mut();
+
+		let id = match inner.free_ids.pop() {
+			Some(id) => id,
+			None => {
+				inner.allocations.push(None);
+				inner.allocations.len() - 1
+			},
+		};
+
+		ArenaBuffer {
+			arena: self.inner.clone(),
+			id,
+			buffer_type,
+			backer: CpuBacker::new(),
+			capacity: BufferCapacity::new(),
+		}
+	}
+
+	/// Number of GL buffers allocated by this arena
+	pub fn block_count(&self) -> usize {
+		self.inner.borrow().blocks.len()
+	}
+
+	/// Number of unallocated bytes in this arena's GL buffers
+	pub fn free_bytes(&self) -> usize {
+		self.inner
+			.borrow()
+			.blocks
+			.iter()
+			.map(|b| b.size - b.allocator.len() + b.allocator.free_len())
+			.sum()
+	}
+
+	/// Move every allocation to the start of its GL buffer,
+	/// and delete GL buffers without allocations
+	///
+	/// Moved buffers report a changed backing buffer, so uploaders using them
+	/// update their VAOs on the next bind.
+	///
+	/// # SAFETY
+	/// * must be called from GL thread
+	pub unsafe fn defragment(&self) {
+		self.inner.borrow_mut().defragment();
+	}
+}
+
+impl ArenaInner {
+	/// Allocate `size` bytes, creating a new block if no block has room
+	///
+	/// # SAFETY
+	/// * must be called from GL thread
+	unsafe fn alloc(&mut self, size: usize) -> (usize, Range<usize>) {
+		for (i, block) in self.blocks.iter_mut().enumerate() {
+			let range = block.allocator.alloc_aligned(size, self.alignment);
+
+			if range.end <= block.size {
+				return (i, range)
+			}
+
+			block.allocator.free(range);
+		}
+
+		let block_size = self.block_size.max(size);
+		let mut allocator = RangeAllocator::new();
+		let range = allocator.alloc(size);
+
+		self.blocks.push(Block {
+			gl_buffer: create_gl_buffer(block_size, self.dsa),
+			size: block_size,
+			allocator,
+		});
+
+		(self.blocks.len() - 1, range)
+	}
+
+	fn free(&mut self, allocation: Allocation) {
+		self.blocks[allocation.block].allocator.free(allocation.range);
+	}
+
+	/// # SAFETY
+	/// * must be called from GL thread
+	unsafe fn defragment(&mut self) {
+		let old_blocks = mem::take(&mut self.blocks);
+
+		for (old_index, mut block) in old_blocks.into_iter().enumerate() {
+			let mut ids = (0..self.allocations.len())
+				.filter(|&id| self.allocations[id].as_ref().is_some_and(|a| a.block == old_index))
+				.collect::<Vec<_>>();
+
+			if ids.is_empty() {
+				gl::DeleteBuffers(1, &block.gl_buffer);
+				continue
+			}
+
+			let allocation = |id: usize| self.allocations[id].as_ref().unwrap();
+			ids.sort_unstable_by_key(|&id| allocation(id).range.start);
+
+			let mut allocator = RangeAllocator::new();
+			let ranges = ids
+				.iter()
+				.map(|&id| allocator.alloc_aligned(allocation(id).range.len(), self.alignment))
+				.collect::<Vec<_>>();
+
+			let moved = ids.iter().zip(&ranges).any(|(&id, range)| allocation(id).range != *range);
+
+			if moved {
+				let gl_buffer = create_gl_buffer(block.size, self.dsa);
+
+				for (&id, range) in ids.iter().zip(ranges) {
+					let allocation = self.allocations[id].as_mut().unwrap();
+
+					copy_gl_buffer(
+						block.gl_buffer,
+						allocation.range.start,
+						gl_buffer,
+						range.start,
+						range.len(),
+						self.dsa,
+					);
+
+					allocation.range = range;
+					allocation.changed = true;
+				}
+
+				gl::DeleteBuffers(1, &block.gl_buffer);
+				block.gl_buffer = gl_buffer;
+				block.allocator = allocator;
+			}
+
+			// blocks only move towards the start, so never onto a block not yet visited
+			for &id in &ids {
+				self.allocations[id].as_mut().unwrap().block = self.blocks.len();
+			}
+
+			self.blocks.push(block);
+		}
+	}
+}
+
+impl Drop for ArenaInner {
+	fn drop(&mut self) {
+		for block in &self.blocks {
+			unsafe {
+				gl::DeleteBuffers(1, &block.gl_buffer);
+			}
+		}
+	}
+}
+
+impl<T: bytemuck::AnyBitPattern> GpuBuffer<T> for ArenaBuffer<T> {
+	/// Bind the whole backing buffer
+	///
+	/// Use `bind_range` or `backing_offset` to address this buffer's data.
+	unsafe fn bind(&self) {
+		gl::BindBuffer(self.buffer_type, self.backing_buffer());
+	}
+
+	unsafe fn prepare_write(&mut self) {}
+
+	unsafe fn write(&mut self) -> &mut CpuBacker<T> {
+		&mut self.backer
+	}
+
+	unsafe fn begin_flush(&mut self) {
+		// the backer may have shrunk since it was written to
+		self.backer.modified.truncate(self.backer.buffer.len());
+
+		let element_size = mem::size_of::<T>();
+		let size = self.backer.buffer.len() * element_size;
+
+		let mut arena = self.arena.borrow_mut();
+		let arena = &mut *arena;
+
+		if let Some(capacity) = self.capacity.update(self.backer.buffer.len()) {
+			if let Some(allocation) = arena.allocations[self.id].take() {
+				arena.free(allocation);
+			}
+
+			let (block, range) = arena.alloc(capacity * element_size);
+
+			write_gl_buffer(
+				arena.blocks[block].gl_buffer,
+				range.start,
+				self.backer.buffer[..].as_ptr() as *const c_void,
+				size,
+				arena.dsa,
+			);
+			self.capacity.record_upload(size);
+
+			arena.allocations[self.id] = Some(Allocation {
+				block,
+				range,
+				changed: true,
+			});
+		} else if let Some(allocation) = &arena.allocations[self.id] {
+			let gl_buffer = arena.blocks[allocation.block].gl_buffer;
+
+			if self.backer.modified.prefer_whole_upload(self.backer.buffer.len(), element_size) {
+				write_gl_buffer(
+					gl_buffer,
+					allocation.range.start,
+					self.backer.buffer[..].as_ptr() as *const c_void,
+					size,
+					arena.dsa,
+				);
+				self.capacity.record_upload(size);
+			} else {
+				let covered_len = self.backer.modified.covered_len();
+				self.capacity.record_upload(covered_len * element_size);
+
+				for range in self.backer.modified.iter() {
+					write_gl_buffer(
+						gl_buffer,
+						allocation.range.start + range.start * element_size,
+						self.backer.buffer[range.clone()].as_ptr() as *const c_void,
+						range.len() * element_size,
+						arena.dsa,
+					);
+				}
+			}
+		}
+
+		self.backer.modified.clear();
+	}
+
+	unsafe fn sync_flush(&mut self) {}
+
+	fn resize(&mut self, size: usize) {
+		self.backer.buffer.resize(size, T::zeroed());
+	}
+
+	fn len(&self) -> usize {
+		self.backer.buffer.len()
+	}
+
+	fn has_backing_buffer(&self) -> bool {
+		self.arena.borrow().allocations[self.id].is_some()
+	}
+
+	fn backing_buffer_changed(&self) -> bool {
+		self.arena.borrow().allocations[self.id].as_ref().is_some_and(|a| a.changed)
+	}
+
+	fn clear_buffer_changed(&mut self) {
+		if let Some(allocation) = &mut self.arena.borrow_mut().allocations[self.id] {
+			allocation.changed = false;
+		}
+	}
+
+	fn set_growth_policy(&mut self, policy: GrowthPolicy) {
+		self.capacity.set_policy(policy);
+	}
+
+	fn shrink_to_fit(&mut self) {
+		self.capacity.shrink_to_fit();
+	}
+
+	/// `size` is the buffer's allocation within the arena
+	fn stats(&self) -> BufferStats {
+		BufferStats {
+			size: self.capacity.capacity() * mem::size_of::<T>(),
+			..self.capacity.stats()
+		}
+	}
+
+	fn backing_buffer(&self) -> GLuint {
+		let arena = self.arena.borrow();
+
+		match &arena.allocations[self.id] {
+			Some(allocation) => arena.blocks[allocation.block].gl_buffer,
+			None => 0,
+		}
+	}
+
+	fn backing_offset(&self) -> usize {
+		match &self.arena.borrow().allocations[self.id] {
+			Some(allocation) => allocation.range.start,
+			None => 0,
+		}
+	}
+}
+
+impl<T: bytemuck::AnyBitPattern> Drop for ArenaBuffer<T> {
+	fn drop(&mut self) {
+		let mut arena = self.arena.borrow_mut();
+
+		if let Some(allocation) = arena.allocations[self.id].take() {
+			arena.free(allocation);
+		}
+
+		arena.free_ids.push(self.id);
+	}
+}
+
+/// # SAFETY
+/// * must be called from GL thread
+unsafe fn create_gl_buffer(size: usize, dsa: bool) -> GLuint {
+	let mut gl_buffer = 0;
+
+	match dsa {
+		true => {
+			gl::CreateBuffers(1, &mut gl_buffer);
+			gl::NamedBufferData(gl_buffer, size as GLsizeiptr, ptr::null(), gl::DYNAMIC_DRAW);
+		},
+		false => {
+			gl::GenBuffers(1, &mut gl_buffer);
+			gl::BindBuffer(gl::COPY_WRITE_BUFFER, gl_buffer);
+			gl::BufferData(
+				gl::COPY_WRITE_BUFFER,
+				size as GLsizeiptr,
+				ptr::null(),
+				gl::DYNAMIC_DRAW,
+			);
+		},
+	}
+
+	gl_buffer
+}
+
+/// # SAFETY
+/// * must be called from GL thread
+/// * `data` must be valid for `size` bytes
+unsafe fn write_gl_buffer(
+	gl_buffer: GLuint,
+	offset: usize,
+	data: *const c_void,
+	size: usize,
+	dsa: bool,
+) {
+	match dsa {
+		true => gl::NamedBufferSubData(gl_buffer, offset as GLintptr, size as GLsizeiptr, data),
+		false => {
+			gl::BindBuffer(gl::COPY_WRITE_BUFFER, gl_buffer);
+			gl::BufferSubData(gl::COPY_WRITE_BUFFER, offset as GLintptr, size as GLsizeiptr, data);
+		},
+	}
+}
+
+/// # SAFETY
+/// * must be called from GL thread
+unsafe fn copy_gl_buffer(
+	read_buffer: GLuint,
+	read_offset: usize,
+	write_buffer: GLuint,
+	write_offset: usize,
+	size: usize,
+	dsa: bool,
+) {
+	match dsa {
+		true => gl::CopyNamedBufferSubData(
+			read_buffer,
+			write_buffer,
+			read_offset as GLintptr,
+			write_offset as GLintptr,
+			size as GLsizeiptr,
+		),
+		false => {
+			gl::BindBuffer(gl::COPY_READ_BUFFER, read_buffer);
+			gl::BindBuffer(gl::COPY_WRITE_BUFFER, write_buffer);
+			gl::CopyBufferSubData(
+				gl::COPY_READ_BUFFER,
+				gl::COPY_WRITE_BUFFER,
+				read_offset as GLintptr,
+				write_offset as GLintptr,
+				size as GLsizeiptr,
+			);
+		},
+	}
+}
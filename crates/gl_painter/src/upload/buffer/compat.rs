@@ -4,11 +4,18 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/./
 
-use std::{ffi::c_void, marker::PhantomData, mem, sync::MutexGuard};
+use std::{ffi::c_void, marker::PhantomData, mem, ptr, sync::MutexGuard};
 
-use gl::types::{GLenum, GLsizeiptr, GLuint};
+use gl::types::{GLenum, GLintptr, GLsizeiptr, GLuint};
 
-use super::{CpuBacker, GpuBuffer};
+use super::{
+	growth::{BufferCapacity, BufferStats, GrowthPolicy},
+	CpuBacker,
+	GpuBuffer,
+};
+
+#[cfg(test)]
+mod test;
 
 /// GPU buffer implemented with `glBufferSubData`
 ///
@@ -18,7 +25,7 @@ pub struct CompatBuffer<T: bytemuck::AnyBitPattern> {
 	buffer_type: GLenum,
 	backer: CpuBacker<T>,
 	gl_buffer: GLuint,
-	gl_buffer_size: usize,
+	capacity: BufferCapacity,
 	backing_buffer_changed: bool,
 	_unsend: PhantomData<MutexGuard<'static, ()>>,
 }
@@ -29,7 +36,7 @@ impl<T: bytemuck::AnyBitPattern> CompatBuffer<T> {
 			buffer_type,
 			backer: CpuBacker::new(),
 			gl_buffer: 0,
-			gl_buffer_size: 0,
+			capacity: BufferCapacity::new(),
 			backing_buffer_changed: false,
 			_unsend: PhantomData,
 		}
@@ -48,10 +55,12 @@ impl<T: bytemuck::AnyBitPattern> GpuBuffer<T> for CompatBuffer<T> {
 	}
 
 	unsafe fn begin_flush(&mut self) {
+		// the backer may have shrunk since it was written to
+		self.backer.modified.truncate(self.backer.buffer.len());
+
 		let buffer_len = (self.backer.buffer.len() * mem::size_of::<T>()) as GLsizeiptr;
 
-		// gl buffer size will be 0 if the buffer does not exist
-		if self.gl_buffer_size < self.backer.buffer.len() {
+		if let Some(capacity) = self.capacity.update(self.backer.buffer.len()) {
 			if self.gl_buffer == 0 {
 				gl::GenBuffers(1, &mut self.gl_buffer);
 				gl::BindBuffer(self.buffer_type, self.gl_buffer);
@@ -63,29 +72,48 @@ impl<T: bytemuck::AnyBitPattern> GpuBuffer<T> for CompatBuffer<T> {
 
 			gl::BufferData(
 				self.buffer_type,
-				buffer_len,
-				self.backer.buffer[..].as_ptr() as *const _ as *const c_void,
-				//bytemuck::cast_slice::<T, u8>(&self.buffer).as_ptr() as *const c_void,
+				(capacity * mem::size_of::<T>()) as GLsizeiptr,
+				ptr::null(),
 				gl::DYNAMIC_DRAW,
 			);
 
-			self.gl_buffer_size = self.backer.buffer.len();
-		} else if let Some(range) = &self.backer.modified_range {
-			// flush region being `Some` means that range is not 0
-
-			self.bind();
-
-			// TODO: invalidate buffer?
 			gl::BufferSubData(
 				self.buffer_type,
-				(range.start * mem::size_of::<T>()) as GLsizeiptr,
-				(range.end * mem::size_of::<T>()) as GLsizeiptr,
-				self.backer.buffer[..].as_ptr() as *const _ as *const c_void,
-				//bytemuck::cast_slice::<T, u8>(&self.buffer).as_ptr() as *const c_void,
+				0,
+				buffer_len,
+				self.backer.buffer[..].as_ptr() as *const c_void,
 			);
+			self.capacity.record_upload(buffer_len as usize);
+		} else if !self.backer.modified.is_empty() {
+			self.bind();
+
+			let element_size = mem::size_of::<T>();
+
+			// TODO: invalidate buffer?
+			if self.backer.modified.prefer_whole_upload(self.backer.buffer.len(), element_size) {
+				gl::BufferSubData(
+					self.buffer_type,
+					0,
+					buffer_len,
+					self.backer.buffer[..].as_ptr() as *const c_void,
+				);
+				self.capacity.record_upload(buffer_len as usize);
+			} else {
+				let covered_len = self.backer.modified.covered_len();
+				self.capacity.record_upload(covered_len * element_size);
+
+				for range in self.backer.modified.iter() {
+					gl::BufferSubData(
+						self.buffer_type,
+						(range.start * element_size) as GLintptr,
+						(range.len() * element_size) as GLsizeiptr,
+						self.backer.buffer[range].as_ptr() as *const c_void,
+					);
+				}
+			}
 		}
 
-		self.backer.modified_range = None;
+		self.backer.modified.clear();
 	}
 
 	unsafe fn sync_flush(&mut self) {}
@@ -110,6 +138,21 @@ impl<T: bytemuck::AnyBitPattern> GpuBuffer<T> for CompatBuffer<T> {
 		self.backing_buffer_changed = false;
 	}
 
+	fn set_growth_policy(&mut self, policy: GrowthPolicy) {
+		self.capacity.set_policy(policy);
+	}
+
+	fn shrink_to_fit(&mut self) {
+		self.capacity.shrink_to_fit();
+	}
+
+	fn stats(&self) -> BufferStats {
+		BufferStats {
+			size: self.capacity.capacity() * mem::size_of::<T>(),
+			..self.capacity.stats()
+		}
+	}
+
 	fn backing_buffer(&self) -> GLuint {
 		self.gl_buffer
 	}
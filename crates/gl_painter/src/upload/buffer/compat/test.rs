@@ -0,0 +1,25 @@
+use super::CompatBuffer;
+use crate::{mock_gl, upload::buffer::GpuBuffer};
+
+#[test]
+fn test_partial_upload() {
+	mock_gl::install(3, 3);
+	let mut buffer = CompatBuffer::<u32>::new(gl::ARRAY_BUFFER);
+
+	unsafe {
+		buffer.write().write(0, 0..10000);
+		buffer.begin_flush();
+		mock_gl::take_calls();
+
+		// sizes are the length of each range, not its end
+		buffer.write().write(10, 0..10);
+		buffer.write().write(9000, 0..5);
+		buffer.begin_flush();
+	}
+
+	assert_eq!(mock_gl::take_calls(), [
+		"BindBuffer(ARRAY_BUFFER, 1)",
+		"BufferSubData(ARRAY_BUFFER, 40, 40)",
+		"BufferSubData(ARRAY_BUFFER, 36000, 20)",
+	]);
+}
@@ -0,0 +1,119 @@
+// Copyright (C) 2022 the ITK authors
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/./
+
+use std::ops::Range;
+
+#[cfg(test)]
+mod test;
+
+/// Default gap, in elements, below which neighbouring ranges are merged
+pub const DEFAULT_MERGE_GAP: usize = 64;
+
+/// Estimated cost of issuing an extra upload call, in bytes
+///
+/// Used to decide when uploading the whole buffer is cheaper
+/// than uploading each range separately.
+const RANGE_COST_BYTES: usize = 4096;
+
+/// Set of modified element ranges
+///
+/// Ranges are kept sorted and disjoint. Ranges closer than the merge gap
+/// are coalesced, as uploading a few unmodified elements is cheaper than
+/// an extra upload call.
+#[derive(Debug, Clone)]
+pub struct DirtyRanges {
+	ranges: Vec<Range<usize>>,
+	merge_gap: usize,
+}
+
+impl DirtyRanges {
+	pub fn new(merge_gap: usize) -> Self {
+		Self {
+			ranges: Vec::new(),
+			merge_gap,
+		}
+	}
+
+	pub fn merge_gap(&self) -> usize {
+		self.merge_gap
+	}
+
+	/// Set the merge gap
+	///
+	/// Only affects ranges inserted after the change.
+	pub fn set_merge_gap(&mut self, merge_gap: usize) {
+		self.merge_gap = merge_gap;
+	}
+
+	/// Mark `range` as modified, merging it with any range within the merge gap
+	pub fn insert(&mut self, range: Range<usize>) {
+		if range.is_empty() {
+			return
+		}
+
+		let gap = self.merge_gap;
+		// ranges are sorted and disjoint, so both predicates are partitioned
+		let first = self.ranges.partition_point(|r| r.end.saturating_add(gap) < range.start);
+		let last = self.ranges.partition_point(|r| r.start <= range.end.saturating_add(gap));
+
+		if first == last {
+			self.ranges.insert(first, range);
+		} else {
+			let start = usize::min(range.start, self.ranges[first].start);
+			let end = usize::max(range.end, self.ranges[last - 1].end);
+			self.ranges.splice(first..last, std::iter::once(start..end));
+		}
+	}
+
+	/// Mark every range of `other` as modified
+	pub fn extend(&mut self, other: &DirtyRanges) {
+		for range in &other.ranges {
+			self.insert(range.clone());
+		}
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.ranges.is_empty()
+	}
+
+	pub fn clear(&mut self) {
+		self.ranges.clear();
+	}
+
+	/// Iterate over the modified ranges in order
+	pub fn iter(&self) -> impl Iterator<Item = Range<usize>> + '_ {
+		self.ranges.iter().cloned()
+	}
+
+	/// Drop everything past `len`, for buffers that have shrunk
+	pub fn truncate(&mut self, len: usize) {
+		self.ranges.retain_mut(|r| {
+			r.end = usize::min(r.end, len);
+			r.start < r.end
+		});
+	}
+
+	/// Number of modified elements
+	pub fn covered_len(&self) -> usize {
+		self.ranges.iter().map(|r| r.len()).sum()
+	}
+
+	/// Check if uploading all `len` elements of the buffer is estimated to
+	/// be cheaper than uploading each range
+	pub fn prefer_whole_upload(&self, len: usize, element_size: usize) -> bool {
+		let per_range = (self.covered_len() * element_size)
+			.saturating_add(self.ranges.len().saturating_mul(RANGE_COST_BYTES));
+		let whole = (len * element_size).saturating_add(RANGE_COST_BYTES);
+
+		self.ranges.len() > 1 && whole <= per_range
+	}
+}
+
+impl Default for DirtyRanges {
+	fn default() -> Self {
+		Self::new(DEFAULT_MERGE_GAP)
+	}
+}
@@ -0,0 +1,55 @@
+use super::DirtyRanges;
+
+fn ranges(dirty: &DirtyRanges) -> Vec<(usize, usize)> {
+	dirty.iter().map(|r| (r.start, r.end)).collect()
+}
+
+#[test]
+fn test_merge() {
+	let mut dirty = DirtyRanges::new(4);
+
+	dirty.insert(0..2);
+	dirty.insert(100..110);
+	dirty.insert(50..60);
+	assert_eq!(ranges(&dirty), [(0, 2), (50, 60), (100, 110)]);
+
+	// within the gap of both neighbours
+	dirty.insert(63..97);
+	assert_eq!(ranges(&dirty), [(0, 2), (50, 110)]);
+
+	// exactly the gap away
+	dirty.insert(6..8);
+	assert_eq!(ranges(&dirty), [(0, 8), (50, 110)]);
+
+	// empty ranges are ignored
+	dirty.insert(30..30);
+	assert_eq!(ranges(&dirty), [(0, 8), (50, 110)]);
+
+	assert_eq!(dirty.covered_len(), 68);
+}
+
+#[test]
+fn test_truncate() {
+	let mut dirty = DirtyRanges::new(0);
+
+	dirty.insert(0..10);
+	dirty.insert(20..30);
+	dirty.insert(40..50);
+	dirty.truncate(25);
+	assert_eq!(ranges(&dirty), [(0, 10), (20, 25)]);
+}
+
+#[test]
+fn test_prefer_whole_upload() {
+	let mut dirty = DirtyRanges::new(0);
+
+	dirty.insert(0..1);
+	dirty.insert(100_000..100_001);
+	assert!(!dirty.prefer_whole_upload(100_001, 4));
+	assert!(dirty.prefer_whole_upload(200, 4));
+
+	// a single range is never split further
+	dirty.clear();
+	dirty.insert(0..200);
+	assert!(!dirty.prefer_whole_upload(200, 4));
+}
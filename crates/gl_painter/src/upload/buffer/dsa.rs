@@ -0,0 +1,161 @@
+// Copyright (C) 2022 the ITK authors
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/./
+
+use std::{ffi::c_void, marker::PhantomData, mem, ptr, sync::MutexGuard};
+
+use gl::types::{GLenum, GLintptr, GLsizeiptr, GLuint};
+
+use super::{
+	growth::{BufferCapacity, BufferStats, GrowthPolicy},
+	CpuBacker,
+	GpuBuffer,
+};
+
+/// GPU buffer implemented with Direct State Access
+/// (`ARB_direct_state_access` or GL 4.5)
+///
+/// Buffers are created with `glCreateBuffers` and updated with
+/// `glNamedBufferSubData`, so flushing never changes binding state.
+///
+/// !Send to ensure the backing GL buffer is deleted
+/// on the same thread
+pub struct DsaBuffer<T: bytemuck::AnyBitPattern> {
+	buffer_type: GLenum,
+	backer: CpuBacker<T>,
+	gl_buffer: GLuint,
+	capacity: BufferCapacity,
+	backing_buffer_changed: bool,
+	_unsend: PhantomData<MutexGuard<'static, ()>>,
+}
+
+impl<T: bytemuck::AnyBitPattern> DsaBuffer<T> {
+	pub fn new(buffer_type: GLenum) -> Self {
+		Self {
+			buffer_type,
+			backer: CpuBacker::new(),
+			gl_buffer: 0,
+			capacity: BufferCapacity::new(),
+			backing_buffer_changed: false,
+			_unsend: PhantomData,
+		}
+	}
+}
+
+impl<T: bytemuck::AnyBitPattern> GpuBuffer<T> for DsaBuffer<T> {
+	unsafe fn bind(&self) {
+		gl::BindBuffer(self.buffer_type, self.gl_buffer);
+	}
+
+	unsafe fn prepare_write(&mut self) {}
+
+	unsafe fn write(&mut self) -> &mut CpuBacker<T> {
+		&mut self.backer
+	}
+
+	unsafe fn begin_flush(&mut self) {
+		// the backer may have shrunk since it was written to
+		self.backer.modified.truncate(self.backer.buffer.len());
+
+		let element_size = mem::size_of::<T>();
+
+		if let Some(capacity) = self.capacity.update(self.backer.buffer.len()) {
+			// immutable storage can't be resized, so a new buffer is created.
+			// the old buffer is kept alive by the driver until pending draws are done.
+			gl::DeleteBuffers(1, &self.gl_buffer);
+			gl::CreateBuffers(1, &mut self.gl_buffer);
+
+			gl::NamedBufferStorage(
+				self.gl_buffer,
+				(capacity * element_size) as GLsizeiptr,
+				ptr::null(),
+				gl::DYNAMIC_STORAGE_BIT,
+			);
+
+			gl::NamedBufferSubData(
+				self.gl_buffer,
+				0,
+				(self.backer.buffer.len() * element_size) as GLsizeiptr,
+				self.backer.buffer[..].as_ptr() as *const c_void,
+			);
+			self.capacity.record_upload(self.backer.buffer.len() * element_size);
+
+			self.backing_buffer_changed = true;
+		} else if !self.backer.modified.is_empty() {
+			if self.backer.modified.prefer_whole_upload(self.backer.buffer.len(), element_size) {
+				gl::NamedBufferSubData(
+					self.gl_buffer,
+					0,
+					(self.backer.buffer.len() * element_size) as GLsizeiptr,
+					self.backer.buffer[..].as_ptr() as *const c_void,
+				);
+				self.capacity.record_upload(self.backer.buffer.len() * element_size);
+			} else {
+				let covered_len = self.backer.modified.covered_len();
+				self.capacity.record_upload(covered_len * element_size);
+
+				for range in self.backer.modified.iter() {
+					gl::NamedBufferSubData(
+						self.gl_buffer,
+						(range.start * element_size) as GLintptr,
+						(range.len() * element_size) as GLsizeiptr,
+						self.backer.buffer[range].as_ptr() as *const c_void,
+					);
+				}
+			}
+		}
+
+		self.backer.modified.clear();
+	}
+
+	unsafe fn sync_flush(&mut self) {}
+
+	fn resize(&mut self, size: usize) {
+		self.backer.buffer.resize(size, T::zeroed());
+	}
+
+	fn len(&self) -> usize {
+		self.backer.buffer.len()
+	}
+
+	fn has_backing_buffer(&self) -> bool {
+		self.gl_buffer != 0
+	}
+
+	fn backing_buffer_changed(&self) -> bool {
+		self.backing_buffer_changed
+	}
+
+	fn clear_buffer_changed(&mut self) {
+		self.backing_buffer_changed = false;
+	}
+
+	fn set_growth_policy(&mut self, policy: GrowthPolicy) {
+		self.capacity.set_policy(policy);
+	}
+
+	fn shrink_to_fit(&mut self) {
+		self.capacity.shrink_to_fit();
+	}
+
+	fn stats(&self) -> BufferStats {
+		BufferStats {
+			size: self.capacity.capacity() * mem::size_of::<T>(),
+			..self.capacity.stats()
+		}
+	}
+
+	fn backing_buffer(&self) -> GLuint {
+		self.gl_buffer
+	}
+}
+
+impl<T: bytemuck::AnyBitPattern> Drop for DsaBuffer<T> {
+	fn drop(&mut self) {
+		unsafe {
+			gl::DeleteBuffers(1, &self.gl_buffer);
+		}
+	}
+}
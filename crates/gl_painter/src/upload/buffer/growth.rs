@@ -0,0 +1,184 @@
+// Copyright (C) 2022 the ITK authors
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/./
+
+use std::time::Duration;
+
+#[cfg(test)]
+mod test;
+
+pub const DEFAULT_GROWTH_FACTOR: f32 = 1.5;
+pub const DEFAULT_SHRINK_FRAMES: u32 = 120;
+pub const DEFAULT_SHRINK_THRESHOLD: f32 = 0.25;
+
+/// How a GPU buffer's backing buffer grows and shrinks
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GrowthPolicy {
+	/// Factor the capacity is multiplied by when the buffer outgrows it
+	///
+	/// Values below 1 are treated as 1.
+	pub growth_factor: f32,
+	/// Number of consecutive low use flushes before shrinking,
+	/// 0 to never shrink automatically
+	pub shrink_frames: u32,
+	/// Fraction of the capacity below which a flush counts as low use
+	pub shrink_threshold: f32,
+}
+
+impl Default for GrowthPolicy {
+	fn default() -> Self {
+		Self {
+			growth_factor: DEFAULT_GROWTH_FACTOR,
+			shrink_frames: DEFAULT_SHRINK_FRAMES,
+			shrink_threshold: DEFAULT_SHRINK_THRESHOLD,
+		}
+	}
+}
+
+/// Allocation and upload statistics of a GPU buffer
+///
+/// Counters accumulate over the buffer's lifetime.
+/// Use `since` to get the counts of a single frame.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct BufferStats {
+	/// Capacity of the backing buffer, in elements
+	pub capacity: usize,
+	/// GPU memory used by the backing buffers, in bytes
+	pub size: usize,
+	/// Number of capacity changes, each reallocating the backing buffer
+	pub allocations: u64,
+	/// Number of allocations made to shrink the backing buffer
+	pub shrinks: u64,
+	/// Number of bytes copied into the backing buffer
+	pub bytes_uploaded: u64,
+	/// Time spent waiting for the GPU to release the backing buffer
+	pub fence_wait: Duration,
+}
+
+impl BufferStats {
+	/// Counters accumulated since `earlier`, a previous snapshot of the same buffer
+	///
+	/// Capacity and size are kept as they are now.
+	pub fn since(&self, earlier: &Self) -> Self {
+		Self {
+			capacity: self.capacity,
+			size: self.size,
+			allocations: self.allocations - earlier.allocations,
+			shrinks: self.shrinks - earlier.shrinks,
+			bytes_uploaded: self.bytes_uploaded - earlier.bytes_uploaded,
+			fence_wait: self.fence_wait.saturating_sub(earlier.fence_wait),
+		}
+	}
+}
+
+/// Capacity of a backing buffer, following a `GrowthPolicy`,
+/// along with the buffer's `BufferStats`
+#[derive(Debug, Default, Clone)]
+pub struct BufferCapacity {
+	policy: GrowthPolicy,
+	capacity: usize,
+	low_use_frames: u32,
+	shrink_to_fit: bool,
+	stats: BufferStats,
+}
+
+impl BufferCapacity {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn policy(&self) -> GrowthPolicy {
+		self.policy
+	}
+
+	pub fn set_policy(&mut self, policy: GrowthPolicy) {
+		self.policy = policy;
+	}
+
+	/// Capacity of the backing buffer, in elements
+	pub fn capacity(&self) -> usize {
+		self.capacity
+	}
+
+	/// Get the buffer's statistics
+	///
+	/// `size` is left for the buffer to fill, as only it knows
+	/// the element size and number of backing buffers.
+	pub fn stats(&self) -> BufferStats {
+		BufferStats {
+			capacity: self.capacity,
+			..self.stats
+		}
+	}
+
+	/// Count `bytes` copied into the backing buffer
+	pub fn record_upload(&mut self, bytes: usize) {
+		self.stats.bytes_uploaded += bytes as u64;
+	}
+
+	/// Count time spent waiting on a fence
+	pub fn record_fence_wait(&mut self, wait: Duration) {
+		self.stats.fence_wait += wait;
+	}
+
+	/// Shrink the capacity to the buffer's length on the next `update`
+	pub fn shrink_to_fit(&mut self) {
+		self.shrink_to_fit = true;
+	}
+
+	/// Update the capacity for a buffer of `len` elements, once per flush
+	///
+	/// Returns the new capacity if the backing buffer must be reallocated.
+	/// Capacity never shrinks to 0, so flushed buffers keep a backing buffer.
+	pub fn update(&mut self, len: usize) -> Option<usize> {
+		let growth_factor = self.policy.growth_factor.max(1.0);
+
+		if len > self.capacity {
+			let grown = (self.capacity as f32 * growth_factor).ceil() as usize;
+			return Some(self.reallocate(grown.max(len), false))
+		}
+
+		if self.shrink_to_fit {
+			self.shrink_to_fit = false;
+
+			return match len.max(1) < self.capacity {
+				true => Some(self.reallocate(len.max(1), true)),
+				false => None,
+			}
+		}
+
+		let low_use = (len as f32) < self.capacity as f32 * self.policy.shrink_threshold;
+
+		match low_use && self.policy.shrink_frames != 0 {
+			true => self.low_use_frames += 1,
+			false => self.low_use_frames = 0,
+		}
+
+		if self.low_use_frames != 0 && self.low_use_frames >= self.policy.shrink_frames {
+			self.low_use_frames = 0;
+
+			// keep room to grow so the buffer isn't immediately reallocated again
+			let shrunk = ((len as f32 * growth_factor).ceil() as usize).max(1);
+
+			if shrunk < self.capacity {
+				return Some(self.reallocate(shrunk, true))
+			}
+		}
+
+		None
+	}
+
+	fn reallocate(&mut self, capacity: usize, shrink: bool) -> usize {
+		self.capacity = capacity;
+		self.low_use_frames = 0;
+		self.stats.allocations += 1;
+
+		if shrink {
+			self.stats.shrinks += 1;
+		}
+
+		capacity
+	}
+}
@@ -0,0 +1,82 @@
+use std::time::Duration;
+
+use super::{BufferCapacity, GrowthPolicy};
+
+#[test]
+fn test_growth() {
+	let mut capacity = BufferCapacity::new();
+	capacity.set_policy(GrowthPolicy {
+		growth_factor: 2.0,
+		..Default::default()
+	});
+
+	// empty buffers are never allocated
+	assert_eq!(capacity.update(0), None);
+
+	assert_eq!(capacity.update(3), Some(3));
+	assert_eq!(capacity.update(3), None);
+	// grows geometrically when appending
+	assert_eq!(capacity.update(4), Some(6));
+	assert_eq!(capacity.update(5), None);
+	assert_eq!(capacity.update(6), None);
+	assert_eq!(capacity.update(7), Some(12));
+	// or to the length if it grows faster
+	assert_eq!(capacity.update(100), Some(100));
+
+	assert_eq!(capacity.stats().capacity, 100);
+	assert_eq!(capacity.stats().allocations, 4);
+	assert_eq!(capacity.stats().shrinks, 0);
+}
+
+#[test]
+fn test_shrink() {
+	let mut capacity = BufferCapacity::new();
+	capacity.set_policy(GrowthPolicy {
+		growth_factor: 2.0,
+		shrink_frames: 3,
+		shrink_threshold: 0.5,
+	});
+
+	assert_eq!(capacity.update(100), Some(100));
+
+	assert_eq!(capacity.update(10), None);
+	assert_eq!(capacity.update(10), None);
+	// low use streak broken
+	assert_eq!(capacity.update(60), None);
+
+	assert_eq!(capacity.update(10), None);
+	assert_eq!(capacity.update(10), None);
+	// shrinks with room to grow
+	assert_eq!(capacity.update(10), Some(20));
+
+	capacity.shrink_to_fit();
+	assert_eq!(capacity.update(15), Some(15));
+	assert_eq!(capacity.update(15), None);
+
+	// never shrinks to 0
+	capacity.shrink_to_fit();
+	assert_eq!(capacity.update(0), Some(1));
+
+	assert_eq!(capacity.stats().allocations, 4);
+	assert_eq!(capacity.stats().shrinks, 3);
+}
+
+#[test]
+fn test_stats_since() {
+	let mut capacity = BufferCapacity::new();
+
+	capacity.update(10);
+	capacity.record_upload(40);
+	capacity.record_fence_wait(Duration::from_millis(2));
+	let earlier = capacity.stats();
+
+	capacity.update(20);
+	capacity.record_upload(80);
+	capacity.record_fence_wait(Duration::from_millis(3));
+	let frame = capacity.stats().since(&earlier);
+
+	assert_eq!(frame.capacity, 20);
+	assert_eq!(frame.allocations, 1);
+	assert_eq!(frame.bytes_uploaded, 80);
+	assert_eq!(frame.fence_wait, Duration::from_millis(3));
+}
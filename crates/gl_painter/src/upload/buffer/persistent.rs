@@ -4,43 +4,187 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/./
 
-use std::{ffi::c_void, mem, ptr};
+use std::{mem, ptr, rc::Rc, time::Duration};
 
-use gl::types::{GLenum, GLsizeiptr, GLsync, GLuint};
+use gl::types::{GLbitfield, GLenum, GLintptr, GLsizeiptr, GLuint};
 
-use super::{CpuBacker, GpuBuffer};
+use super::{
+	dirty::DirtyRanges,
+	growth::{BufferCapacity, BufferStats, GrowthPolicy},
+	CpuBacker,
+	GpuBuffer,
+};
+use crate::{
+	upload::sync::{Fence, DEFAULT_FRAME_LATENCY},
+	GlCapabilities,
+};
 
-// FIXME: Performance seems a bit lower than it should be
-// FIXME: Possible synchronization issues (gpu still reading as buffer is overwritten)
-/// GPU buffer implemented with persistent mapped buffers
+#[cfg(test)]
+mod test;
+
+/// GPU buffer implemented with a ring of persistent mapped segments
+///
+/// Each frame writes into the next segment of the ring, so the CPU never
+/// overwrites a segment the GPU may still be reading from. Every segment
+/// is fenced when the frame using it is done, and the fence is waited on
+/// with `glClientWaitSync` before the segment is written again.
+///
+/// The ring has one segment more than the frame latency, so the CPU only
+/// waits here if it runs further ahead than the latency allows. Segments
+/// are fenced by `finish_use`, or else by the next `prepare_write`.
+///
+/// All segments share one backing buffer, which only changes when the ring
+/// is reallocated. The current segment starts at `backing_offset`, so users
+/// should address it with a base vertex or instance rather than rebinding.
+/// Segments of shader storage buffers are aligned so they can be bound with
+/// `glBindBufferRange`.
 ///
 /// !Send to ensure the backing GL buffer is deleted
 /// on the same thread
 pub struct PersistentBuffer<T: bytemuck::AnyBitPattern> {
 	buffer_type: GLenum,
 	backer: CpuBacker<T>,
-	mapped_region: *mut T,
 	gl_buffer: GLuint,
-	gl_buffer_size: usize,
-	flush_fence: GLsync,
+	mapped_region: *mut T,
+	/// Size of each segment in elements
+	segment_size: usize,
+	/// Distance between the starts of segments in bytes
+	segment_stride: usize,
+	/// Alignment of each segment in bytes
+	segment_alignment: usize,
+	/// Number of segments the backing buffer was allocated with
+	allocated_segments: usize,
+	segments: Vec<Segment>,
+	capacity: BufferCapacity,
+	current: usize,
 	backing_buffer_changed: bool,
 }
 
-// the raw pointer in `PersistentBuffer` sets !Send and !Sync
+struct Segment {
+	/// Ranges of the backer modified since this segment was last written
+	dirty: DirtyRanges,
+	/// Fence placed after the last frame that used this segment
+	fence: Option<Rc<Fence>>,
+	/// Set if this segment has been flushed since it became current
+	flushed: bool,
+}
+
+// the raw pointers in `PersistentBuffer` set !Send and !Sync
 unsafe impl<T: bytemuck::AnyBitPattern> Sync for PersistentBuffer<T> {}
 
 impl<T: bytemuck::AnyBitPattern> PersistentBuffer<T> {
+	/// # NOTES
+	/// Must be called from the GL thread.
 	pub fn new(buffer_type: GLenum) -> Self {
+		let segment_alignment = match buffer_type {
+			gl::SHADER_STORAGE_BUFFER => {
+				let capabilities = unsafe { GlCapabilities::get() };
+				capabilities.limits.shader_storage_buffer_offset_alignment as usize
+			},
+			_ => 1,
+		};
+
 		Self {
 			buffer_type,
 			backer: CpuBacker::new(),
-			mapped_region: ptr::null_mut(),
 			gl_buffer: 0,
-			gl_buffer_size: 0,
-			flush_fence: ptr::null(),
+			mapped_region: ptr::null_mut(),
+			segment_size: 0,
+			segment_stride: 0,
+			segment_alignment: segment_alignment.max(1),
+			allocated_segments: 0,
+			segments: (0..=DEFAULT_FRAME_LATENCY).map(|_| Segment::new()).collect(),
+			capacity: BufferCapacity::new(),
+			current: 0,
 			backing_buffer_changed: false,
 		}
 	}
+
+	/// Reallocate the ring with segments of the buffer's capacity, uploading
+	/// all of the backer to the current segment
+	///
+	/// # SAFETY
+	/// * must be called from GL thread
+	unsafe fn reallocate(&mut self) {
+		let element_size = mem::size_of::<T>();
+		let segment_size = self.capacity.capacity();
+		let segment_stride = (segment_size * element_size).next_multiple_of(self.segment_alignment);
+		let ring_size = segment_stride * self.segments.len();
+
+		// the old buffer is kept alive by the driver until the GPU is done with it,
+		// and nothing uses the new one yet
+		if self.gl_buffer != 0 {
+			gl::DeleteBuffers(1, &self.gl_buffer);
+		}
+
+		self.gl_buffer = 0;
+		gl::GenBuffers(1, &mut self.gl_buffer);
+		gl::BindBuffer(self.buffer_type, self.gl_buffer);
+
+		let flags: GLbitfield = gl::MAP_PERSISTENT_BIT | gl::MAP_WRITE_BIT;
+
+		gl::BufferStorage(self.buffer_type, ring_size as GLsizeiptr, ptr::null(), flags);
+
+		self.mapped_region = gl::MapBufferRange(
+			self.buffer_type,
+			0,
+			ring_size as GLsizeiptr,
+			flags | gl::MAP_FLUSH_EXPLICIT_BIT,
+		) as *mut T;
+
+		self.segment_size = segment_size;
+		self.segment_stride = segment_stride;
+		self.allocated_segments = self.segments.len();
+
+		let buffer_len = self.backer.buffer.len();
+		for segment in &mut self.segments {
+			segment.fence = None;
+			segment.dirty.clear();
+			segment.dirty.insert(0..buffer_len);
+		}
+
+		std::slice::from_raw_parts_mut::<T>(self.current_segment(), buffer_len)
+			.copy_from_slice(&self.backer.buffer);
+
+		if buffer_len != 0 {
+			gl::FlushMappedBufferRange(
+				self.buffer_type,
+				self.backing_offset() as GLintptr,
+				(buffer_len * element_size) as GLsizeiptr,
+			);
+		}
+
+		self.capacity.record_upload(buffer_len * element_size);
+
+		self.segments[self.current].dirty.clear();
+		self.backing_buffer_changed = true;
+	}
+
+	/// Start of the current segment's mapping
+	fn current_segment(&self) -> *mut T {
+		self.mapped_region.cast::<u8>().wrapping_add(self.backing_offset()).cast()
+	}
+}
+
+impl Segment {
+	fn new() -> Self {
+		Self {
+			dirty: DirtyRanges::default(),
+			fence: None,
+			flushed: false,
+		}
+	}
+
+	/// Wait for the GPU to finish using this segment, returning the time waited
+	///
+	/// # SAFETY
+	/// * must be called from GL thread
+	unsafe fn wait(&mut self) -> Duration {
+		match self.fence.take() {
+			Some(fence) => fence.wait(),
+			None => Duration::ZERO,
+		}
+	}
 }
 
 impl<T: bytemuck::AnyBitPattern> GpuBuffer<T> for PersistentBuffer<T> {
@@ -49,7 +193,21 @@ impl<T: bytemuck::AnyBitPattern> GpuBuffer<T> for PersistentBuffer<T> {
 	}
 
 	unsafe fn prepare_write(&mut self) {
-		self.sync_flush();
+		let segment = &mut self.segments[self.current];
+
+		if segment.flushed {
+			// the last frame's commands using this segment have been submitted
+			segment.flushed = false;
+
+			if segment.fence.is_none() {
+				segment.fence = Some(Fence::insert());
+			}
+
+			self.current = (self.current + 1) % self.segments.len();
+		}
+
+		let wait = self.segments[self.current].wait();
+		self.capacity.record_fence_wait(wait);
 	}
 
 	unsafe fn write(&mut self) -> &mut CpuBacker<T> {
@@ -57,71 +215,56 @@ impl<T: bytemuck::AnyBitPattern> GpuBuffer<T> for PersistentBuffer<T> {
 	}
 
 	unsafe fn begin_flush(&mut self) {
-		let buffer_len = self.backer.buffer.len() * mem::size_of::<T>();
-
-		// gl buffer size will be 0 if the buffer does not exist
-		if self.gl_buffer_size < self.backer.buffer.len() {
-			if self.gl_buffer != 0 {
-				gl::DeleteBuffers(1, &self.gl_buffer);
-			}
+		let buffer_len = self.backer.buffer.len();
 
-			self.gl_buffer = 0;
-			gl::GenBuffers(1, &mut self.gl_buffer);
-			gl::BindBuffer(self.buffer_type, self.gl_buffer);
+		for segment in &mut self.segments {
+			segment.dirty.set_merge_gap(self.backer.modified.merge_gap());
+			segment.dirty.extend(&self.backer.modified);
+			// the backer may have shrunk since it was written to
+			segment.dirty.truncate(buffer_len);
+		}
+		self.backer.modified.clear();
 
-			gl::BufferStorage(
-				self.buffer_type,
-				buffer_len as GLsizeiptr,
-				self.backer.buffer[..].as_ptr() as *const _ as *const c_void,
-				//bytemuck::cast_slice::<T, u8>(&self.buffer).as_ptr() as *const c_void,
-				gl::DYNAMIC_STORAGE_BIT | gl::MAP_PERSISTENT_BIT | gl::MAP_WRITE_BIT,
-			);
+		self.capacity.update(buffer_len);
 
-			self.mapped_region = gl::MapBufferRange(
-				self.buffer_type,
-				0,
-				buffer_len as GLsizeiptr,
-				gl::MAP_PERSISTENT_BIT | gl::MAP_WRITE_BIT | gl::MAP_FLUSH_EXPLICIT_BIT,
-			) as *mut T;
+		if self.segment_size != self.capacity.capacity()
+			|| self.allocated_segments != self.segments.len()
+		{
+			self.reallocate();
+		} else if !self.segments[self.current].dirty.is_empty() {
+			let element_size = mem::size_of::<T>();
+			let segment_start = self.backing_offset();
+			let mapped_region =
+				std::slice::from_raw_parts_mut::<T>(self.current_segment(), self.segment_size);
+			let segment = &mut self.segments[self.current];
 
-			self.gl_buffer_size = self.backer.buffer.len();
-			self.backing_buffer_changed = true;
+			gl::BindBuffer(self.buffer_type, self.gl_buffer);
 
-			if self.flush_fence != ptr::null() {
-				gl::DeleteSync(self.flush_fence);
-				self.flush_fence = ptr::null();
+			if segment.dirty.prefer_whole_upload(buffer_len, element_size) {
+				segment.dirty.clear();
+				segment.dirty.insert(0..buffer_len);
 			}
-		} else if let Some(range) = &self.backer.modified_range {
-			// flush region being `Some` means that range is not 0
-
-			self.bind();
 
-			std::slice::from_raw_parts_mut::<T>(self.mapped_region, self.gl_buffer_size)
-				[self.backer.modified_range.as_ref().unwrap().clone()]
-			.clone_from_slice(&self.backer.buffer[range.clone()]);
+			self.capacity.record_upload(segment.dirty.covered_len() * element_size);
 
-			gl::FlushMappedBufferRange(
-				self.buffer_type,
-				(range.start * mem::size_of::<T>()) as GLsizeiptr,
-				(range.end * mem::size_of::<T>()) as GLsizeiptr,
-			);
-			gl::MemoryBarrier(gl::CLIENT_MAPPED_BUFFER_BARRIER_BIT);
+			for range in segment.dirty.iter() {
+				mapped_region[range.clone()].copy_from_slice(&self.backer.buffer[range.clone()]);
 
-			if self.flush_fence != ptr::null() {
-				gl::DeleteSync(self.flush_fence);
+				gl::FlushMappedBufferRange(
+					self.buffer_type,
+					(segment_start + range.start * element_size) as GLintptr,
+					(range.len() * element_size) as GLsizeiptr,
+				);
 			}
-			self.flush_fence = gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
+
+			segment.dirty.clear();
 		}
 
-		self.backer.modified_range = None;
+		self.segments[self.current].flushed = true;
 	}
 
 	unsafe fn sync_flush(&mut self) {
-		if !self.flush_fence.is_null() {
-			gl::WaitSync(self.flush_fence, 0, u64::MAX);
-			gl::DeleteSync(self.flush_fence);
-			self.flush_fence = ptr::null();
-		}
+		// explicitly flushed writes are visible to commands issued after the flush
 	}
 
 	fn resize(&mut self, size: usize) {
@@ -144,15 +287,63 @@ impl<T: bytemuck::AnyBitPattern> GpuBuffer<T> for PersistentBuffer<T> {
 		self.backing_buffer_changed = false;
 	}
 
+	unsafe fn finish_use(&mut self, fence: &Rc<Fence>) {
+		self.segments[self.current].fence = Some(fence.clone());
+	}
+
+	/// Resize the ring to one segment more than `frames`
+	///
+	/// The ring is reallocated on the next `begin_flush`.
+	fn set_frame_latency(&mut self, frames: usize) {
+		let segment_count = frames.max(1) + 1;
+
+		while self.segments.len() < segment_count {
+			self.segments.push(Segment::new());
+		}
+
+		while self.segments.len() > segment_count {
+			let removed = match self.current == self.segments.len() - 1 {
+				true => 0,
+				false => self.segments.len() - 1,
+			};
+
+			self.segments.remove(removed);
+
+			if removed < self.current {
+				self.current -= 1;
+			}
+		}
+	}
+
+	fn set_growth_policy(&mut self, policy: GrowthPolicy) {
+		self.capacity.set_policy(policy);
+	}
+
+	fn shrink_to_fit(&mut self) {
+		self.capacity.shrink_to_fit();
+	}
+
+	/// `size` covers every segment of the ring
+	fn stats(&self) -> BufferStats {
+		BufferStats {
+			size: self.segment_stride * self.allocated_segments,
+			..self.capacity.stats()
+		}
+	}
+
 	fn backing_buffer(&self) -> GLuint {
 		self.gl_buffer
 	}
+
+	fn backing_offset(&self) -> usize {
+		self.current * self.segment_stride
+	}
 }
 
 impl<T: bytemuck::AnyBitPattern> Drop for PersistentBuffer<T> {
 	fn drop(&mut self) {
 		unsafe {
-			gl::DeleteBuffers(1, &mut self.gl_buffer);
+			gl::DeleteBuffers(1, &self.gl_buffer);
 		}
 	}
 }
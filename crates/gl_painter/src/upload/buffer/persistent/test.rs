@@ -0,0 +1,39 @@
+use super::PersistentBuffer;
+use crate::{
+	mock_gl,
+	upload::buffer::{self, GpuBuffer},
+};
+
+#[test]
+fn test_ring_keeps_backing_buffer() {
+	mock_gl::install(4, 4);
+	let mut buffer = PersistentBuffer::<u32>::new(gl::ARRAY_BUFFER);
+
+	unsafe {
+		buffer.prepare_write();
+		buffer.write().write(0, [1, 2, 3]);
+		buffer.begin_flush();
+	}
+
+	assert!(buffer.backing_buffer_changed());
+	assert_eq!(buffer.backing_offset(), 0);
+	buffer.clear_buffer_changed();
+	mock_gl::take_calls();
+
+	// the next frame writes the next segment of the same buffer
+	let segment_size = buffer.stats().size / 3;
+	unsafe {
+		buffer.prepare_write();
+		buffer.write().write(1, [4]);
+		buffer.begin_flush();
+	}
+
+	assert!(!buffer.backing_buffer_changed());
+	assert_eq!(buffer.backing_offset(), segment_size);
+	assert_eq!(buffer::base_element(&buffer), (segment_size / 4) as gl::types::GLint);
+	assert_eq!(mock_gl::take_calls(), [
+		"FenceSync(2)".to_string(),
+		"BindBuffer(ARRAY_BUFFER, 1)".to_string(),
+		format!("FlushMappedBufferRange(ARRAY_BUFFER, {segment_size}, 12)"),
+	]);
+}
@@ -0,0 +1,207 @@
+// Copyright (C) 2022 the ITK authors
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/./
+
+//! Reading GPU buffers back to the CPU
+//!
+//! Buffers are read through the `COPY_READ_BUFFER` and `COPY_WRITE_BUFFER`
+//! targets when DSA is unavailable, so VAO state is never disturbed.
+
+use std::{ffi::c_void, marker::PhantomData, mem, ptr};
+
+use gl::types::{GLintptr, GLsizeiptr, GLsync, GLuint};
+
+use crate::GlCapabilities;
+
+/// Timeout of a single `glClientWaitSync` call, in nanoseconds
+const FENCE_WAIT_TIMEOUT: u64 = 1_000_000;
+
+/// Read `len` elements starting `offset` bytes into `gl_buffer`
+///
+/// # SAFETY
+/// * must be called from GL thread
+/// * `gl_buffer` must hold at least `len` elements after `offset`
+pub unsafe fn read_gl_buffer<T: bytemuck::AnyBitPattern>(
+	gl_buffer: GLuint,
+	offset: usize,
+	len: usize,
+) -> Vec<T> {
+	let mut data = vec![T::zeroed(); len];
+
+	if len == 0 {
+		return data
+	}
+
+	let size = (len * mem::size_of::<T>()) as GLsizeiptr;
+	// T is AnyBitPattern, so any bytes written by the driver are a valid T
+	let data_ptr = data.as_mut_ptr() as *mut c_void;
+
+	match GlCapabilities::get().has_direct_state_access() {
+		true => gl::GetNamedBufferSubData(gl_buffer, offset as GLintptr, size, data_ptr),
+		false => {
+			gl::BindBuffer(gl::COPY_READ_BUFFER, gl_buffer);
+			gl::GetBufferSubData(gl::COPY_READ_BUFFER, offset as GLintptr, size, data_ptr);
+		},
+	}
+
+	data
+}
+
+/// Pending read of a GPU buffer
+///
+/// The data is copied into a staging buffer on the GPU and fenced,
+/// so the CPU only waits for the copy once it needs the data.
+///
+/// !Send to ensure the staging buffer is deleted on the same thread
+pub struct Readback<T: bytemuck::AnyBitPattern> {
+	staging_buffer: GLuint,
+	fence: GLsync,
+	len: usize,
+	_phantom: PhantomData<T>,
+}
+
+impl<T: bytemuck::AnyBitPattern> Readback<T> {
+	/// Start reading `len` elements starting `offset` bytes into `gl_buffer`
+	///
+	/// # SAFETY
+	/// * must be called from GL thread
+	/// * `gl_buffer` must hold at least `len` elements after `offset`
+	pub unsafe fn new(gl_buffer: GLuint, offset: usize, len: usize) -> Self {
+		let mut readback = Self {
+			staging_buffer: 0,
+			fence: ptr::null(),
+			len,
+			_phantom: PhantomData,
+		};
+
+		if len == 0 {
+			return readback
+		}
+
+		let size = (len * mem::size_of::<T>()) as GLsizeiptr;
+
+		match GlCapabilities::get().has_direct_state_access() {
+			true => {
+				gl::CreateBuffers(1, &mut readback.staging_buffer);
+				gl::NamedBufferData(readback.staging_buffer, size, ptr::null(), gl::STREAM_READ);
+				gl::CopyNamedBufferSubData(
+					gl_buffer,
+					readback.staging_buffer,
+					offset as GLintptr,
+					0,
+					size,
+				);
+			},
+			false => {
+				gl::GenBuffers(1, &mut readback.staging_buffer);
+				gl::BindBuffer(gl::COPY_WRITE_BUFFER, readback.staging_buffer);
+				gl::BufferData(gl::COPY_WRITE_BUFFER, size, ptr::null(), gl::STREAM_READ);
+				gl::BindBuffer(gl::COPY_READ_BUFFER, gl_buffer);
+				gl::CopyBufferSubData(
+					gl::COPY_READ_BUFFER,
+					gl::COPY_WRITE_BUFFER,
+					offset as GLintptr,
+					0,
+					size,
+				);
+			},
+		}
+
+		readback.fence = gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
+		readback
+	}
+
+	/// Check if the data can be taken without blocking
+	///
+	/// # SAFETY
+	/// * must be called from GL thread
+	pub unsafe fn is_ready(&self) -> bool {
+		self.fence.is_null()
+			|| matches!(
+				gl::ClientWaitSync(self.fence, gl::SYNC_FLUSH_COMMANDS_BIT, 0),
+				gl::ALREADY_SIGNALED | gl::CONDITION_SATISFIED
+			)
+	}
+
+	/// Take the data if it is ready, otherwise give back the readback
+	///
+	/// # SAFETY
+	/// * must be called from GL thread
+	pub unsafe fn try_take(self) -> Result<Vec<T>, Self> {
+		match self.is_ready() {
+			true => Ok(self.take()),
+			false => Err(self),
+		}
+	}
+
+	/// Wait for the data, blocking until the GPU has copied it
+	///
+	/// # SAFETY
+	/// * must be called from GL thread
+	pub unsafe fn wait(self) -> Vec<T> {
+		if !self.fence.is_null() {
+			// flush on the first wait so the fence is guaranteed to be signalled eventually
+			// stops on ALREADY_SIGNALED, CONDITION_SATISFIED or WAIT_FAILED
+			let mut flags = gl::SYNC_FLUSH_COMMANDS_BIT;
+			while gl::ClientWaitSync(self.fence, flags, FENCE_WAIT_TIMEOUT) == gl::TIMEOUT_EXPIRED {
+				flags = 0;
+			}
+		}
+
+		self.take()
+	}
+
+	/// # SAFETY
+	/// * must be called from GL thread
+	/// * the copy into the staging buffer must be complete
+	unsafe fn take(self) -> Vec<T> {
+		let mut data = vec![T::zeroed(); self.len];
+
+		if self.len == 0 {
+			return data
+		}
+
+		let size = self.len * mem::size_of::<T>();
+		let dsa = GlCapabilities::get().has_direct_state_access();
+
+		let mapped_region = match dsa {
+			true => gl::MapNamedBufferRange(
+				self.staging_buffer,
+				0,
+				size as GLsizeiptr,
+				gl::MAP_READ_BIT,
+			),
+			false => {
+				gl::BindBuffer(gl::COPY_READ_BUFFER, self.staging_buffer);
+				gl::MapBufferRange(gl::COPY_READ_BUFFER, 0, size as GLsizeiptr, gl::MAP_READ_BIT)
+			},
+		};
+
+		if !mapped_region.is_null() {
+			// T is AnyBitPattern, so any bytes in the buffer are a valid T
+			let data_ptr = data.as_mut_ptr() as *mut u8;
+			ptr::copy_nonoverlapping(mapped_region as *const u8, data_ptr, size);
+		}
+
+		match dsa {
+			true => gl::UnmapNamedBuffer(self.staging_buffer),
+			false => gl::UnmapBuffer(gl::COPY_READ_BUFFER),
+		};
+
+		data
+	}
+}
+
+impl<T: bytemuck::AnyBitPattern> Drop for Readback<T> {
+	fn drop(&mut self) {
+		unsafe {
+			if !self.fence.is_null() {
+				gl::DeleteSync(self.fence);
+			}
+
+			gl::DeleteBuffers(1, &self.staging_buffer);
+		}
+	}
+}
@@ -4,20 +4,34 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/./
 
-use std::ffi::c_void;
+use std::mem;
 
-use gl::types::{GLsizei, GLuint};
+use gl::types::GLuint;
 
 use super::{
-	attribute::GLtype,
+	attribute,
+	batch::DrawBatch,
 	buffer::{self, GpuBuffer},
+	encoder::Encoder,
+	index::IndexBuffer,
+	stats::{DrawStats, UploaderStats},
+	sync::FrameSync,
 	Uploader,
+	VertexAttribute,
 };
 use crate::{
 	drawable::{Drawable, DrawableData, VertexPassable},
-	shader::ShaderProgram,
+	shader::{
+		registry::{ProgramState, ShaderRegistry, SharedProgram},
+		ProgramCreateError,
+		ProgramHandle,
+		ShaderVariant,
+	},
 };
 
+#[cfg(test)]
+mod test;
+
 pub struct CompatUploader<D: Drawable> {
 	vao: GLuint,
 	vertex_buffer: Box<
@@ -28,15 +42,20 @@ pub struct CompatUploader<D: Drawable> {
 			>,
 		>,
 	>,
-	index_buffer: Box<dyn GpuBuffer<u32>>,
-	shader: ShaderProgram,
+	index_buffer: IndexBuffer,
+	batch: DrawBatch,
+	/// Submitted encoders, merged on the next flush
+	encoders: Vec<Encoder<D>>,
+	draws: DrawStats,
+	frame_sync: FrameSync,
+	shader: SharedProgram,
 }
 
 #[derive(bytemuck::Pod, bytemuck::Zeroable)]
 #[repr(C, packed)]
-struct CompatVertex<D: bytemuck::Pod, V: bytemuck::Pod> {
-	vertex: V,
-	drawable_data: D,
+pub(super) struct CompatVertex<D: bytemuck::Pod, V: bytemuck::Pod> {
+	pub vertex: V,
+	pub drawable_data: D,
 }
 
 impl<D: bytemuck::Pod, V: bytemuck::Pod> Copy for CompatVertex<D, V> {}
@@ -48,9 +67,36 @@ impl<D: bytemuck::Pod, V: bytemuck::Pod> core::clone::Clone for CompatVertex<D,
 }
 
 impl<D: Drawable> CompatUploader<D> {
+	/// Create an uploader using the thread's shared program for `D`
+	///
+	/// # SAFETY
+	/// * must be called from GL thread (shader creation)
+	pub unsafe fn new() -> Result<Self, ProgramCreateError>
+	where
+		D: 'static,
+	{
+		Ok(Self::with_program(ShaderRegistry::with_thread(|r| {
+			r.get::<D>(ShaderVariant::Compat)
+		})?))
+	}
+
+	/// Create an uploader without waiting for its shader program to compile
+	///
+	/// Nothing will be drawn until the program is finished by
+	/// `ShaderRegistry::poll`. See `Uploader::is_ready`.
+	///
 	/// # SAFETY
 	/// * must be called from GL thread (shader creation)
-	pub unsafe fn new() -> Self {
+	pub unsafe fn new_pending() -> Result<Self, ProgramCreateError>
+	where
+		D: 'static,
+	{
+		Ok(Self::with_program(ShaderRegistry::with_thread(|r| {
+			r.get_pending::<D>(ShaderVariant::Compat)
+		})?))
+	}
+
+	pub(super) fn with_program(shader: SharedProgram) -> Self {
 		Self {
 			vao: 0,
 			vertex_buffer: buffer::new::<
@@ -59,56 +105,72 @@ impl<D: Drawable> CompatUploader<D> {
 					<D::Vertex as DrawableData>::Compat,
 				>,
 			>(gl::ARRAY_BUFFER),
-			index_buffer: buffer::new::<u32>(gl::ELEMENT_ARRAY_BUFFER),
-			shader: D::SHADER_SOURCE.create_program(false),
+			index_buffer: IndexBuffer::new(),
+			batch: DrawBatch::new(),
+			encoders: Vec::new(),
+			draws: DrawStats::default(),
+			frame_sync: FrameSync::new(),
+			shader,
 		}
 	}
 
+	/// Create an encoder for this uploader, which may be filled on any thread
+	pub fn encoder(&self) -> Encoder<D> {
+		Encoder::new(ShaderVariant::Compat)
+	}
+
+	/// Queue an encoder's drawables to be merged on the next `begin_flush`,
+	/// after every drawable written with `Uploader::write`
+	///
+	/// Encoders are merged in submission order.
+	pub fn submit(&mut self, encoder: Encoder<D>) {
+		debug_assert_eq!(encoder.variant(), ShaderVariant::Compat);
+		self.encoders.push(encoder);
+	}
+
+	/// Encode `drawables` in parallel on the rayon thread pool,
+	/// then submit them in order
+	#[cfg(feature = "rayon")]
+	pub fn write_par(&mut self, drawables: &[D])
+	where
+		D: Sync,
+		Encoder<D>: Send,
+	{
+		for encoder in super::encoder::encode_par(ShaderVariant::Compat, drawables) {
+			self.submit(encoder);
+		}
+	}
+
+	/// Write an encoder's drawables, rebasing their indices
+	///
 	/// # SAFETY
-	/// * VAO, VBO and EBO must be bound
-	unsafe fn set_vertex_attributes() {
-		let stride = <D::Vertex as DrawableData>::Compat::VERTEX_ATTRIBUTES
-			.iter()
-			.chain(<D::Drawable as DrawableData>::Compat::VERTEX_ATTRIBUTES.iter())
-			.map(|a| a.padding + a.count * a.ty_size)
-			.sum::<usize>() as GLsizei;
+	/// * must have previously called `prepare_write`
+	unsafe fn merge(&mut self, encoder: &Encoder<D>) {
+		for ((vertices, indices), drawable_data) in encoder.drawables().zip(&encoder.compat_data) {
+			let combined_vertex_data = vertices.iter().map(|&vertex| CompatVertex {
+				vertex,
+				drawable_data: *drawable_data,
+			});
+
+			let vbo_index = self.vertex_buffer.len();
+			self.vertex_buffer.write().write(vbo_index, combined_vertex_data);
 
-		let mut offset = 0;
-		for (i, attribute) in <D::Vertex as DrawableData>::Compat::VERTEX_ATTRIBUTES
+			let vertices = vbo_index..self.vertex_buffer.len();
+			self.batch.write::<D>(&mut self.index_buffer, vertices, indices.iter().copied());
+		}
+	}
+
+	/// Attributes of `CompatVertex`, in location order
+	pub(super) fn vertex_attributes() -> impl Iterator<Item = &'static VertexAttribute> + Clone {
+		<D::Vertex as DrawableData>::Compat::VERTEX_ATTRIBUTES
 			.iter()
 			.chain(<D::Drawable as DrawableData>::Compat::VERTEX_ATTRIBUTES.iter())
-			.enumerate()
-		{
-			offset += attribute.padding;
-
-			if attribute.is_integer {
-				gl::VertexAttribIPointer(
-					i as u32,
-					attribute.count as GLsizei,
-					attribute.ty,
-					stride,
-					offset as *const c_void,
-				);
-			} else {
-				gl::VertexAttribPointer(
-					i as u32,
-					attribute.count as GLsizei,
-					attribute.ty,
-					gl::FALSE,
-					stride,
-					offset as *const c_void,
-				);
-			}
-
-			gl::EnableVertexAttribArray(i as u32);
-
-			offset += attribute.count * attribute.ty_size;
-		}
 	}
 }
 
 impl<D: Drawable> Uploader<D> for CompatUploader<D> {
 	unsafe fn prepare_write(&mut self) {
+		self.frame_sync.wait_for_frame();
 		self.vertex_buffer.prepare_write();
 		self.index_buffer.prepare_write();
 	}
@@ -123,14 +185,17 @@ impl<D: Drawable> Uploader<D> for CompatUploader<D> {
 		});
 
 		let vbo_index = self.vertex_buffer.len();
-		let index_data = index_data.into_iter().map(|i| vbo_index as u32 + i);
-
 		self.vertex_buffer.write().write(vbo_index, combined_vertex_data);
-		let index_buffer_len = self.index_buffer.len();
-		self.index_buffer.write().write(index_buffer_len, index_data);
+
+		let vertices = vbo_index..self.vertex_buffer.len();
+		self.batch.write::<D>(&mut self.index_buffer, vertices, index_data);
 	}
 
 	unsafe fn begin_flush(&mut self) {
+		for encoder in mem::take(&mut self.encoders) {
+			self.merge(&encoder);
+		}
+
 		self.vertex_buffer.begin_flush();
 		self.index_buffer.begin_flush();
 	}
@@ -141,52 +206,100 @@ impl<D: Drawable> Uploader<D> for CompatUploader<D> {
 	}
 
 	unsafe fn bind(&mut self) {
-		if !(self.vertex_buffer.has_backing_buffer() && self.index_buffer.has_backing_buffer()) {
+		// non-indexed drawables never write to the index buffer
+		if !(self.vertex_buffer.has_backing_buffer()
+			&& (self.index_buffer.has_backing_buffer() || !D::INDEXED))
+		{
 			return
 		}
 
 		let no_vao = self.vao == 0;
 		if no_vao {
-			gl::GenVertexArrays(1, &mut self.vao);
+			self.vao = attribute::create_vertex_array();
 		}
-		gl::BindVertexArray(self.vao);
-
-		self.vertex_buffer.bind();
-		self.index_buffer.bind();
 
 		if no_vao
 			|| self.vertex_buffer.backing_buffer_changed()
 			|| self.index_buffer.backing_buffer_changed()
 		{
-			Self::set_vertex_attributes();
+			attribute::set_vertex_attributes(
+				self.vao,
+				self.vertex_buffer.backing_buffer(),
+				buffer::attribute_offset(self.vertex_buffer.as_ref()),
+				0,
+				Self::vertex_attributes(),
+				0,
+				0,
+			);
+			attribute::set_element_buffer(self.vao, self.index_buffer.backing_buffer());
 			self.vertex_buffer.clear_buffer_changed();
 			self.index_buffer.clear_buffer_changed();
 		}
+
+		gl::BindVertexArray(self.vao);
 	}
 
 	unsafe fn upload(&mut self) {
+		if !self.is_ready() {
+			return
+		}
+
 		self.shader.bind();
 		self.bind();
 		self.sync_flush();
-		gl::DrawElements(
-			D::GL_TYPE,
-			self.index_buffer.len() as GLsizei,
-			u32::GL_TYPE,
-			0 as *const c_void,
+		let vertices = self.batch.draw::<D>(
+			&self.index_buffer,
+			self.vertex_buffer.len(),
+			buffer::base_element(self.vertex_buffer.as_ref()),
 		);
+		self.draws.record(vertices);
 		self.finish_use();
 	}
 
 	unsafe fn finish_use(&mut self) {
-		// TODO: synchronization
+		let fence = self.frame_sync.end_frame();
+		self.vertex_buffer.finish_use(&fence);
+		self.index_buffer.finish_use(&fence);
+	}
+
+	fn set_frame_latency(&mut self, frames: usize) {
+		self.frame_sync.set_frame_latency(frames);
+		let frames = self.frame_sync.frame_latency();
+		self.vertex_buffer.set_frame_latency(frames);
+		self.index_buffer.set_frame_latency(frames);
 	}
 
 	unsafe fn clear(&mut self) {
 		self.vertex_buffer.resize(0);
 		self.index_buffer.resize(0);
+		self.batch.clear();
+		self.encoders.clear();
+	}
+
+	fn shader_program(&self) -> ProgramHandle {
+		self.shader.handle()
+	}
+
+	fn program_state(&self) -> ProgramState {
+		self.shader.state()
 	}
 
-	fn shader_program(&self) -> &ShaderProgram {
-		&self.shader
+	fn stats(&self) -> UploaderStats {
+		UploaderStats {
+			draws: self.draws,
+			frame_wait: self.frame_sync.wait_time(),
+			buffers: vec![
+				("vertex", self.vertex_buffer.stats()),
+				("index", self.index_buffer.stats()),
+			],
+		}
+	}
+}
+
+impl<D: Drawable> Drop for CompatUploader<D> {
+	fn drop(&mut self) {
+		unsafe {
+			gl::DeleteVertexArrays(1, &self.vao);
+		}
 	}
 }
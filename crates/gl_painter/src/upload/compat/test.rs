@@ -0,0 +1,117 @@
+use super::CompatUploader;
+use crate::{
+	drawable::ColoredTriangle,
+	mock_gl::{self, frame, triangle},
+	shader::{registry::SharedProgram, ShaderProgram},
+	upload::Uploader,
+};
+
+fn uploader() -> CompatUploader<ColoredTriangle> {
+	mock_gl::install(3, 3);
+	CompatUploader::with_program(SharedProgram::new(ShaderProgram { program_object: 1 }))
+}
+
+#[test]
+fn test_first_frame() {
+	let mut uploader = uploader();
+
+	assert_eq!(frame(&mut uploader, &[triangle(0.0)]), [
+		"GenBuffers([1])",
+		"BindBuffer(ARRAY_BUFFER, 1)",
+		"BufferData(ARRAY_BUFFER, 72, DYNAMIC_DRAW)",
+		"BufferSubData(ARRAY_BUFFER, 0, 72)",
+		"GenBuffers([2])",
+		"BindBuffer(ELEMENT_ARRAY_BUFFER, 2)",
+		"BufferData(ELEMENT_ARRAY_BUFFER, 6, DYNAMIC_DRAW)",
+		"BufferSubData(ELEMENT_ARRAY_BUFFER, 0, 6)",
+		"UseProgram(1)",
+		"GenVertexArrays([3])",
+		"BindVertexArray(3)",
+		"BindBuffer(ARRAY_BUFFER, 1)",
+		"VertexAttribPointer(0, 2, FLOAT, 0, 24, 0)",
+		"EnableVertexAttribArray(0)",
+		"VertexAttribPointer(1, 4, FLOAT, 0, 24, 8)",
+		"EnableVertexAttribArray(1)",
+		"BindVertexArray(3)",
+		"BindBuffer(ELEMENT_ARRAY_BUFFER, 2)",
+		"BindVertexArray(3)",
+		"DrawElements(TRIANGLES, 3, UNSIGNED_SHORT, 0)",
+		"FenceSync(4)",
+	]);
+}
+
+#[test]
+fn test_later_frames() {
+	let mut uploader = uploader();
+	frame(&mut uploader, &[triangle(0.0)]);
+
+	// reallocating keeps the buffer names, so attributes are not specified again
+	assert_eq!(frame(&mut uploader, &[triangle(0.0), triangle(2.0)]), [
+		"ClientWaitSync(4, 0)",
+		"DeleteSync(4)",
+		"BindBuffer(ARRAY_BUFFER, 1)",
+		"BufferData(ARRAY_BUFFER, 144, DYNAMIC_DRAW)",
+		"BufferSubData(ARRAY_BUFFER, 0, 144)",
+		"BindBuffer(ELEMENT_ARRAY_BUFFER, 2)",
+		"BufferData(ELEMENT_ARRAY_BUFFER, 12, DYNAMIC_DRAW)",
+		"BufferSubData(ELEMENT_ARRAY_BUFFER, 0, 12)",
+		"UseProgram(1)",
+		"BindVertexArray(3)",
+		"DrawElements(TRIANGLES, 6, UNSIGNED_SHORT, 0)",
+		"FenceSync(5)",
+	]);
+
+	assert_eq!(frame(&mut uploader, &[triangle(0.0), triangle(3.0)]), [
+		"ClientWaitSync(5, 0)",
+		"DeleteSync(5)",
+		"BindBuffer(ARRAY_BUFFER, 1)",
+		"BufferSubData(ARRAY_BUFFER, 0, 144)",
+		"BindBuffer(ELEMENT_ARRAY_BUFFER, 2)",
+		"BufferSubData(ELEMENT_ARRAY_BUFFER, 0, 12)",
+		"UseProgram(1)",
+		"BindVertexArray(3)",
+		"DrawElements(TRIANGLES, 6, UNSIGNED_SHORT, 0)",
+		"FenceSync(6)",
+	]);
+
+	drop(uploader);
+	assert_eq!(mock_gl::take_calls(), [
+		"DeleteVertexArrays([3])",
+		"DeleteBuffers([1])",
+		"DeleteBuffers([2])",
+		"DeleteSync(6)",
+		"DeleteProgram(1)",
+	]);
+}
+
+#[test]
+fn test_frame_latency() {
+	let mut uploader = uploader();
+	mock_gl::set_gpu_busy(true);
+
+	// with the default latency of 2, one frame may still be in flight
+	frame(&mut uploader, &[triangle(0.0)]);
+	assert_eq!(frame(&mut uploader, &[triangle(0.0)])[..2], [
+		"ClientWaitSync(4, 0)",
+		"BindBuffer(ARRAY_BUFFER, 1)",
+	]);
+
+	// but not two
+	assert_eq!(frame(&mut uploader, &[triangle(0.0)])[..4], [
+		"ClientWaitSync(4, 0)",
+		"ClientWaitSync(4, 1000000)",
+		"DeleteSync(4)",
+		"BindBuffer(ARRAY_BUFFER, 1)",
+	]);
+
+	// with a latency of 1, every frame is waited on before writing
+	uploader.set_frame_latency(1);
+	assert_eq!(frame(&mut uploader, &[triangle(0.0)])[..6], [
+		"ClientWaitSync(5, 0)",
+		"ClientWaitSync(5, 1000000)",
+		"DeleteSync(5)",
+		"ClientWaitSync(6, 1000000)",
+		"DeleteSync(6)",
+		"BindBuffer(ARRAY_BUFFER, 1)",
+	]);
+}
@@ -0,0 +1,434 @@
+// Copyright (C) 2022 the ITK authors
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/./
+
+//! Drawing drawables of different types in z-index order
+//!
+//! A `DrawList` collects drawables of any registered type with a z-index,
+//! then draws them with as few uploads as it can while keeping painter's
+//! order. Drawables are drawn in increasing z-index, then submission order,
+//! but only where their bounding boxes overlap, so drawables of the same
+//! type that do not overlap anything in between them share a batch.
+
+use std::{
+	any::{Any, TypeId},
+	collections::{HashMap, HashSet},
+};
+
+use super::Uploader;
+use crate::{
+	drawable::{BoundingBox, Drawable},
+	shader::ProgramCreateError,
+};
+
+#[cfg(test)]
+mod test;
+
+/// Creates an uploader for a batch, e.g. `upload::new::<D>`
+pub type UploaderFactory<D> = unsafe fn() -> Result<Box<dyn Uploader<D>>, ProgramCreateError>;
+
+pub struct DrawList {
+	queues: Vec<Box<dyn AnyQueue>>,
+	queue_indices: HashMap<TypeId, usize>,
+	items: Vec<Item>,
+}
+
+/// Drawable in a `DrawList`
+#[derive(Copy, Clone)]
+struct Item {
+	z_index: i32,
+	/// Index of the drawable's type in `DrawList::queues`
+	queue: usize,
+	/// Index of the drawable in its queue
+	index: usize,
+	bounds: Option<BoundingBox>,
+}
+
+/// Items drawn by a single upload
+struct Batch {
+	queue: usize,
+	/// Indices into the batched items, in draw order
+	items: Vec<usize>,
+}
+
+impl DrawList {
+	pub fn new() -> Self {
+		Self {
+			queues: Vec::new(),
+			queue_indices: HashMap::new(),
+			items: Vec::new(),
+		}
+	}
+
+	/// Set how uploaders for `D` are created
+	///
+	/// Types pushed without being registered use `upload::new`.
+	/// Uploaders already created for `D` are dropped.
+	pub fn register<D: Drawable + 'static>(&mut self, new_uploader: UploaderFactory<D>) {
+		match self.queue_indices.get(&TypeId::of::<D>()) {
+			Some(&queue) => {
+				let queue = self.queue_mut::<D>(queue);
+				queue.new_uploader = new_uploader;
+				queue.uploaders.clear();
+			},
+			None => {
+				self.queue_indices.insert(TypeId::of::<D>(), self.queues.len());
+				self.queues.push(Box::new(Queue::new(new_uploader)));
+			},
+		}
+	}
+
+	/// Queue `drawable` to be drawn by the next `draw`
+	///
+	/// Drawables with a higher z-index are drawn above lower ones,
+	/// and drawables with the same z-index are drawn in submission order.
+	pub fn push<D: Drawable + 'static>(&mut self, drawable: D, z_index: i32) {
+		if !self.queue_indices.contains_key(&TypeId::of::<D>()) {
+			self.register::<D>(super::new::<D>);
+		}
+
+		let queue = self.queue_indices[&TypeId::of::<D>()];
+		let bounds = drawable.bounding_box();
+
+		let drawables = &mut self.queue_mut::<D>(queue).drawables;
+		let index = drawables.len();
+		drawables.push(drawable);
+
+		self.items.push(Item {
+			z_index,
+			queue,
+			index,
+			bounds,
+		});
+	}
+
+	/// Number of queued drawables
+	pub fn len(&self) -> usize {
+		self.items.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.items.is_empty()
+	}
+
+	/// Remove every queued drawable without drawing it
+	pub fn clear(&mut self) {
+		for queue in &mut self.queues {
+			queue.clear();
+		}
+
+		self.items.clear();
+	}
+
+	/// Draw and remove every queued drawable, returning the number of uploads
+	///
+	/// Every batch is written and flushed before the first is uploaded.
+	/// If an uploader can't be created, nothing is drawn and the drawables
+	/// stay queued.
+	///
+	/// # SAFETY
+	/// * must be called from GL thread
+	pub unsafe fn draw(&mut self) -> Result<usize, ProgramCreateError> {
+		let batches = batch_items(&self.items);
+
+		// uploader of each batch within its queue
+		let mut queue_batches = vec![0; self.queues.len()];
+		let slots = batches
+			.iter()
+			.map(|batch| {
+				queue_batches[batch.queue] += 1;
+				queue_batches[batch.queue] - 1
+			})
+			.collect::<Vec<_>>();
+
+		for (queue, &batch_count) in self.queues.iter_mut().zip(&queue_batches) {
+			queue.reserve_uploaders(batch_count)?;
+		}
+
+		for (batch, &slot) in batches.iter().zip(&slots) {
+			let mut indices = batch.items.iter().map(|&item| self.items[item].index);
+			self.queues[batch.queue].write_batch(slot, &mut indices);
+		}
+
+		for (batch, slot) in batches.iter().zip(slots) {
+			self.queues[batch.queue].upload_batch(slot);
+		}
+
+		self.clear();
+		Ok(batches.len())
+	}
+
+	fn queue_mut<D: Drawable + 'static>(&mut self, queue: usize) -> &mut Queue<D> {
+		self.queues[queue]
+			.as_any_mut()
+			.downcast_mut()
+			.expect("queue index should belong to the drawable type")
+	}
+}
+
+impl Default for DrawList {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// Number of items above which `batch_items` settles for a greedy order,
+/// so sets of items fit in a `u64`
+const MAX_SEARCH_ITEMS: usize = u64::BITS as usize;
+/// Number of states `batch_items` searches before settling for a greedy order
+const MAX_SEARCH_STATES: usize = 4096;
+
+/// Group items into the fewest batches of a single type, in draw order
+///
+/// Items are visited in painter's order (z-index, then submission order),
+/// and must stay in that order where they overlap. Batch types are chosen
+/// by a breadth-first search, where each batch takes every item of its type
+/// whose overlapping predecessors are already drawn. Lists of more than
+/// `MAX_SEARCH_ITEMS`, or too large to search within `MAX_SEARCH_STATES`,
+/// use the greedy order of `batch_greedy`.
+fn batch_items(items: &[Item]) -> Vec<Batch> {
+	let mut order = (0..items.len()).collect::<Vec<_>>();
+	// stable, so submission order is kept within a z-index
+	order.sort_by_key(|&item| items[item].z_index);
+
+	let greedy = batch_greedy(items, &order);
+
+	// every type needs a batch of its own
+	let queue_count = items.iter().map(|item| item.queue).collect::<HashSet<_>>().len();
+	if greedy.len() <= queue_count || items.len() > MAX_SEARCH_ITEMS {
+		return greedy
+	}
+
+	search_batches(items, &order, greedy.len() - 1).unwrap_or(greedy)
+}
+
+/// Find the fewest batches, if there are no more than `max_batches`
+///
+/// Sets of items are bitsets of positions in `order`, which must have no
+/// more than `MAX_SEARCH_ITEMS` items.
+fn search_batches(items: &[Item], order: &[usize], max_batches: usize) -> Option<Vec<Batch>> {
+	let overlapping = |a: usize, b: usize| {
+		overlaps(items[order[a]].bounds.as_ref(), items[order[b]].bounds.as_ref())
+	};
+
+	// items overlapping each item before it
+	let predecessors = (0..order.len())
+		.map(|position| {
+			(0..position)
+				.filter(|&other| overlapping(other, position))
+				.fold(0u64, |set, other| set | 1 << other)
+		})
+		.collect::<Vec<_>>();
+
+	let all_items = u64::MAX >> (MAX_SEARCH_ITEMS - order.len());
+
+	let mut queues = items.iter().map(|item| item.queue).collect::<Vec<_>>();
+	queues.sort_unstable();
+	queues.dedup();
+
+	// drawn items, with the previous state and the queue of its last batch
+	let mut states = vec![(0u64, usize::MAX, usize::MAX)];
+	let mut seen = HashSet::from([0u64]);
+	let mut layer = 0..1;
+
+	for _ in 0..max_batches {
+		let next_layer = states.len();
+
+		for state in layer {
+			let drawn = states[state].0;
+
+			for &queue in &queues {
+				let batch = take_batch(items, order, &predecessors, drawn, queue);
+				if batch == 0 {
+					continue
+				}
+
+				if drawn | batch == all_items {
+					let mut batch_queues = vec![queue];
+					let mut state = state;
+					while state != 0 {
+						batch_queues.push(states[state].2);
+						state = states[state].1;
+					}
+
+					let mut drawn = 0;
+					let batches = batch_queues.into_iter().rev().map(|queue| {
+						let batch = take_batch(items, order, &predecessors, drawn, queue);
+						drawn |= batch;
+
+						Batch {
+							queue,
+							items: (0..order.len())
+								.filter(|&position| batch & 1 << position != 0)
+								.map(|position| order[position])
+								.collect(),
+						}
+					});
+
+					return Some(batches.collect())
+				}
+
+				if seen.insert(drawn | batch) {
+					states.push((drawn | batch, state, queue));
+				}
+			}
+		}
+
+		if states.len() > MAX_SEARCH_STATES {
+			return None
+		}
+
+		layer = next_layer..states.len();
+	}
+
+	None
+}
+
+/// Get the items of `queue` that can be drawn after `drawn`
+fn take_batch(
+	items: &[Item],
+	order: &[usize],
+	predecessors: &[u64],
+	drawn: u64,
+	queue: usize,
+) -> u64 {
+	let mut batch = 0;
+
+	// predecessors come first, so items of `queue` they unblock are taken as well
+	for (position, &item) in order.iter().enumerate() {
+		if items[item].queue == queue
+			&& drawn & 1 << position == 0
+			&& predecessors[position] & !(drawn | batch) == 0
+		{
+			batch |= 1 << position;
+		}
+	}
+
+	batch
+}
+
+/// Group items into batches in painter's order
+///
+/// Each item joins the earliest batch of its type that is not before the
+/// last batch containing an item it overlaps, or else starts a new batch
+/// at the end. This does not always find the fewest batches.
+fn batch_greedy(items: &[Item], order: &[usize]) -> Vec<Batch> {
+	let mut batches = Vec::<Batch>::new();
+	// union of each batch's bounds, `None` if any item is unbounded
+	let mut batch_bounds = Vec::<Option<BoundingBox>>::new();
+
+	for &item_index in order {
+		let item = &items[item_index];
+
+		// last batch that must be drawn before the item
+		let conflict = batches.iter().zip(&batch_bounds).rposition(|(batch, bounds)| {
+			overlaps(bounds.as_ref(), item.bounds.as_ref())
+				&& batch
+					.items
+					.iter()
+					.any(|&other| overlaps(items[other].bounds.as_ref(), item.bounds.as_ref()))
+		});
+
+		let start = conflict.unwrap_or(0);
+		match batches[start..].iter().position(|batch| batch.queue == item.queue) {
+			Some(offset) => {
+				batches[start + offset].items.push(item_index);
+				let bounds = &mut batch_bounds[start + offset];
+				*bounds = match (&*bounds, &item.bounds) {
+					(Some(a), Some(b)) => Some(a.union(b)),
+					_ => None,
+				};
+			},
+			None => {
+				batches.push(Batch {
+					queue: item.queue,
+					items: vec![item_index],
+				});
+				batch_bounds.push(item.bounds);
+			},
+		}
+	}
+
+	batches
+}
+
+/// Check if two bounds overlap, where `None` overlaps everything
+fn overlaps(a: Option<&BoundingBox>, b: Option<&BoundingBox>) -> bool {
+	match (a, b) {
+		(Some(a), Some(b)) => a.overlaps(b),
+		_ => true,
+	}
+}
+
+/// Type erased `Queue`
+trait AnyQueue {
+	/// Create uploaders until there are at least `count`
+	///
+	/// # SAFETY
+	/// * must be called from GL thread
+	unsafe fn reserve_uploaders(&mut self, count: usize) -> Result<(), ProgramCreateError>;
+	/// Write the given drawables to the uploader of batch `slot`
+	///
+	/// # SAFETY
+	/// * must be called from GL thread, after `reserve_uploaders`
+	unsafe fn write_batch(&mut self, slot: usize, drawables: &mut dyn Iterator<Item = usize>);
+	/// # SAFETY
+	/// * must be called from GL thread, after `write_batch`
+	unsafe fn upload_batch(&mut self, slot: usize);
+	fn clear(&mut self);
+	fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+/// Queued drawables of one type, and the uploaders drawing them
+struct Queue<D: Drawable> {
+	new_uploader: UploaderFactory<D>,
+	/// One per batch, kept between draws to reuse their buffers
+	uploaders: Vec<Box<dyn Uploader<D>>>,
+	drawables: Vec<D>,
+}
+
+impl<D: Drawable> Queue<D> {
+	fn new(new_uploader: UploaderFactory<D>) -> Self {
+		Self {
+			new_uploader,
+			uploaders: Vec::new(),
+			drawables: Vec::new(),
+		}
+	}
+}
+
+impl<D: Drawable + 'static> AnyQueue for Queue<D> {
+	unsafe fn reserve_uploaders(&mut self, count: usize) -> Result<(), ProgramCreateError> {
+		while self.uploaders.len() < count {
+			self.uploaders.push((self.new_uploader)()?);
+		}
+
+		Ok(())
+	}
+
+	unsafe fn write_batch(&mut self, slot: usize, drawables: &mut dyn Iterator<Item = usize>) {
+		let uploader = &mut self.uploaders[slot];
+		uploader.prepare_write();
+		uploader.clear();
+
+		for drawable in drawables {
+			uploader.write(&self.drawables[drawable]);
+		}
+
+		uploader.begin_flush();
+	}
+
+	unsafe fn upload_batch(&mut self, slot: usize) {
+		self.uploaders[slot].upload();
+	}
+
+	fn clear(&mut self) {
+		self.drawables.clear();
+	}
+
+	fn as_any_mut(&mut self) -> &mut dyn Any {
+		self
+	}
+}
@@ -0,0 +1,151 @@
+use gl::types::GLenum;
+
+use super::{batch_items, DrawList, Item};
+use crate::{
+	drawable::{BoundingBox, ColoredTriangle, Drawable, ShaderSource},
+	mock_gl::{self, triangle},
+	shader::{
+		registry::SharedProgram,
+		ProgramCreateError,
+		ShaderLinkError,
+		ShaderProgram,
+		ShaderVariant,
+	},
+	upload::{compat::CompatUploader, Uploader},
+};
+
+fn item(queue: usize, z_index: i32, bounds: Option<([f32; 2], [f32; 2])>) -> Item {
+	Item {
+		z_index,
+		queue,
+		index: 0,
+		bounds: bounds.map(|(min, max)| BoundingBox { min, max }),
+	}
+}
+
+/// Batches as (queue, item indices)
+fn batches(items: &[Item]) -> Vec<(usize, Vec<usize>)> {
+	batch_items(items).into_iter().map(|batch| (batch.queue, batch.items)).collect()
+}
+
+#[test]
+fn test_disjoint_reorder() {
+	let a = Some(([0.0, 0.0], [1.0, 1.0]));
+	let b = Some(([2.0, 0.0], [3.0, 1.0]));
+	let c = Some(([4.0, 0.0], [5.0, 1.0]));
+
+	// alternating types that never overlap are merged into one batch per type
+	let items = [item(0, 0, a), item(1, 0, b), item(0, 0, c)];
+	assert_eq!(batches(&items), [(0, vec![0, 2]), (1, vec![1])]);
+}
+
+#[test]
+fn test_overlap_order() {
+	let a = Some(([0.0, 0.0], [2.0, 2.0]));
+	let b = Some(([1.0, 1.0], [3.0, 3.0]));
+	let c = Some(([2.5, 2.5], [4.0, 4.0]));
+
+	// c overlaps b, which overlaps a, so painter's order is kept
+	let items = [item(0, 0, a), item(1, 0, b), item(0, 0, c)];
+	assert_eq!(batches(&items), [(0, vec![0]), (1, vec![1]), (0, vec![2])]);
+
+	// unbounded items overlap everything
+	let items = [item(0, 0, a), item(1, 0, None), item(0, 0, c)];
+	assert_eq!(batches(&items), [(0, vec![0]), (1, vec![1]), (0, vec![2])]);
+}
+
+#[test]
+fn test_z_index() {
+	let a = Some(([0.0, 0.0], [2.0, 2.0]));
+
+	// drawn by z-index, then submission order
+	let items = [item(0, 1, a), item(1, 0, a), item(0, 0, a), item(1, 1, a)];
+	assert_eq!(batches(&items), [(1, vec![1]), (0, vec![2, 0]), (1, vec![3])]);
+}
+
+#[test]
+fn test_join_after_conflict() {
+	let a = Some(([0.0, 0.0], [1.0, 1.0]));
+	let b = Some(([2.0, 0.0], [3.0, 1.0]));
+
+	// the last item must be drawn after the first batch,
+	// so it joins the next batch of its type instead of starting one
+	let items = [item(0, 0, a), item(1, 0, b), item(0, 0, b), item(1, 0, a)];
+	assert_eq!(batches(&items), [(0, vec![0]), (1, vec![1, 3]), (0, vec![2])]);
+}
+
+#[test]
+fn test_fewest_batches() {
+	let a = Some(([2.0, 0.0], [3.0, 1.0]));
+	let b = Some(([0.0, 0.0], [1.0, 1.0]));
+	let c = Some(([0.5, 0.0], [1.5, 1.0]));
+
+	// drawing a first would put c in a third batch, as it must follow b
+	let items = [item(0, 0, a), item(1, 0, b), item(0, 0, c)];
+	assert_eq!(batches(&items), [(1, vec![1]), (0, vec![0, 2])]);
+}
+
+#[test]
+fn test_long_list() {
+	// unbounded items all overlap, so the list is too long to search
+	let items = (0..5000).map(|i| item(i % 2, 0, None)).collect::<Vec<_>>();
+	assert_eq!(batch_items(&items).len(), 5000);
+}
+
+/// Triangle drawn by a second queue, whose uploaders can't be created
+struct FailingTriangle(ColoredTriangle);
+
+impl Drawable for FailingTriangle {
+	type Drawable = <ColoredTriangle as Drawable>::Drawable;
+	type Vertex = <ColoredTriangle as Drawable>::Vertex;
+
+	const GL_TYPE: GLenum = ColoredTriangle::GL_TYPE;
+	const SHADER_SOURCE: ShaderSource = ColoredTriangle::SHADER_SOURCE;
+
+	fn drawable_data(&self) -> Self::Drawable {
+		self.0.drawable_data()
+	}
+
+	fn drawable_vertices<'s>(
+		&'s self,
+	) -> (
+		impl IntoIterator<
+			Item = Self::Vertex,
+			IntoIter = impl ExactSizeIterator<Item = Self::Vertex> + 's,
+		>,
+		impl IntoIterator<Item = u32, IntoIter = impl ExactSizeIterator<Item = u32> + 's>,
+	) {
+		self.0.drawable_vertices()
+	}
+}
+
+unsafe fn compat_uploader() -> Result<Box<dyn Uploader<ColoredTriangle>>, ProgramCreateError> {
+	let program = SharedProgram::new(ShaderProgram { program_object: 1 });
+	Ok(Box::new(CompatUploader::with_program(program)))
+}
+
+unsafe fn failing_uploader() -> Result<Box<dyn Uploader<FailingTriangle>>, ProgramCreateError> {
+	Err(ProgramCreateError::Link {
+		variant: ShaderVariant::Compat,
+		vertex_source: String::new(),
+		fragment_source: String::new(),
+		error: ShaderLinkError::CouldNotCreate,
+	})
+}
+
+#[test]
+fn test_uploader_error() {
+	mock_gl::install(3, 3);
+
+	let mut list = DrawList::new();
+	list.register::<ColoredTriangle>(compat_uploader);
+	list.register::<FailingTriangle>(failing_uploader);
+	list.push(triangle(0.0), 0);
+	list.push(FailingTriangle(triangle(0.0)), 0);
+	list.push(triangle(0.0), 0);
+
+	// the first batch is not written before the second batch's uploader fails
+	assert!(unsafe { list.draw() }.is_err());
+	assert!(!mock_gl::take_calls().iter().any(|call| call.starts_with("Buffer")));
+	assert_eq!(list.len(), 3);
+}
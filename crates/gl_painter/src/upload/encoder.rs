@@ -0,0 +1,145 @@
+// Copyright (C) 2022 the ITK authors
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/./
+
+//! Encoding drawables off the GL thread
+//!
+//! An `Encoder` does the CPU side of `Uploader::write` (vertex generation
+//! and conversion to the shader variant's layout) without touching GL, so
+//! it can be filled on any thread. Filled encoders are submitted to a
+//! `CompatUploader` or `SsboUploader`, which merge them into their buffers,
+//! rebasing indices, in `begin_flush`.
+
+use crate::{
+	drawable::{Drawable, DrawableData},
+	shader::ShaderVariant,
+};
+
+/// Vertex and index counts of an encoded drawable
+#[derive(Copy, Clone)]
+pub(super) struct Span {
+	pub vertex_count: usize,
+	pub index_count: usize,
+}
+
+/// Shard of encoded drawables for an uploader using `variant`
+///
+/// Indices are relative to each drawable's own vertices.
+pub struct Encoder<D: Drawable> {
+	variant: ShaderVariant,
+	pub(super) vertices: Vec<<D::Vertex as DrawableData>::Compat>,
+	pub(super) indices: Vec<u32>,
+	pub(super) spans: Vec<Span>,
+	/// Only filled for the compat variant
+	pub(super) compat_data: Vec<<D::Drawable as DrawableData>::Compat>,
+	/// Only filled for the SSBO variant
+	pub(super) ssbo_data: Vec<<D::Drawable as DrawableData>::Ssbo>,
+}
+
+impl<D: Drawable> Encoder<D> {
+	pub fn new(variant: ShaderVariant) -> Self {
+		Self {
+			variant,
+			vertices: Vec::new(),
+			indices: Vec::new(),
+			spans: Vec::new(),
+			compat_data: Vec::new(),
+			ssbo_data: Vec::new(),
+		}
+	}
+
+	pub fn variant(&self) -> ShaderVariant {
+		self.variant
+	}
+
+	/// Number of encoded drawables
+	pub fn len(&self) -> usize {
+		self.spans.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.spans.is_empty()
+	}
+
+	pub fn encode(&mut self, drawable: &D) {
+		let (vertex_data, index_data) = drawable.drawable_vertices();
+		let (vertex_data, index_data) = (vertex_data.into_iter(), index_data.into_iter());
+
+		let span = Span {
+			vertex_count: vertex_data.len(),
+			index_count: match D::INDEXED {
+				true => index_data.len(),
+				false => 0,
+			},
+		};
+
+		self.vertices.extend(vertex_data.map(|vertex| vertex.into_compat()));
+
+		if D::INDEXED {
+			self.indices.extend(index_data);
+		}
+
+		self.spans.push(span);
+
+		match self.variant {
+			ShaderVariant::Compat => self.compat_data.push(drawable.drawable_data().into_compat()),
+			ShaderVariant::Ssbo => self.ssbo_data.push(drawable.drawable_data().into_ssbo()),
+		}
+	}
+
+	/// Remove every encoded drawable, keeping allocations
+	pub fn clear(&mut self) {
+		self.vertices.clear();
+		self.indices.clear();
+		self.spans.clear();
+		self.compat_data.clear();
+		self.ssbo_data.clear();
+	}
+
+	/// Iterate over each drawable's span with its vertices and indices
+	pub(super) fn drawables(
+		&self,
+	) -> impl Iterator<Item = (&[<D::Vertex as DrawableData>::Compat], &[u32])> {
+		let (mut vertex_start, mut index_start) = (0, 0);
+
+		self.spans.iter().map(move |span| {
+			let vertices = &self.vertices[vertex_start..vertex_start + span.vertex_count];
+			let indices = &self.indices[index_start..index_start + span.index_count];
+
+			vertex_start += span.vertex_count;
+			index_start += span.index_count;
+
+			(vertices, indices)
+		})
+	}
+}
+
+/// Encode `drawables` in parallel, returning one encoder per chunk in order
+#[cfg(feature = "rayon")]
+pub fn encode_par<D>(variant: ShaderVariant, drawables: &[D]) -> Vec<Encoder<D>>
+where
+	D: Drawable + Sync,
+	Encoder<D>: Send,
+{
+	use rayon::prelude::*;
+
+	/// Smallest chunk worth sending to another thread
+	const MIN_CHUNK_LEN: usize = 256;
+
+	let chunk_len = (drawables.len() / rayon::current_num_threads()).max(MIN_CHUNK_LEN);
+
+	drawables
+		.par_chunks(chunk_len)
+		.map(|chunk| {
+			let mut encoder = Encoder::new(variant);
+
+			for drawable in chunk {
+				encoder.encode(drawable);
+			}
+
+			encoder
+		})
+		.collect()
+}
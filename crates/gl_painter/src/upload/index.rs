@@ -0,0 +1,290 @@
+// Copyright (C) 2022 the ITK authors
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/./
+
+//! Index buffers using the smallest index type fitting their vertices
+
+use std::rc::Rc;
+
+use gl::types::{GLenum, GLuint};
+
+use super::{
+	attribute::GLtype,
+	buffer::{self, growth::BufferStats, GpuBuffer},
+	sync::{Fence, DEFAULT_FRAME_LATENCY},
+};
+use crate::GlCapabilities;
+
+#[cfg(test)]
+mod test;
+
+/// Type of the elements of an index buffer
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum IndexType {
+	U8,
+	U16,
+	U32,
+}
+
+impl IndexType {
+	/// Smallest index type able to address `count` vertices
+	///
+	/// The largest index of each type is reserved for primitive restart.
+	pub fn for_vertex_count(count: usize) -> Self {
+		match count {
+			0..=0xff => Self::U8,
+			0x100..=0xffff => Self::U16,
+			_ => Self::U32,
+		}
+	}
+
+	/// Index restarting primitives when primitive restart is enabled
+	pub fn restart_index(self) -> u32 {
+		match self {
+			Self::U8 => u8::MAX as u32,
+			Self::U16 => u16::MAX as u32,
+			Self::U32 => u32::MAX,
+		}
+	}
+
+	pub fn gl_type(self) -> GLenum {
+		match self {
+			Self::U8 => u8::GL_TYPE,
+			Self::U16 => u16::GL_TYPE,
+			Self::U32 => u32::GL_TYPE,
+		}
+	}
+
+	/// Size of an index in bytes
+	pub fn size(self) -> usize {
+		match self {
+			Self::U8 => 1,
+			Self::U16 => 2,
+			Self::U32 => 4,
+		}
+	}
+}
+
+/// Index written to an `IndexBuffer` to restart primitives,
+/// converted to the restart index of the buffer's index type
+pub const RESTART_INDEX: u32 = u32::MAX;
+
+/// Integer usable as an index
+pub trait Index: bytemuck::AnyBitPattern + GLtype {
+	/// Truncates indices that don't fit, so `RESTART_INDEX` becomes the type's maximum
+	fn from_u32(index: u32) -> Self;
+	/// Converts the type's maximum to `RESTART_INDEX`
+	fn to_u32(self) -> u32;
+}
+
+macro_rules! index_types {
+	($($type:ident),*) => {
+		$(
+			impl Index for $type {
+				fn from_u32(index: u32) -> Self {
+					index as $type
+				}
+
+				fn to_u32(self) -> u32 {
+					match self {
+						$type::MAX => RESTART_INDEX,
+						index => index as u32,
+					}
+				}
+			}
+		)*
+	}
+}
+
+index_types!(u8, u16, u32);
+
+enum Indices {
+	U8(Box<dyn GpuBuffer<u8>>),
+	U16(Box<dyn GpuBuffer<u16>>),
+	U32(Box<dyn GpuBuffer<u32>>),
+}
+
+/// Run `$body` with `$buffer` bound to the typed buffer of `$indices`
+macro_rules! with_buffer {
+	($indices:expr, $buffer:ident => $body:expr) => {
+		match $indices {
+			Indices::U8($buffer) => $body,
+			Indices::U16($buffer) => $body,
+			Indices::U32($buffer) => $body,
+		}
+	};
+}
+
+/// Element array buffer switching to a larger index type when
+/// its vertices no longer fit in the current one
+///
+/// Indices only ever get larger, as switching type reallocates the buffer.
+/// Buffers start with `u16` indices, as many drivers convert `u8` indices
+/// on the CPU. Use `with_type` to start with `u8` indices anyway.
+pub struct IndexBuffer {
+	indices: Indices,
+	/// Applied to the buffer of each new index type
+	frame_latency: usize,
+}
+
+impl IndexBuffer {
+	pub fn new() -> Self {
+		Self::with_type(IndexType::U16)
+	}
+
+	pub fn with_type(index_type: IndexType) -> Self {
+		Self {
+			indices: new_indices(index_type),
+			frame_latency: DEFAULT_FRAME_LATENCY,
+		}
+	}
+
+	pub fn index_type(&self) -> IndexType {
+		match self.indices {
+			Indices::U8(_) => IndexType::U8,
+			Indices::U16(_) => IndexType::U16,
+			Indices::U32(_) => IndexType::U32,
+		}
+	}
+
+	/// Switch to a larger index type if needed to address `count` vertices
+	///
+	/// # SAFETY
+	/// * must have previously called `prepare_write`
+	pub unsafe fn fit_vertices(&mut self, count: usize) {
+		let index_type = IndexType::for_vertex_count(count);
+
+		if index_type <= self.index_type() {
+			return
+		}
+
+		let old = with_buffer!(&mut self.indices, b => {
+			b.write().data().iter().map(|i| i.to_u32()).collect::<Vec<_>>()
+		});
+
+		self.indices = new_indices(index_type);
+		self.set_frame_latency(self.frame_latency);
+		self.prepare_write();
+		self.write(0, old);
+	}
+
+	/// Write indices starting at `offset`
+	///
+	/// Indices must fit in the index type, see `fit_vertices`.
+	///
+	/// # SAFETY
+	/// * must have previously called `prepare_write`
+	pub unsafe fn write(
+		&mut self,
+		offset: usize,
+		indices: impl IntoIterator<IntoIter = impl ExactSizeIterator<Item = u32>>,
+	) {
+		let indices = indices.into_iter();
+		with_buffer!(&mut self.indices, b => b.write().write(offset, indices.map(Index::from_u32)))
+	}
+
+	/// # SAFETY
+	/// * must be called from GL thread
+	pub unsafe fn prepare_write(&mut self) {
+		with_buffer!(&mut self.indices, b => b.prepare_write())
+	}
+
+	/// # SAFETY
+	/// * must be called from GL thread
+	pub unsafe fn begin_flush(&mut self) {
+		with_buffer!(&mut self.indices, b => b.begin_flush())
+	}
+
+	/// # SAFETY
+	/// * must be called from GL thread
+	pub unsafe fn sync_flush(&mut self) {
+		with_buffer!(&mut self.indices, b => b.sync_flush())
+	}
+
+	pub fn resize(&mut self, size: usize) {
+		with_buffer!(&mut self.indices, b => b.resize(size))
+	}
+
+	pub fn len(&self) -> usize {
+		with_buffer!(&self.indices, b => b.len())
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	pub fn has_backing_buffer(&self) -> bool {
+		with_buffer!(&self.indices, b => b.has_backing_buffer())
+	}
+
+	pub fn backing_buffer_changed(&self) -> bool {
+		with_buffer!(&self.indices, b => b.backing_buffer_changed())
+	}
+
+	pub fn clear_buffer_changed(&mut self) {
+		with_buffer!(&mut self.indices, b => b.clear_buffer_changed())
+	}
+
+	pub fn backing_buffer(&self) -> GLuint {
+		with_buffer!(&self.indices, b => b.backing_buffer())
+	}
+
+	pub fn backing_offset(&self) -> usize {
+		with_buffer!(&self.indices, b => b.backing_offset())
+	}
+
+	/// # SAFETY
+	/// * must be called from GL thread
+	pub unsafe fn finish_use(&mut self, fence: &Rc<Fence>) {
+		with_buffer!(&mut self.indices, b => b.finish_use(fence))
+	}
+
+	pub fn set_frame_latency(&mut self, frames: usize) {
+		self.frame_latency = frames;
+		with_buffer!(&mut self.indices, b => b.set_frame_latency(frames))
+	}
+
+	/// Statistics of the current buffer, which are reset when the index type changes
+	pub fn stats(&self) -> BufferStats {
+		with_buffer!(&self.indices, b => b.stats())
+	}
+}
+
+impl Default for IndexBuffer {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+fn new_indices(index_type: IndexType) -> Indices {
+	match index_type {
+		IndexType::U8 => Indices::U8(buffer::new(gl::ELEMENT_ARRAY_BUFFER)),
+		IndexType::U16 => Indices::U16(buffer::new(gl::ELEMENT_ARRAY_BUFFER)),
+		IndexType::U32 => Indices::U32(buffer::new(gl::ELEMENT_ARRAY_BUFFER)),
+	}
+}
+
+/// Enable primitive restart at the restart index of `index_type`
+///
+/// # SAFETY
+/// * must be called from GL thread
+pub unsafe fn enable_primitive_restart(index_type: IndexType) {
+	match GlCapabilities::get().has_primitive_restart_fixed_index() {
+		true => gl::Enable(gl::PRIMITIVE_RESTART_FIXED_INDEX),
+		false => {
+			gl::Enable(gl::PRIMITIVE_RESTART);
+			gl::PrimitiveRestartIndex(index_type.restart_index());
+		},
+	}
+}
+
+/// # SAFETY
+/// * must be called from GL thread
+pub unsafe fn disable_primitive_restart() {
+	match GlCapabilities::get().has_primitive_restart_fixed_index() {
+		true => gl::Disable(gl::PRIMITIVE_RESTART_FIXED_INDEX),
+		false => gl::Disable(gl::PRIMITIVE_RESTART),
+	}
+}
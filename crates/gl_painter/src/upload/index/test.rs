@@ -0,0 +1,22 @@
+use super::{Index, IndexType, RESTART_INDEX};
+
+#[test]
+fn test_index_type() {
+	assert_eq!(IndexType::for_vertex_count(0), IndexType::U8);
+	// the largest index is reserved for primitive restart
+	assert_eq!(IndexType::for_vertex_count(255), IndexType::U8);
+	assert_eq!(IndexType::for_vertex_count(256), IndexType::U16);
+	assert_eq!(IndexType::for_vertex_count(65535), IndexType::U16);
+	assert_eq!(IndexType::for_vertex_count(65536), IndexType::U32);
+
+	assert!(IndexType::U8 < IndexType::U16 && IndexType::U16 < IndexType::U32);
+	assert_eq!(IndexType::U16.size(), 2);
+}
+
+#[test]
+fn test_restart_conversion() {
+	assert_eq!(u16::from_u32(RESTART_INDEX), u16::MAX);
+	assert_eq!(u16::MAX.to_u32(), RESTART_INDEX);
+	assert_eq!(u8::from_u32(254).to_u32(), 254);
+	assert_eq!(IndexType::U16.restart_index(), 0xffff);
+}
@@ -0,0 +1,548 @@
+// Copyright (C) 2022 the ITK authors
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/./
+
+//! Uploader batching compatible drawable types into indirect draws
+
+use std::{ffi::c_void, mem, rc::Rc};
+
+use gl::types::{GLenum, GLsizei, GLuint};
+
+use super::{
+	attribute::{self, GLtype},
+	buffer::{self, GpuBuffer},
+	compat::{CompatUploader, CompatVertex},
+	ssbo::{SsboUploader, SsboVertex},
+	stats::{BufferStats, DrawStats, UploaderStats},
+	sync::{Fence, FrameSync},
+	Uploader,
+};
+use crate::{
+	drawable::{Drawable, DrawableData},
+	shader::{
+		registry::{ProgramState, ShaderRegistry, SharedProgram},
+		ProgramCreateError,
+		ProgramHandle,
+		ShaderVariant,
+	},
+	GlCapabilities,
+};
+
+/// Layout of a single command in a `GL_DRAW_INDIRECT_BUFFER`
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+pub struct DrawElementsIndirectCommand {
+	pub count: u32,
+	pub instance_count: u32,
+	pub first_index: u32,
+	pub base_vertex: i32,
+	pub base_instance: u32,
+}
+
+type VertexCompat<D> = <<D as Drawable>::Vertex as DrawableData>::Compat;
+type DrawableCompat<D> = <<D as Drawable>::Drawable as DrawableData>::Compat;
+type DrawableSsbo<D> = <<D as Drawable>::Drawable as DrawableData>::Ssbo;
+
+enum Backend<D: Drawable> {
+	/// GL 4.3+, drawn with `glMultiDrawElementsIndirect`
+	Indirect {
+		vertex_buffer: Box<dyn GpuBuffer<SsboVertex<VertexCompat<D>>>>,
+		storage_buffer: Box<dyn GpuBuffer<DrawableSsbo<D>>>,
+		command_buffer: Box<dyn GpuBuffer<DrawElementsIndirectCommand>>,
+	},
+	/// Drawn with a `glDrawElementsBaseVertex` per command
+	Fallback {
+		vertex_buffer: Box<dyn GpuBuffer<CompatVertex<DrawableCompat<D>, VertexCompat<D>>>>,
+	},
+}
+
+impl<D: Drawable> Backend<D> {
+	fn has_vertex_buffer(&self) -> bool {
+		match self {
+			Self::Indirect { vertex_buffer, .. } => vertex_buffer.has_backing_buffer(),
+			Self::Fallback { vertex_buffer } => vertex_buffer.has_backing_buffer(),
+		}
+	}
+
+	fn vertex_buffer_changed(&self) -> bool {
+		match self {
+			Self::Indirect { vertex_buffer, .. } => vertex_buffer.backing_buffer_changed(),
+			Self::Fallback { vertex_buffer } => vertex_buffer.backing_buffer_changed(),
+		}
+	}
+
+	/// Statistics of every buffer of the backend, by name
+	fn buffer_stats(&self) -> Vec<(&'static str, BufferStats)> {
+		match self {
+			Self::Indirect {
+				vertex_buffer,
+				storage_buffer,
+				command_buffer,
+			} => vec![
+				("vertex", vertex_buffer.stats()),
+				("storage", storage_buffer.stats()),
+				("command", command_buffer.stats()),
+			],
+			Self::Fallback { vertex_buffer } => vec![("vertex", vertex_buffer.stats())],
+		}
+	}
+
+	/// # SAFETY
+	/// * must be called from GL thread
+	unsafe fn finish_use(&mut self, fence: &Rc<Fence>) {
+		match self {
+			Self::Indirect {
+				vertex_buffer,
+				storage_buffer,
+				command_buffer,
+			} => {
+				vertex_buffer.finish_use(fence);
+				storage_buffer.finish_use(fence);
+				command_buffer.finish_use(fence);
+			},
+			Self::Fallback { vertex_buffer } => vertex_buffer.finish_use(fence),
+		}
+	}
+
+	fn set_frame_latency(&mut self, frames: usize) {
+		match self {
+			Self::Indirect {
+				vertex_buffer,
+				storage_buffer,
+				command_buffer,
+			} => {
+				vertex_buffer.set_frame_latency(frames);
+				storage_buffer.set_frame_latency(frames);
+				command_buffer.set_frame_latency(frames);
+			},
+			Self::Fallback { vertex_buffer } => vertex_buffer.set_frame_latency(frames),
+		}
+	}
+
+	fn clear_vertex_buffer_changed(&mut self) {
+		match self {
+			Self::Indirect { vertex_buffer, .. } => vertex_buffer.clear_buffer_changed(),
+			Self::Fallback { vertex_buffer } => vertex_buffer.clear_buffer_changed(),
+		}
+	}
+
+	/// # SAFETY
+	/// * must be called from GL thread
+	unsafe fn set_vertex_attributes(&self, vao: GLuint) {
+		match self {
+			Self::Indirect { vertex_buffer, .. } => attribute::set_vertex_attributes(
+				vao,
+				vertex_buffer.backing_buffer(),
+				buffer::attribute_offset(vertex_buffer.as_ref()),
+				0,
+				SsboUploader::<D>::vertex_attributes(),
+				0,
+				0,
+			),
+			Self::Fallback { vertex_buffer } => attribute::set_vertex_attributes(
+				vao,
+				vertex_buffer.backing_buffer(),
+				buffer::attribute_offset(vertex_buffer.as_ref()),
+				0,
+				CompatUploader::<D>::vertex_attributes(),
+				0,
+				0,
+			),
+		}
+	}
+}
+
+/// Uploader sharing one vertex and index arena between every drawable
+/// type compatible with `D`
+///
+/// Compatible types have the same vertex and drawable data and shader
+/// sources as `D`, as they are all drawn with `D`'s program. Each drawable
+/// becomes one draw command, and commands are submitted with a single
+/// `glMultiDrawElementsIndirect` per primitive type on GL 4.3+, or a loop
+/// of draws on older contexts.
+pub struct IndirectUploader<D: Drawable> {
+	vao: GLuint,
+	backend: Backend<D>,
+	index_buffer: Box<dyn GpuBuffer<u32>>,
+	/// Commands with their primitive type, in write order
+	commands: Vec<(GLenum, DrawElementsIndirectCommand)>,
+	draws: DrawStats,
+	frame_sync: FrameSync,
+	shader: SharedProgram,
+}
+
+impl<D: Drawable> IndirectUploader<D> {
+	/// Create an uploader using the thread's shared program for `D`
+	///
+	/// Uses indirect draws if the context supports multi-draw-indirect
+	/// and the SSBO variant of `D`'s shader can be created.
+	///
+	/// # SAFETY
+	/// * must be called from GL thread (shader creation)
+	pub unsafe fn new() -> Result<Self, ProgramCreateError>
+	where
+		D: 'static,
+	{
+		if Self::can_draw_indirect() {
+			if let Ok(shader) = ShaderRegistry::with_thread(|r| r.get::<D>(ShaderVariant::Ssbo)) {
+				return Ok(Self::with_backend(shader, Self::indirect_backend()))
+			}
+		}
+
+		let shader = ShaderRegistry::with_thread(|r| r.get::<D>(ShaderVariant::Compat))?;
+		Ok(Self::with_backend(shader, Self::fallback_backend()))
+	}
+
+	/// Create an uploader without waiting for its shader program to compile
+	///
+	/// Unlike `new`, the backend is chosen only by the context's capabilities,
+	/// as the SSBO variant's success is not known yet.
+	/// Nothing will be drawn until the program is finished by
+	/// `ShaderRegistry::poll`. See `Uploader::is_ready`.
+	///
+	/// # SAFETY
+	/// * must be called from GL thread (shader creation)
+	pub unsafe fn new_pending() -> Result<Self, ProgramCreateError>
+	where
+		D: 'static,
+	{
+		Ok(match Self::can_draw_indirect() {
+			true => Self::with_backend(
+				ShaderRegistry::with_thread(|r| r.get_pending::<D>(ShaderVariant::Ssbo))?,
+				Self::indirect_backend(),
+			),
+			false => Self::with_backend(
+				ShaderRegistry::with_thread(|r| r.get_pending::<D>(ShaderVariant::Compat))?,
+				Self::fallback_backend(),
+			),
+		})
+	}
+
+	/// # SAFETY
+	/// * must be called from GL thread
+	unsafe fn can_draw_indirect() -> bool {
+		let capabilities = GlCapabilities::get();
+		capabilities.has_multi_draw_indirect() && capabilities.has_shader_storage_buffers()
+	}
+
+	fn indirect_backend() -> Backend<D> {
+		Backend::Indirect {
+			vertex_buffer: buffer::new::<SsboVertex<VertexCompat<D>>>(gl::ARRAY_BUFFER),
+			storage_buffer: buffer::new::<DrawableSsbo<D>>(gl::SHADER_STORAGE_BUFFER),
+			command_buffer: buffer::new::<DrawElementsIndirectCommand>(gl::DRAW_INDIRECT_BUFFER),
+		}
+	}
+
+	fn fallback_backend() -> Backend<D> {
+		Backend::Fallback {
+			vertex_buffer: buffer::new::<CompatVertex<DrawableCompat<D>, VertexCompat<D>>>(
+				gl::ARRAY_BUFFER,
+			),
+		}
+	}
+
+	fn with_backend(shader: SharedProgram, backend: Backend<D>) -> Self {
+		Self {
+			vao: 0,
+			backend,
+			index_buffer: buffer::new::<u32>(gl::ELEMENT_ARRAY_BUFFER),
+			commands: Vec::new(),
+			draws: DrawStats::default(),
+			frame_sync: FrameSync::new(),
+			shader,
+		}
+	}
+
+	/// Check if commands are submitted with `glMultiDrawElementsIndirect`
+	pub fn is_indirect(&self) -> bool {
+		matches!(self.backend, Backend::Indirect { .. })
+	}
+
+	/// Write a drawable of any type compatible with `D`
+	///
+	/// Every drawable is its own command, so strips need no primitive
+	/// restart, but drawables must be indexed.
+	///
+	/// `E` must have the same `SHADER_SOURCE` as `D`, as it is drawn with
+	/// `D`'s program. Drawables with another shader need their own uploader.
+	///
+	/// # SAFETY
+	/// * must call prepare_write before calling write_compatible
+	pub unsafe fn write_compatible<E>(&mut self, drawable: &E)
+	where
+		E: Drawable<Vertex = D::Vertex, Drawable = D::Drawable>,
+	{
+		const { assert!(E::INDEXED, "indirect uploaders do not support non-indexed drawables") };
+		assert!(
+			E::SHADER_SOURCE.same_sources(&D::SHADER_SOURCE),
+			"compatible drawables must have the same shader sources as the uploader's"
+		);
+
+		let (vertex_data, index_data) = drawable.drawable_vertices();
+		let (vertex_data, index_data) = (vertex_data.into_iter(), index_data.into_iter());
+
+		// indices are not rebased, the command's base vertex is used instead
+		let first_index = self.index_buffer.len();
+		let index_count = index_data.len();
+		self.index_buffer.write().write(first_index, index_data);
+
+		let base_vertex = match &mut self.backend {
+			Backend::Indirect {
+				vertex_buffer,
+				storage_buffer,
+				..
+			} => {
+				let ssbo_index = storage_buffer.len();
+				storage_buffer.write().write(ssbo_index, [drawable.drawable_data().into_ssbo()]);
+
+				let base_vertex = vertex_buffer.len();
+				vertex_buffer.write().write(
+					base_vertex,
+					vertex_data.map(|vertex| SsboVertex {
+						vertex: vertex.into_compat(),
+						ssbo_index: ssbo_index as u32,
+					}),
+				);
+
+				base_vertex
+			},
+			Backend::Fallback { vertex_buffer } => {
+				let drawable_data = drawable.drawable_data().into_compat();
+
+				let base_vertex = vertex_buffer.len();
+				vertex_buffer.write().write(
+					base_vertex,
+					vertex_data.map(|vertex| CompatVertex {
+						vertex: vertex.into_compat(),
+						drawable_data,
+					}),
+				);
+
+				base_vertex
+			},
+		};
+
+		self.commands.push((E::GL_TYPE, DrawElementsIndirectCommand {
+			count: index_count as u32,
+			instance_count: 1,
+			first_index: first_index as u32,
+			base_vertex: base_vertex as i32,
+			base_instance: 0,
+		}));
+	}
+}
+
+impl<D: Drawable> Uploader<D> for IndirectUploader<D> {
+	unsafe fn prepare_write(&mut self) {
+		self.frame_sync.wait_for_frame();
+		self.index_buffer.prepare_write();
+
+		match &mut self.backend {
+			Backend::Indirect {
+				vertex_buffer,
+				storage_buffer,
+				command_buffer,
+			} => {
+				vertex_buffer.prepare_write();
+				storage_buffer.prepare_write();
+				command_buffer.prepare_write();
+			},
+			Backend::Fallback { vertex_buffer } => vertex_buffer.prepare_write(),
+		}
+	}
+
+	unsafe fn write(&mut self, drawable: &D) {
+		self.write_compatible(drawable);
+	}
+
+	unsafe fn begin_flush(&mut self) {
+		self.index_buffer.begin_flush();
+
+		match &mut self.backend {
+			Backend::Indirect {
+				vertex_buffer,
+				storage_buffer,
+				command_buffer,
+			} => {
+				vertex_buffer.begin_flush();
+				storage_buffer.begin_flush();
+
+				// commands address the buffers' current data, known once they are flushed
+				let base_vertex = buffer::base_element(vertex_buffer.as_ref());
+				let first_index = buffer::base_element(self.index_buffer.as_ref()) as u32;
+
+				command_buffer.resize(0);
+				command_buffer.write().write(
+					0,
+					self.commands.iter().map(|(_, command)| DrawElementsIndirectCommand {
+						base_vertex: command.base_vertex + base_vertex,
+						first_index: command.first_index + first_index,
+						..*command
+					}),
+				);
+				command_buffer.begin_flush();
+			},
+			Backend::Fallback { vertex_buffer } => vertex_buffer.begin_flush(),
+		}
+	}
+
+	unsafe fn sync_flush(&mut self) {
+		self.index_buffer.sync_flush();
+
+		match &mut self.backend {
+			Backend::Indirect {
+				vertex_buffer,
+				storage_buffer,
+				command_buffer,
+			} => {
+				vertex_buffer.sync_flush();
+				storage_buffer.sync_flush();
+				command_buffer.sync_flush();
+			},
+			Backend::Fallback { vertex_buffer } => vertex_buffer.sync_flush(),
+		}
+	}
+
+	unsafe fn bind(&mut self) {
+		if !(self.backend.has_vertex_buffer() && self.index_buffer.has_backing_buffer()) {
+			return
+		}
+
+		let no_vao = self.vao == 0;
+		if no_vao {
+			self.vao = attribute::create_vertex_array();
+		}
+
+		if no_vao
+			|| self.backend.vertex_buffer_changed()
+			|| self.index_buffer.backing_buffer_changed()
+		{
+			self.backend.set_vertex_attributes(self.vao);
+			attribute::set_element_buffer(self.vao, self.index_buffer.backing_buffer());
+			self.backend.clear_vertex_buffer_changed();
+			self.index_buffer.clear_buffer_changed();
+		}
+
+		gl::BindVertexArray(self.vao);
+	}
+
+	unsafe fn upload(&mut self) {
+		if !self.is_ready() || self.commands.is_empty() {
+			return
+		}
+
+		self.shader.bind();
+		self.bind();
+		self.sync_flush();
+
+		match &self.backend {
+			Backend::Indirect {
+				storage_buffer,
+				command_buffer,
+				..
+			} => {
+				storage_buffer.bind_range(gl::SHADER_STORAGE_BUFFER, 0);
+				command_buffer.bind();
+
+				// one draw per run of commands with the same primitive type
+				let mut start = 0;
+				while start < self.commands.len() {
+					let mode = self.commands[start].0;
+					let len = self.commands[start..].iter().take_while(|(m, _)| *m == mode).count();
+
+					gl::MultiDrawElementsIndirect(
+						mode,
+						u32::GL_TYPE,
+						(command_buffer.backing_offset()
+							+ start * mem::size_of::<DrawElementsIndirectCommand>())
+							as *const c_void,
+						len as GLsizei,
+						0,
+					);
+
+					let commands = &self.commands[start..start + len];
+					self.draws.record(commands.iter().map(|(_, c)| c.count as usize).sum());
+
+					start += len;
+				}
+			},
+			Backend::Fallback { vertex_buffer } => {
+				let base_vertex = buffer::base_element(vertex_buffer.as_ref());
+
+				for (mode, command) in &self.commands {
+					gl::DrawElementsBaseVertex(
+						*mode,
+						command.count as GLsizei,
+						u32::GL_TYPE,
+						(self.index_buffer.backing_offset()
+							+ command.first_index as usize * mem::size_of::<u32>())
+							as *const c_void,
+						command.base_vertex + base_vertex,
+					);
+					self.draws.record(command.count as usize);
+				}
+			},
+		}
+
+		self.finish_use();
+	}
+
+	unsafe fn finish_use(&mut self) {
+		let fence = self.frame_sync.end_frame();
+		self.backend.finish_use(&fence);
+		self.index_buffer.finish_use(&fence);
+	}
+
+	fn set_frame_latency(&mut self, frames: usize) {
+		self.frame_sync.set_frame_latency(frames);
+		let frames = self.frame_sync.frame_latency();
+		self.backend.set_frame_latency(frames);
+		self.index_buffer.set_frame_latency(frames);
+	}
+
+	unsafe fn clear(&mut self) {
+		self.index_buffer.resize(0);
+		self.commands.clear();
+
+		match &mut self.backend {
+			Backend::Indirect {
+				vertex_buffer,
+				storage_buffer,
+				..
+			} => {
+				vertex_buffer.resize(0);
+				storage_buffer.resize(0);
+			},
+			Backend::Fallback { vertex_buffer } => vertex_buffer.resize(0),
+		}
+	}
+
+	fn shader_program(&self) -> ProgramHandle {
+		self.shader.handle()
+	}
+
+	fn program_state(&self) -> ProgramState {
+		self.shader.state()
+	}
+
+	fn stats(&self) -> UploaderStats {
+		let mut buffers = self.backend.buffer_stats();
+		buffers.push(("index", self.index_buffer.stats()));
+
+		UploaderStats {
+			draws: self.draws,
+			frame_wait: self.frame_sync.wait_time(),
+			buffers,
+		}
+	}
+}
+
+impl<D: Drawable> Drop for IndirectUploader<D> {
+	fn drop(&mut self) {
+		unsafe {
+			gl::DeleteVertexArrays(1, &self.vao);
+		}
+	}
+}
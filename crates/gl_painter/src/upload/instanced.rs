@@ -0,0 +1,263 @@
+// Copyright (C) 2022 the ITK authors
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/./
+
+use std::ffi::c_void;
+
+use gl::types::{GLsizei, GLuint};
+
+use super::{
+	attribute,
+	buffer::{self, GpuBuffer},
+	index::IndexBuffer,
+	stats::{DrawStats, UploaderStats},
+	sync::FrameSync,
+	Uploader,
+};
+use crate::{
+	drawable::{DrawableData, InstancedDrawable, VertexPassable},
+	shader::{
+		registry::{ProgramState, ShaderRegistry, SharedProgram},
+		ProgramCreateError,
+		ProgramHandle,
+		ShaderVariant,
+	},
+};
+
+/// Uploader drawing every drawable as an instance of a shared mesh
+///
+/// The mesh from `InstancedDrawable::instance_geometry` is uploaded once,
+/// and only the drawable data is written per drawable, into a buffer
+/// of per-instance attributes.
+pub struct InstancedUploader<D: InstancedDrawable> {
+	vao: GLuint,
+	mesh_buffer: Box<dyn GpuBuffer<<D::Vertex as DrawableData>::Compat>>,
+	index_buffer: IndexBuffer,
+	/// Set once the mesh has been flushed by the first `begin_flush`
+	mesh_flushed: bool,
+	instance_buffer: Box<dyn GpuBuffer<<D::Drawable as DrawableData>::Compat>>,
+	draws: DrawStats,
+	frame_sync: FrameSync,
+	shader: SharedProgram,
+}
+
+impl<D: InstancedDrawable> InstancedUploader<D> {
+	/// Create an uploader using the thread's shared program for `D`
+	///
+	/// # SAFETY
+	/// * must be called from GL thread (shader creation, mesh upload)
+	pub unsafe fn new() -> Result<Self, ProgramCreateError>
+	where
+		D: 'static,
+	{
+		Ok(Self::with_program(ShaderRegistry::with_thread(|r| {
+			r.get::<D>(ShaderVariant::Compat)
+		})?))
+	}
+
+	/// Create an uploader without waiting for its shader program to compile
+	///
+	/// Nothing will be drawn until the program is finished by
+	/// `ShaderRegistry::poll`. See `Uploader::is_ready`.
+	///
+	/// # SAFETY
+	/// * must be called from GL thread (shader creation, mesh upload)
+	pub unsafe fn new_pending() -> Result<Self, ProgramCreateError>
+	where
+		D: 'static,
+	{
+		Ok(Self::with_program(ShaderRegistry::with_thread(|r| {
+			r.get_pending::<D>(ShaderVariant::Compat)
+		})?))
+	}
+
+	/// # SAFETY
+	/// * must be called from GL thread
+	unsafe fn with_program(shader: SharedProgram) -> Self {
+		let mut mesh_buffer = buffer::new(gl::ARRAY_BUFFER);
+		let mut index_buffer = IndexBuffer::new();
+
+		// the mesh never changes, so it is only flushed by the first `begin_flush`
+		let (vertices, indices) = D::instance_geometry();
+		let vertices = vertices.into_iter().map(|v| v.into_compat());
+		mesh_buffer.prepare_write();
+		mesh_buffer.write().write(0, vertices);
+		index_buffer.prepare_write();
+		index_buffer.fit_vertices(mesh_buffer.len());
+		index_buffer.write(0, indices);
+
+		Self {
+			vao: 0,
+			mesh_buffer,
+			index_buffer,
+			mesh_flushed: false,
+			instance_buffer: buffer::new::<<D::Drawable as DrawableData>::Compat>(gl::ARRAY_BUFFER),
+			draws: DrawStats::default(),
+			frame_sync: FrameSync::new(),
+			shader,
+		}
+	}
+
+	/// # SAFETY
+	/// * must be called from GL thread
+	unsafe fn set_vertex_attributes(&self) {
+		let vertex_attributes = <D::Vertex as DrawableData>::Compat::VERTEX_ATTRIBUTES;
+		let instance_attributes = <D::Drawable as DrawableData>::Compat::VERTEX_ATTRIBUTES;
+
+		attribute::set_vertex_attributes(
+			self.vao,
+			self.mesh_buffer.backing_buffer(),
+			buffer::attribute_offset(self.mesh_buffer.as_ref()),
+			0,
+			vertex_attributes.iter(),
+			0,
+			0,
+		);
+		attribute::set_vertex_attributes(
+			self.vao,
+			self.instance_buffer.backing_buffer(),
+			buffer::attribute_offset(self.instance_buffer.as_ref()),
+			1,
+			instance_attributes.iter(),
+			attribute::location_count(vertex_attributes),
+			1,
+		);
+	}
+}
+
+impl<D: InstancedDrawable> Uploader<D> for InstancedUploader<D> {
+	unsafe fn prepare_write(&mut self) {
+		self.frame_sync.wait_for_frame();
+		self.instance_buffer.prepare_write();
+	}
+
+	unsafe fn write(&mut self, drawable: &D) {
+		let instance_index = self.instance_buffer.len();
+		self.instance_buffer
+			.write()
+			.write(instance_index, [drawable.drawable_data().into_compat()]);
+	}
+
+	unsafe fn begin_flush(&mut self) {
+		if !self.mesh_flushed {
+			self.mesh_buffer.begin_flush();
+			self.index_buffer.begin_flush();
+			self.mesh_flushed = true;
+		}
+
+		self.instance_buffer.begin_flush();
+	}
+
+	unsafe fn sync_flush(&mut self) {
+		self.mesh_buffer.sync_flush();
+		self.index_buffer.sync_flush();
+		self.instance_buffer.sync_flush();
+	}
+
+	unsafe fn bind(&mut self) {
+		if !(self.mesh_buffer.has_backing_buffer()
+			&& self.index_buffer.has_backing_buffer()
+			&& self.instance_buffer.has_backing_buffer())
+		{
+			return
+		}
+
+		let no_vao = self.vao == 0;
+		if no_vao {
+			self.vao = attribute::create_vertex_array();
+		}
+
+		if no_vao
+			|| self.mesh_buffer.backing_buffer_changed()
+			|| self.index_buffer.backing_buffer_changed()
+			|| self.instance_buffer.backing_buffer_changed()
+		{
+			self.set_vertex_attributes();
+			attribute::set_element_buffer(self.vao, self.index_buffer.backing_buffer());
+			self.mesh_buffer.clear_buffer_changed();
+			self.index_buffer.clear_buffer_changed();
+			self.instance_buffer.clear_buffer_changed();
+		}
+
+		gl::BindVertexArray(self.vao);
+	}
+
+	unsafe fn upload(&mut self) {
+		if !self.is_ready() || self.instance_buffer.len() == 0 {
+			return
+		}
+
+		self.shader.bind();
+		self.bind();
+		self.sync_flush();
+
+		let count = self.index_buffer.len() as GLsizei;
+		let index_type = self.index_buffer.index_type().gl_type();
+		let indices = self.index_buffer.backing_offset() as *const c_void;
+		let instance_count = self.instance_buffer.len() as GLsizei;
+		let base_vertex = buffer::base_element(self.mesh_buffer.as_ref());
+		let base_instance = buffer::base_element(self.instance_buffer.as_ref());
+
+		// only ring buffers have a base instance, and they need GL 4.4
+		match (base_vertex, base_instance) {
+			(0, 0) =>
+				gl::DrawElementsInstanced(D::GL_TYPE, count, index_type, indices, instance_count),
+			_ => gl::DrawElementsInstancedBaseVertexBaseInstance(
+				D::GL_TYPE,
+				count,
+				index_type,
+				indices,
+				instance_count,
+				base_vertex,
+				base_instance as GLuint,
+			),
+		}
+		self.draws.record(self.index_buffer.len() * self.instance_buffer.len());
+		self.finish_use();
+	}
+
+	unsafe fn finish_use(&mut self) {
+		let fence = self.frame_sync.end_frame();
+		self.instance_buffer.finish_use(&fence);
+	}
+
+	fn set_frame_latency(&mut self, frames: usize) {
+		self.frame_sync.set_frame_latency(frames);
+		let frames = self.frame_sync.frame_latency();
+		self.instance_buffer.set_frame_latency(frames);
+	}
+
+	unsafe fn clear(&mut self) {
+		self.instance_buffer.resize(0);
+	}
+
+	fn shader_program(&self) -> ProgramHandle {
+		self.shader.handle()
+	}
+
+	fn program_state(&self) -> ProgramState {
+		self.shader.state()
+	}
+
+	fn stats(&self) -> UploaderStats {
+		UploaderStats {
+			draws: self.draws,
+			frame_wait: self.frame_sync.wait_time(),
+			buffers: vec![
+				("mesh", self.mesh_buffer.stats()),
+				("index", self.index_buffer.stats()),
+				("instance", self.instance_buffer.stats()),
+			],
+		}
+	}
+}
+
+impl<D: InstancedDrawable> Drop for InstancedUploader<D> {
+	fn drop(&mut self) {
+		unsafe {
+			gl::DeleteVertexArrays(1, &self.vao);
+		}
+	}
+}
@@ -0,0 +1,514 @@
+// Copyright (C) 2022 the ITK authors
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/./
+
+//! Uploader keeping drawables between frames
+
+use std::{ffi::c_void, mem, ops::Range};
+
+use gl::types::{GLenum, GLsizei};
+
+use super::{
+	alloc::RangeAllocator,
+	attribute::GLtype,
+	buffer::{self, arena::BufferArena, GpuBuffer},
+	ssbo::SsboBuffers,
+	stats::{DrawStats, UploaderStats},
+	sync::FrameSync,
+	Uploader,
+};
+use crate::{
+	drawable::{Drawable, DrawableData},
+	shader::{
+		registry::{ProgramState, ShaderRegistry, SharedProgram},
+		ProgramCreateError,
+		ProgramHandle,
+		ShaderVariant,
+	},
+};
+
+#[cfg(test)]
+mod test;
+
+/// Default fraction of free vertex or index space that triggers compaction
+pub const DEFAULT_COMPACTION_THRESHOLD: f32 = 0.5;
+
+/// Buffers smaller than this are never compacted
+const MIN_COMPACTION_LEN: usize = 1024;
+
+/// Handle to a drawable in a `RetainedUploader`
+///
+/// Handles are invalidated when their drawable is removed,
+/// and are never reused for another drawable.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DrawableHandle {
+	slot: u32,
+	generation: u32,
+}
+
+struct Slot {
+	generation: u32,
+	/// `None` if the slot is free
+	ranges: Option<SlotRanges>,
+}
+
+#[derive(Clone)]
+struct SlotRanges {
+	vertices: Range<usize>,
+	indices: Range<usize>,
+}
+
+/// Uploader that keeps drawables between frames
+///
+/// Drawables are inserted once and only rewritten when updated, so only
+/// the vertex, index and storage slots of changed drawables are uploaded.
+/// Freed slots are reused by later drawables, and the vertex and index
+/// buffers are compacted when too much of them is free.
+///
+/// Freed index ranges are skipped when drawing, with one draw per range
+/// of live indices, so only indexed lists are supported.
+///
+/// Drawables written with `Uploader::write` have no handle and can
+/// only be removed with `Uploader::clear`.
+///
+/// Uploaders created with `new_in` share the GL buffers of a `BufferArena`
+/// instead of owning their own.
+pub struct RetainedUploader<D: Drawable> {
+	buffers: SsboBuffers<D>,
+	index_buffer: Box<dyn GpuBuffer<u32>>,
+	draws: DrawStats,
+	frame_sync: FrameSync,
+	shader: SharedProgram,
+	/// Indexed by storage buffer index
+	slots: Vec<Slot>,
+	free_slots: Vec<u32>,
+	vertex_alloc: RangeAllocator,
+	index_alloc: RangeAllocator,
+	compaction_threshold: f32,
+}
+
+impl<D: Drawable> RetainedUploader<D> {
+	/// Create an uploader using the thread's shared program for `D`
+	///
+	/// # SAFETY
+	/// * must be called from GL thread (shader creation)
+	pub unsafe fn new() -> Result<Self, ProgramCreateError>
+	where
+		D: 'static,
+	{
+		Ok(Self::with_program(
+			ShaderRegistry::with_thread(|r| r.get::<D>(ShaderVariant::Ssbo))?,
+			None,
+		))
+	}
+
+	/// Create an uploader without waiting for its shader program to compile
+	///
+	/// Nothing will be drawn until the program is finished by
+	/// `ShaderRegistry::poll`. See `Uploader::is_ready`.
+	///
+	/// # SAFETY
+	/// * must be called from GL thread (shader creation)
+	pub unsafe fn new_pending() -> Result<Self, ProgramCreateError>
+	where
+		D: 'static,
+	{
+		Ok(Self::with_program(
+			ShaderRegistry::with_thread(|r| r.get_pending::<D>(ShaderVariant::Ssbo))?,
+			None,
+		))
+	}
+
+	/// Create an uploader allocating its buffers from `arena`
+	///
+	/// # SAFETY
+	/// * must be called from GL thread (shader creation)
+	pub unsafe fn new_in(arena: &BufferArena) -> Result<Self, ProgramCreateError>
+	where
+		D: 'static,
+	{
+		Ok(Self::with_program(
+			ShaderRegistry::with_thread(|r| r.get::<D>(ShaderVariant::Ssbo))?,
+			Some(arena),
+		))
+	}
+
+	/// Create an uploader allocating its buffers from `arena`, without waiting
+	/// for its shader program to compile
+	///
+	/// # SAFETY
+	/// * must be called from GL thread (shader creation)
+	pub unsafe fn new_pending_in(arena: &BufferArena) -> Result<Self, ProgramCreateError>
+	where
+		D: 'static,
+	{
+		Ok(Self::with_program(
+			ShaderRegistry::with_thread(|r| r.get_pending::<D>(ShaderVariant::Ssbo))?,
+			Some(arena),
+		))
+	}
+
+	fn with_program(shader: SharedProgram, arena: Option<&BufferArena>) -> Self {
+		const {
+			assert!(
+				D::INDEXED && !D::IS_STRIP,
+				"retained uploaders only support indexed list primitives"
+			)
+		};
+
+		Self {
+			buffers: SsboBuffers::new(
+				new_buffer(arena, gl::ARRAY_BUFFER),
+				new_buffer(arena, gl::SHADER_STORAGE_BUFFER),
+			),
+			index_buffer: new_buffer(arena, gl::ELEMENT_ARRAY_BUFFER),
+			draws: DrawStats::default(),
+			frame_sync: FrameSync::new(),
+			shader,
+			slots: Vec::new(),
+			free_slots: Vec::new(),
+			vertex_alloc: RangeAllocator::new(),
+			index_alloc: RangeAllocator::new(),
+			compaction_threshold: DEFAULT_COMPACTION_THRESHOLD,
+		}
+	}
+
+	/// Set the fraction of free vertex or index space, between 0 and 1,
+	/// above which the buffers are compacted on the next flush
+	pub fn set_compaction_threshold(&mut self, threshold: f32) {
+		self.compaction_threshold = threshold;
+	}
+
+	/// Number of drawables in this uploader
+	pub fn len(&self) -> usize {
+		self.slots.len() - self.free_slots.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	/// Check if `handle` refers to a drawable in this uploader
+	pub fn contains(&self, handle: DrawableHandle) -> bool {
+		self.slots
+			.get(handle.slot as usize)
+			.is_some_and(|slot| slot.generation == handle.generation && slot.ranges.is_some())
+	}
+
+	/// Insert a drawable, returning a handle to update or remove it with
+	///
+	/// # SAFETY
+	/// * must call prepare_write before calling insert
+	pub unsafe fn insert(&mut self, drawable: &D) -> DrawableHandle {
+		let slot = match self.free_slots.pop() {
+			Some(slot) => slot,
+			None => {
+				self.slots.push(Slot {
+					generation: 0,
+					ranges: None,
+				});
+
+				(self.slots.len() - 1) as u32
+			},
+		};
+
+		let ranges = self.write_drawable(slot, drawable, None);
+		self.slots[slot as usize].ranges = Some(ranges);
+
+		DrawableHandle {
+			slot,
+			generation: self.slots[slot as usize].generation,
+		}
+	}
+
+	/// Replace a drawable
+	///
+	/// Returns false if the handle is no longer valid.
+	///
+	/// # SAFETY
+	/// * must call prepare_write before calling update
+	pub unsafe fn update(&mut self, handle: DrawableHandle, drawable: &D) -> bool {
+		if !self.contains(handle) {
+			return false
+		}
+
+		let old_ranges = self.slots[handle.slot as usize].ranges.take();
+		let ranges = self.write_drawable(handle.slot, drawable, old_ranges);
+		self.slots[handle.slot as usize].ranges = Some(ranges);
+
+		true
+	}
+
+	/// Remove a drawable
+	///
+	/// Returns false if the handle is no longer valid.
+	///
+	/// # SAFETY
+	/// * must call prepare_write before calling remove
+	pub unsafe fn remove(&mut self, handle: DrawableHandle) -> bool {
+		if !self.contains(handle) {
+			return false
+		}
+
+		let slot = &mut self.slots[handle.slot as usize];
+		let ranges = slot.ranges.take().unwrap();
+		slot.generation = slot.generation.wrapping_add(1);
+		self.free_slots.push(handle.slot);
+
+		self.free_vertices(ranges.vertices);
+		self.free_indices(ranges.indices);
+
+		true
+	}
+
+	/// Write a drawable's data to its slot, reusing its old ranges if they fit
+	unsafe fn write_drawable(
+		&mut self,
+		slot: u32,
+		drawable: &D,
+		old_ranges: Option<SlotRanges>,
+	) -> SlotRanges {
+		let drawable_data = drawable.drawable_data().into_ssbo();
+		let (vertex_data, index_data) = drawable.drawable_vertices();
+		let (vertex_data, index_data) = (vertex_data.into_iter(), index_data.into_iter());
+
+		let (old_vertices, old_indices) = match old_ranges {
+			Some(ranges) => (ranges.vertices, ranges.indices),
+			None => (0..0, 0..0),
+		};
+
+		let vertices = match old_vertices.len() == vertex_data.len() {
+			true => old_vertices,
+			false => {
+				self.free_vertices(old_vertices);
+				self.vertex_alloc.alloc(vertex_data.len())
+			},
+		};
+
+		let indices = match old_indices.len() == index_data.len() {
+			true => old_indices,
+			false => {
+				self.free_indices(old_indices);
+				self.index_alloc.alloc(index_data.len())
+			},
+		};
+
+		// slots are allocated in order, so the storage buffer is at most appended to
+		let vertex_data = vertex_data.map(|vertex| vertex.into_compat());
+		self.buffers.write(vertices.start, vertex_data, slot as usize, drawable_data);
+
+		let index_data = index_data.map(|i| vertices.start as u32 + i);
+		self.index_buffer.write().write(indices.start, index_data);
+
+		SlotRanges { vertices, indices }
+	}
+
+	fn free_vertices(&mut self, range: Range<usize>) {
+		self.vertex_alloc.free(range);
+		self.buffers.vertex_buffer.resize(self.vertex_alloc.len());
+	}
+
+	fn free_indices(&mut self, range: Range<usize>) {
+		self.index_alloc.free(range);
+		self.index_buffer.resize(self.index_alloc.len());
+	}
+
+	fn should_compact(&self, alloc: &RangeAllocator) -> bool {
+		alloc.len() >= MIN_COMPACTION_LEN
+			&& alloc.free_len() as f32 > alloc.len() as f32 * self.compaction_threshold
+	}
+
+	/// Move every drawable's vertices and indices to the start of their buffers,
+	/// rewriting indices to point to the moved vertices
+	///
+	/// # SAFETY
+	/// * must have previously called `prepare_write`
+	unsafe fn compact(&mut self) {
+		let mut live = self
+			.slots
+			.iter_mut()
+			.filter_map(|slot| slot.ranges.as_mut())
+			.map(|ranges| {
+				let old_vertex_start = ranges.vertices.start;
+				(ranges, old_vertex_start)
+			})
+			.collect::<Vec<_>>();
+
+		let vertex_backer = self.buffers.vertex_buffer.write();
+		live.sort_unstable_by_key(|(ranges, _)| ranges.vertices.start);
+
+		let mut vertex_len = 0;
+		for (ranges, _) in &mut live {
+			let new_vertices = vertex_len..vertex_len + ranges.vertices.len();
+
+			if new_vertices != ranges.vertices {
+				// ranges are moved towards the start in order, so never overlap a live range
+				let data = vertex_backer.data()[ranges.vertices.clone()].to_vec();
+				vertex_backer.write(new_vertices.start, data);
+			}
+
+			vertex_len = new_vertices.end;
+			ranges.vertices = new_vertices;
+		}
+
+		let index_backer = self.index_buffer.write();
+		live.sort_unstable_by_key(|(ranges, _)| ranges.indices.start);
+
+		let mut index_len = 0;
+		for (ranges, old_vertex_start) in &mut live {
+			let new_indices = index_len..index_len + ranges.indices.len();
+
+			if new_indices != ranges.indices || ranges.vertices.start != *old_vertex_start {
+				let data = index_backer.data()[ranges.indices.clone()]
+					.iter()
+					.map(|&i| i - *old_vertex_start as u32 + ranges.vertices.start as u32)
+					.collect::<Vec<_>>();
+				index_backer.write(new_indices.start, data);
+			}
+
+			index_len = new_indices.end;
+			ranges.indices = new_indices;
+		}
+
+		self.vertex_alloc.reset(vertex_len);
+		self.index_alloc.reset(index_len);
+		self.buffers.vertex_buffer.resize(vertex_len);
+		self.index_buffer.resize(index_len);
+	}
+}
+
+impl<D: Drawable> Uploader<D> for RetainedUploader<D> {
+	unsafe fn prepare_write(&mut self) {
+		self.frame_sync.wait_for_frame();
+		self.buffers.prepare_write();
+		self.index_buffer.prepare_write();
+	}
+
+	/// Insert a drawable without keeping its handle
+	unsafe fn write(&mut self, drawable: &D) {
+		self.insert(drawable);
+	}
+
+	unsafe fn begin_flush(&mut self) {
+		if self.should_compact(&self.vertex_alloc) || self.should_compact(&self.index_alloc) {
+			self.compact();
+		}
+
+		self.buffers.begin_flush();
+		self.index_buffer.begin_flush();
+	}
+
+	unsafe fn sync_flush(&mut self) {
+		self.buffers.sync_flush();
+		self.index_buffer.sync_flush();
+	}
+
+	unsafe fn bind(&mut self) {
+		if !(self.buffers.has_backing_buffers() && self.index_buffer.has_backing_buffer()) {
+			return
+		}
+
+		self.buffers
+			.bind(self.index_buffer.backing_buffer(), self.index_buffer.backing_buffer_changed());
+		self.index_buffer.clear_buffer_changed();
+	}
+
+	unsafe fn upload(&mut self) {
+		if !self.is_ready() || self.index_buffer.len() == 0 {
+			return
+		}
+
+		self.shader.bind();
+		self.bind();
+		self.sync_flush();
+		self.buffers.bind_storage();
+
+		// one draw per range of live indices, skipping freed ranges
+		let index_offset = self.index_buffer.backing_offset();
+		let base_vertex = self.buffers.base_vertex();
+		let (mut counts, mut offsets, mut base_vertices) = (Vec::new(), Vec::new(), Vec::new());
+
+		for range in self.index_alloc.allocated() {
+			counts.push(range.len() as GLsizei);
+			offsets.push((index_offset + range.start * mem::size_of::<u32>()) as *const c_void);
+			base_vertices.push(base_vertex);
+		}
+
+		gl::MultiDrawElementsBaseVertex(
+			D::GL_TYPE,
+			counts.as_ptr(),
+			u32::GL_TYPE,
+			offsets.as_ptr(),
+			counts.len() as GLsizei,
+			base_vertices.as_ptr(),
+		);
+		self.draws.record(self.index_alloc.len() - self.index_alloc.free_len());
+		self.finish_use();
+	}
+
+	unsafe fn finish_use(&mut self) {
+		let fence = self.frame_sync.end_frame();
+		self.buffers.finish_use(&fence);
+		self.index_buffer.finish_use(&fence);
+	}
+
+	fn set_frame_latency(&mut self, frames: usize) {
+		self.frame_sync.set_frame_latency(frames);
+		let frames = self.frame_sync.frame_latency();
+		self.buffers.set_frame_latency(frames);
+		self.index_buffer.set_frame_latency(frames);
+	}
+
+	/// Remove every drawable, invalidating all handles
+	unsafe fn clear(&mut self) {
+		self.free_slots.clear();
+
+		for slot in &mut self.slots {
+			if slot.ranges.take().is_some() {
+				slot.generation = slot.generation.wrapping_add(1);
+			}
+		}
+
+		// popped lowest first, so the emptied storage buffer is only appended to
+		self.free_slots.extend((0..self.slots.len() as u32).rev());
+
+		self.vertex_alloc.reset(0);
+		self.index_alloc.reset(0);
+		self.buffers.vertex_buffer.resize(0);
+		self.index_buffer.resize(0);
+		self.buffers.storage_buffer.resize(0);
+	}
+
+	fn shader_program(&self) -> ProgramHandle {
+		self.shader.handle()
+	}
+
+	fn program_state(&self) -> ProgramState {
+		self.shader.state()
+	}
+
+	fn stats(&self) -> UploaderStats {
+		UploaderStats {
+			draws: self.draws,
+			frame_wait: self.frame_sync.wait_time(),
+			buffers: vec![
+				("vertex", self.buffers.vertex_buffer.stats()),
+				("index", self.index_buffer.stats()),
+				("storage", self.buffers.storage_buffer.stats()),
+			],
+		}
+	}
+}
+
+/// Create a buffer in `arena`, or a standalone buffer without one
+fn new_buffer<T: bytemuck::AnyBitPattern>(
+	arena: Option<&BufferArena>,
+	buffer_type: GLenum,
+) -> Box<dyn GpuBuffer<T>> {
+	match arena {
+		Some(arena) => Box::new(arena.buffer(buffer_type)),
+		None => buffer::new(buffer_type),
+	}
+}
@@ -0,0 +1,80 @@
+use super::RetainedUploader;
+use crate::{
+	drawable::ColoredTriangle,
+	mock_gl::{self, triangle},
+	shader::{registry::SharedProgram, ShaderProgram},
+	upload::Uploader,
+};
+
+fn uploader() -> RetainedUploader<ColoredTriangle> {
+	mock_gl::install(4, 3);
+	RetainedUploader::with_program(SharedProgram::new(ShaderProgram { program_object: 1 }), None)
+}
+
+/// Draw the uploader's drawables as one frame, returning the draw call made
+fn draw(uploader: &mut RetainedUploader<ColoredTriangle>) -> String {
+	mock_gl::take_calls();
+
+	unsafe {
+		uploader.begin_flush();
+		uploader.upload();
+	}
+
+	let calls = mock_gl::take_calls();
+	calls.into_iter().find(|call| call.starts_with("MultiDraw")).unwrap()
+}
+
+#[test]
+fn test_skip_removed() {
+	let mut uploader = uploader();
+
+	let handles = unsafe {
+		uploader.prepare_write();
+		[0.0, 2.0, 4.0].map(|x| uploader.insert(&triangle(x)))
+	};
+
+	assert_eq!(
+		draw(&mut uploader),
+		"MultiDrawElementsBaseVertex(TRIANGLES, [9], UNSIGNED_INT, [0], [0])"
+	);
+
+	// the removed triangle's indices are not drawn until they are reused
+	unsafe {
+		uploader.prepare_write();
+		uploader.remove(handles[1]);
+	}
+
+	assert_eq!(
+		draw(&mut uploader),
+		"MultiDrawElementsBaseVertex(TRIANGLES, [3, 3], UNSIGNED_INT, [0, 24], [0, 0])"
+	);
+
+	unsafe {
+		uploader.prepare_write();
+		uploader.insert(&triangle(6.0));
+	}
+
+	assert_eq!(
+		draw(&mut uploader),
+		"MultiDrawElementsBaseVertex(TRIANGLES, [9], UNSIGNED_INT, [0], [0])"
+	);
+}
+
+#[test]
+fn test_clear() {
+	let mut uploader = uploader();
+
+	unsafe {
+		uploader.prepare_write();
+		uploader.insert(&triangle(0.0));
+		uploader.insert(&triangle(2.0));
+		uploader.clear();
+	}
+
+	assert_eq!(uploader.buffers.storage_buffer.len(), 0);
+
+	// slots are reused in order after clearing
+	let handle = unsafe { uploader.insert(&triangle(0.0)) };
+	assert_eq!(handle.slot, 0);
+	assert_eq!(uploader.buffers.storage_buffer.len(), 1);
+}
@@ -4,34 +4,50 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/./
 
-use std::ffi::c_void;
+use std::{mem, rc::Rc};
 
-use gl::types::{GLsizei, GLuint};
+use gl::types::{GLint, GLuint};
 
 use super::{
-	attribute::GLtype,
+	attribute,
+	batch::DrawBatch,
 	buffer::{self, GpuBuffer},
+	encoder::Encoder,
+	index::IndexBuffer,
+	stats::{DrawStats, UploaderStats},
+	sync::{Fence, FrameSync},
 	Uploader,
+	VertexAttribute,
 };
 use crate::{
 	drawable::{Drawable, DrawableData, VertexPassable},
-	shader::ShaderProgram,
-	upload::VertexAttribute,
+	shader::{
+		registry::{ProgramState, ShaderRegistry, SharedProgram},
+		ProgramCreateError,
+		ProgramHandle,
+		ShaderVariant,
+	},
 };
 
+#[cfg(test)]
+mod test;
+
 pub struct SsboUploader<D: Drawable> {
-	vao: GLuint,
-	vertex_buffer: Box<dyn GpuBuffer<SsboVertex<<D::Vertex as DrawableData>::Compat>>>,
-	index_buffer: Box<dyn GpuBuffer<u32>>,
-	storage_buffer: Box<dyn GpuBuffer<<D::Drawable as DrawableData>::Ssbo>>,
-	shader: ShaderProgram,
+	buffers: SsboBuffers<D>,
+	index_buffer: IndexBuffer,
+	batch: DrawBatch,
+	/// Submitted encoders, merged on the next flush
+	encoders: Vec<Encoder<D>>,
+	draws: DrawStats,
+	frame_sync: FrameSync,
+	shader: SharedProgram,
 }
 
 #[derive(bytemuck::Pod, bytemuck::Zeroable)]
 #[repr(C, packed)]
-struct SsboVertex<V: bytemuck::Pod> {
-	vertex: V,
-	ssbo_index: u32,
+pub(super) struct SsboVertex<V: bytemuck::Pod> {
+	pub vertex: V,
+	pub ssbo_index: u32,
 }
 
 impl<V: bytemuck::Pod> Copy for SsboVertex<V> {}
@@ -42,160 +58,342 @@ impl<V: bytemuck::Pod> core::clone::Clone for SsboVertex<V> {
 	}
 }
 
+type VertexCompat<D> = <<D as Drawable>::Vertex as DrawableData>::Compat;
+type DrawableSsbo<D> = <<D as Drawable>::Drawable as DrawableData>::Ssbo;
+
+/// Vertex and storage buffers of drawables drawn with the SSBO variant of
+/// `D`'s shader, and the VAO reading them
+///
+/// Shared by `SsboUploader` and `RetainedUploader`, which only differ in
+/// where drawables are written and how their indices are drawn.
+pub(super) struct SsboBuffers<D: Drawable> {
+	vao: GLuint,
+	pub vertex_buffer: Box<dyn GpuBuffer<SsboVertex<VertexCompat<D>>>>,
+	pub storage_buffer: Box<dyn GpuBuffer<DrawableSsbo<D>>>,
+}
+
+impl<D: Drawable> SsboBuffers<D> {
+	pub fn new(
+		vertex_buffer: Box<dyn GpuBuffer<SsboVertex<VertexCompat<D>>>>,
+		storage_buffer: Box<dyn GpuBuffer<DrawableSsbo<D>>>,
+	) -> Self {
+		Self {
+			vao: 0,
+			vertex_buffer,
+			storage_buffer,
+		}
+	}
+
+	/// Write a drawable's vertices from `vertex_start`, and its data at `ssbo_index`
+	///
+	/// # SAFETY
+	/// * must have previously called `prepare_write`
+	pub unsafe fn write(
+		&mut self,
+		vertex_start: usize,
+		vertices: impl IntoIterator<IntoIter = impl ExactSizeIterator<Item = VertexCompat<D>>>,
+		ssbo_index: usize,
+		drawable_data: DrawableSsbo<D>,
+	) {
+		let vertices = vertices.into_iter().map(|vertex| SsboVertex {
+			vertex,
+			ssbo_index: ssbo_index as u32,
+		});
+
+		self.vertex_buffer.write().write(vertex_start, vertices);
+		self.storage_buffer.write().write(ssbo_index, [drawable_data]);
+	}
+
+	/// # SAFETY
+	/// * must be called from GL thread
+	pub unsafe fn prepare_write(&mut self) {
+		self.vertex_buffer.prepare_write();
+		self.storage_buffer.prepare_write();
+	}
+
+	/// # SAFETY
+	/// * must be called from GL thread
+	pub unsafe fn begin_flush(&mut self) {
+		self.vertex_buffer.begin_flush();
+		self.storage_buffer.begin_flush();
+	}
+
+	/// # SAFETY
+	/// * must be called from GL thread
+	pub unsafe fn sync_flush(&mut self) {
+		self.vertex_buffer.sync_flush();
+		self.storage_buffer.sync_flush();
+	}
+
+	pub fn has_backing_buffers(&self) -> bool {
+		self.vertex_buffer.has_backing_buffer() && self.storage_buffer.has_backing_buffer()
+	}
+
+	/// Bind the VAO reading the vertex buffer and `index_buffer`, which is
+	/// respecified if either buffer changed
+	///
+	/// # SAFETY
+	/// * must be called from GL thread
+	pub unsafe fn bind(&mut self, index_buffer: GLuint, index_buffer_changed: bool) {
+		let no_vao = self.vao == 0;
+		if no_vao {
+			self.vao = attribute::create_vertex_array();
+		}
+
+		// the storage buffer is bound by index when drawing, so is not part of the VAO
+		if no_vao || self.vertex_buffer.backing_buffer_changed() || index_buffer_changed {
+			attribute::set_vertex_attributes(
+				self.vao,
+				self.vertex_buffer.backing_buffer(),
+				buffer::attribute_offset(self.vertex_buffer.as_ref()),
+				0,
+				SsboUploader::<D>::vertex_attributes(),
+				0,
+				0,
+			);
+			attribute::set_element_buffer(self.vao, index_buffer);
+			self.vertex_buffer.clear_buffer_changed();
+		}
+
+		gl::BindVertexArray(self.vao);
+	}
+
+	/// Bind the storage buffer to the shader's storage block
+	///
+	/// # SAFETY
+	/// * must be called from GL thread
+	/// * must be flushed
+	pub unsafe fn bind_storage(&self) {
+		self.storage_buffer.bind_range(gl::SHADER_STORAGE_BUFFER, 0);
+	}
+
+	/// Base vertex to draw the vertex buffer's current data with
+	pub fn base_vertex(&self) -> GLint {
+		buffer::base_element(self.vertex_buffer.as_ref())
+	}
+
+	/// # SAFETY
+	/// * must be called from GL thread
+	pub unsafe fn finish_use(&mut self, fence: &Rc<Fence>) {
+		self.vertex_buffer.finish_use(fence);
+		self.storage_buffer.finish_use(fence);
+	}
+
+	pub fn set_frame_latency(&mut self, frames: usize) {
+		self.vertex_buffer.set_frame_latency(frames);
+		self.storage_buffer.set_frame_latency(frames);
+	}
+}
+
+impl<D: Drawable> Drop for SsboBuffers<D> {
+	fn drop(&mut self) {
+		unsafe {
+			gl::DeleteVertexArrays(1, &self.vao);
+		}
+	}
+}
+
 impl<D: Drawable> SsboUploader<D> {
+	/// Create an uploader using the thread's shared program for `D`
+	///
+	/// # SAFETY
+	/// * must be called from GL thread (shader creation)
+	pub unsafe fn new() -> Result<Self, ProgramCreateError>
+	where
+		D: 'static,
+	{
+		Ok(Self::with_program(ShaderRegistry::with_thread(|r| {
+			r.get::<D>(ShaderVariant::Ssbo)
+		})?))
+	}
+
+	/// Create an uploader without waiting for its shader program to compile
+	///
+	/// Nothing will be drawn until the program is finished by
+	/// `ShaderRegistry::poll`. See `Uploader::is_ready`.
+	///
 	/// # SAFETY
 	/// * must be called from GL thread (shader creation)
-	pub unsafe fn new() -> Self {
+	pub unsafe fn new_pending() -> Result<Self, ProgramCreateError>
+	where
+		D: 'static,
+	{
+		Ok(Self::with_program(ShaderRegistry::with_thread(|r| {
+			r.get_pending::<D>(ShaderVariant::Ssbo)
+		})?))
+	}
+
+	fn with_program(shader: SharedProgram) -> Self {
 		Self {
-			vao: 0,
-			vertex_buffer: buffer::new::<SsboVertex<<D::Vertex as DrawableData>::Compat>>(
-				gl::ARRAY_BUFFER,
-			),
-			index_buffer: buffer::new::<u32>(gl::ELEMENT_ARRAY_BUFFER),
-			storage_buffer: buffer::new::<<D::Drawable as DrawableData>::Ssbo>(
-				gl::SHADER_STORAGE_BUFFER,
+			buffers: SsboBuffers::new(
+				buffer::new(gl::ARRAY_BUFFER),
+				buffer::new(gl::SHADER_STORAGE_BUFFER),
 			),
-			shader: D::SHADER_SOURCE.create_program(true),
+			index_buffer: IndexBuffer::new(),
+			batch: DrawBatch::new(),
+			encoders: Vec::new(),
+			draws: DrawStats::default(),
+			frame_sync: FrameSync::new(),
+			shader,
 		}
 	}
 
+	/// Create an encoder for this uploader, which may be filled on any thread
+	pub fn encoder(&self) -> Encoder<D> {
+		Encoder::new(ShaderVariant::Ssbo)
+	}
+
+	/// Queue an encoder's drawables to be merged on the next `begin_flush`,
+	/// after every drawable written with `Uploader::write`
+	///
+	/// Encoders are merged in submission order.
+	pub fn submit(&mut self, encoder: Encoder<D>) {
+		debug_assert_eq!(encoder.variant(), ShaderVariant::Ssbo);
+		self.encoders.push(encoder);
+	}
+
+	/// Encode `drawables` in parallel on the rayon thread pool,
+	/// then submit them in order
+	#[cfg(feature = "rayon")]
+	pub fn write_par(&mut self, drawables: &[D])
+	where
+		D: Sync,
+		Encoder<D>: Send,
+	{
+		for encoder in super::encoder::encode_par(ShaderVariant::Ssbo, drawables) {
+			self.submit(encoder);
+		}
+	}
+
+	/// Write an encoder's drawables, rebasing their indices
+	///
 	/// # SAFETY
-	/// * VAO, VBO and EBO must be bound
-	unsafe fn set_vertex_attributes() {
+	/// * must have previously called `prepare_write`
+	unsafe fn merge(&mut self, encoder: &Encoder<D>) {
+		for ((vertices, indices), drawable_data) in encoder.drawables().zip(&encoder.ssbo_data) {
+			let ssbo_index = self.buffers.storage_buffer.len();
+			let vbo_index = self.buffers.vertex_buffer.len();
+			self.buffers
+				.write(vbo_index, vertices.iter().copied(), ssbo_index, *drawable_data);
+
+			let vertices = vbo_index..self.buffers.vertex_buffer.len();
+			self.batch.write::<D>(&mut self.index_buffer, vertices, indices.iter().copied());
+		}
+	}
+
+	/// Attributes of `SsboVertex`, in location order
+	pub(super) fn vertex_attributes() -> impl Iterator<Item = &'static VertexAttribute> + Clone {
 		const SSBO_ATTRIBUTE: &[VertexAttribute] = &[VertexAttribute::new::<u32>(1)];
-		let stride = <D::Vertex as DrawableData>::Compat::VERTEX_ATTRIBUTES
-			.iter()
-			.chain(SSBO_ATTRIBUTE)
-			.map(|a| a.padding + a.count * a.ty_size)
-			.sum::<usize>() as GLsizei;
 
-		let mut offset = 0;
-		for (i, attribute) in <D::Vertex as DrawableData>::Compat::VERTEX_ATTRIBUTES
+		<D::Vertex as DrawableData>::Compat::VERTEX_ATTRIBUTES
 			.iter()
 			.chain(SSBO_ATTRIBUTE)
-			.enumerate()
-		{
-			offset += attribute.padding;
-
-			if attribute.is_integer {
-				gl::VertexAttribIPointer(
-					i as u32,
-					attribute.count as GLsizei,
-					attribute.ty,
-					stride,
-					offset as *const c_void,
-				);
-			} else {
-				gl::VertexAttribPointer(
-					i as u32,
-					attribute.count as GLsizei,
-					attribute.ty,
-					gl::FALSE,
-					stride,
-					offset as *const c_void,
-				);
-			}
-
-			gl::EnableVertexAttribArray(i as u32);
-
-			offset += attribute.count * attribute.ty_size;
-		}
 	}
 }
 
 impl<D: Drawable> Uploader<D> for SsboUploader<D> {
 	unsafe fn prepare_write(&mut self) {
-		self.vertex_buffer.prepare_write();
+		self.frame_sync.wait_for_frame();
+		self.buffers.prepare_write();
 		self.index_buffer.prepare_write();
-		self.storage_buffer.prepare_write();
 	}
 
 	unsafe fn write(&mut self, drawable: &D) {
 		let drawable_data = drawable.drawable_data().into_ssbo();
 		let (vertex_data, index_data) = drawable.drawable_vertices();
+		let vertex_data = vertex_data.into_iter().map(|vertex| vertex.into_compat());
 
-		let combined_vertex_data = vertex_data.into_iter().map(|vertex| SsboVertex {
-			vertex: vertex.into_compat(),
-			ssbo_index: self.storage_buffer.len() as u32,
-		});
-
-		let vbo_index = self.vertex_buffer.len();
-		let index_data = index_data.into_iter().map(|i| vbo_index as u32 + i);
+		let ssbo_index = self.buffers.storage_buffer.len();
+		let vbo_index = self.buffers.vertex_buffer.len();
+		self.buffers.write(vbo_index, vertex_data, ssbo_index, drawable_data);
 
-		self.vertex_buffer.write().write(vbo_index, combined_vertex_data);
-		let index_buffer_len = self.index_buffer.len();
-		self.index_buffer.write().write(index_buffer_len, index_data);
-		let storage_buffer_len = self.storage_buffer.len();
-		self.storage_buffer.write().write(storage_buffer_len, [drawable_data]);
+		let vertices = vbo_index..self.buffers.vertex_buffer.len();
+		self.batch.write::<D>(&mut self.index_buffer, vertices, index_data);
 	}
 
 	unsafe fn begin_flush(&mut self) {
-		self.vertex_buffer.begin_flush();
+		for encoder in mem::take(&mut self.encoders) {
+			self.merge(&encoder);
+		}
+
+		self.buffers.begin_flush();
 		self.index_buffer.begin_flush();
-		self.storage_buffer.begin_flush();
 	}
 
 	unsafe fn sync_flush(&mut self) {
-		self.vertex_buffer.sync_flush();
+		self.buffers.sync_flush();
 		self.index_buffer.sync_flush();
-		self.storage_buffer.sync_flush();
 	}
 
 	unsafe fn bind(&mut self) {
-		if !(self.vertex_buffer.has_backing_buffer()
-			&& self.index_buffer.has_backing_buffer()
-			&& self.storage_buffer.has_backing_buffer())
+		// non-indexed drawables never write to the index buffer
+		if !(self.buffers.has_backing_buffers()
+			&& (self.index_buffer.has_backing_buffer() || !D::INDEXED))
 		{
 			return
 		}
 
-		let no_vbo = self.vao == 0;
-		if no_vbo {
-			gl::GenVertexArrays(1, &mut self.vao);
-		}
-		gl::BindVertexArray(self.vao);
-
-		self.vertex_buffer.bind();
-		self.index_buffer.bind();
-		self.storage_buffer.bind();
-
-		if no_vbo
-			|| self.vertex_buffer.backing_buffer_changed()
-			|| self.index_buffer.backing_buffer_changed()
-			|| self.storage_buffer.backing_buffer_changed()
-		{
-			Self::set_vertex_attributes();
-			self.vertex_buffer.clear_buffer_changed();
-			self.index_buffer.clear_buffer_changed();
-			self.storage_buffer.backing_buffer_changed();
-		}
+		self.buffers
+			.bind(self.index_buffer.backing_buffer(), self.index_buffer.backing_buffer_changed());
+		self.index_buffer.clear_buffer_changed();
 	}
 
 	unsafe fn upload(&mut self) {
+		if !self.is_ready() {
+			return
+		}
+
 		self.shader.bind();
 		self.bind();
 		self.sync_flush();
-		gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 0, self.storage_buffer.backing_buffer());
-		gl::DrawElements(
-			D::GL_TYPE,
-			self.index_buffer.len() as GLsizei,
-			u32::GL_TYPE,
-			0 as *const c_void,
+		self.buffers.bind_storage();
+		let vertices = self.batch.draw::<D>(
+			&self.index_buffer,
+			self.buffers.vertex_buffer.len(),
+			self.buffers.base_vertex(),
 		);
+		self.draws.record(vertices);
 		self.finish_use();
 	}
 
 	unsafe fn finish_use(&mut self) {
-		// TODO: synchronization
+		let fence = self.frame_sync.end_frame();
+		self.buffers.finish_use(&fence);
+		self.index_buffer.finish_use(&fence);
+	}
+
+	fn set_frame_latency(&mut self, frames: usize) {
+		self.frame_sync.set_frame_latency(frames);
+		let frames = self.frame_sync.frame_latency();
+		self.buffers.set_frame_latency(frames);
+		self.index_buffer.set_frame_latency(frames);
 	}
 
 	unsafe fn clear(&mut self) {
-		self.vertex_buffer.resize(0);
+		self.buffers.vertex_buffer.resize(0);
 		self.index_buffer.resize(0);
-		self.storage_buffer.resize(0);
+		self.batch.clear();
+		self.encoders.clear();
+		self.buffers.storage_buffer.resize(0);
+	}
+
+	fn shader_program(&self) -> ProgramHandle {
+		self.shader.handle()
 	}
 
-	fn shader_program(&self) -> &ShaderProgram {
-		&self.shader
+	fn program_state(&self) -> ProgramState {
+		self.shader.state()
+	}
+
+	fn stats(&self) -> UploaderStats {
+		UploaderStats {
+			draws: self.draws,
+			frame_wait: self.frame_sync.wait_time(),
+			buffers: vec![
+				("vertex", self.buffers.vertex_buffer.stats()),
+				("index", self.index_buffer.stats()),
+				("storage", self.buffers.storage_buffer.stats()),
+			],
+		}
 	}
 }
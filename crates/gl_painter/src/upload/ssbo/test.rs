@@ -0,0 +1,115 @@
+use super::SsboUploader;
+use crate::{
+	drawable::ColoredTriangle,
+	mock_gl::{self, frame, triangle},
+	shader::{registry::SharedProgram, ShaderProgram},
+};
+
+fn uploader() -> SsboUploader<ColoredTriangle> {
+	mock_gl::install(4, 3);
+	SsboUploader::with_program(SharedProgram::new(ShaderProgram { program_object: 1 }))
+}
+
+#[test]
+fn test_first_frame() {
+	let mut uploader = uploader();
+
+	assert_eq!(frame(&mut uploader, &[triangle(0.0)]), [
+		"GenBuffers([1])",
+		"BindBuffer(ARRAY_BUFFER, 1)",
+		"BufferData(ARRAY_BUFFER, 36, DYNAMIC_DRAW)",
+		"BufferSubData(ARRAY_BUFFER, 0, 36)",
+		"GenBuffers([2])",
+		"BindBuffer(SHADER_STORAGE_BUFFER, 2)",
+		"BufferData(SHADER_STORAGE_BUFFER, 16, DYNAMIC_DRAW)",
+		"BufferSubData(SHADER_STORAGE_BUFFER, 0, 16)",
+		"GenBuffers([3])",
+		"BindBuffer(ELEMENT_ARRAY_BUFFER, 3)",
+		"BufferData(ELEMENT_ARRAY_BUFFER, 6, DYNAMIC_DRAW)",
+		"BufferSubData(ELEMENT_ARRAY_BUFFER, 0, 6)",
+		"UseProgram(1)",
+		"GenVertexArrays([4])",
+		"BindVertexArray(4)",
+		"BindBuffer(ARRAY_BUFFER, 1)",
+		"VertexAttribPointer(0, 2, FLOAT, 0, 12, 0)",
+		"EnableVertexAttribArray(0)",
+		"VertexAttribIPointer(1, 1, UNSIGNED_INT, 12, 8)",
+		"EnableVertexAttribArray(1)",
+		"BindVertexArray(4)",
+		"BindBuffer(ELEMENT_ARRAY_BUFFER, 3)",
+		"BindVertexArray(4)",
+		"BindBufferRange(SHADER_STORAGE_BUFFER, 0, 2, 0, 16)",
+		"DrawElements(TRIANGLES, 3, UNSIGNED_SHORT, 0)",
+		"FenceSync(5)",
+	]);
+}
+
+#[test]
+fn test_later_frames() {
+	let mut uploader = uploader();
+	frame(&mut uploader, &[triangle(0.0)]);
+
+	// reallocating the storage buffer must not specify attributes again
+	assert_eq!(frame(&mut uploader, &[triangle(0.0), triangle(2.0)]), [
+		"ClientWaitSync(5, 0)",
+		"DeleteSync(5)",
+		"BindBuffer(ARRAY_BUFFER, 1)",
+		"BufferData(ARRAY_BUFFER, 72, DYNAMIC_DRAW)",
+		"BufferSubData(ARRAY_BUFFER, 0, 72)",
+		"BindBuffer(SHADER_STORAGE_BUFFER, 2)",
+		"BufferData(SHADER_STORAGE_BUFFER, 32, DYNAMIC_DRAW)",
+		"BufferSubData(SHADER_STORAGE_BUFFER, 0, 32)",
+		"BindBuffer(ELEMENT_ARRAY_BUFFER, 3)",
+		"BufferData(ELEMENT_ARRAY_BUFFER, 12, DYNAMIC_DRAW)",
+		"BufferSubData(ELEMENT_ARRAY_BUFFER, 0, 12)",
+		"UseProgram(1)",
+		"BindVertexArray(4)",
+		"BindBufferRange(SHADER_STORAGE_BUFFER, 0, 2, 0, 32)",
+		"DrawElements(TRIANGLES, 6, UNSIGNED_SHORT, 0)",
+		"FenceSync(6)",
+	]);
+
+	assert_eq!(frame(&mut uploader, &[triangle(0.0), triangle(3.0)]), [
+		"ClientWaitSync(6, 0)",
+		"DeleteSync(6)",
+		"BindBuffer(ARRAY_BUFFER, 1)",
+		"BufferSubData(ARRAY_BUFFER, 0, 72)",
+		"BindBuffer(SHADER_STORAGE_BUFFER, 2)",
+		"BufferSubData(SHADER_STORAGE_BUFFER, 0, 32)",
+		"BindBuffer(ELEMENT_ARRAY_BUFFER, 3)",
+		"BufferSubData(ELEMENT_ARRAY_BUFFER, 0, 12)",
+		"UseProgram(1)",
+		"BindVertexArray(4)",
+		"BindBufferRange(SHADER_STORAGE_BUFFER, 0, 2, 0, 32)",
+		"DrawElements(TRIANGLES, 6, UNSIGNED_SHORT, 0)",
+		"FenceSync(7)",
+	]);
+
+	drop(uploader);
+	assert_eq!(mock_gl::take_calls(), [
+		"DeleteVertexArrays([4])",
+		"DeleteBuffers([1])",
+		"DeleteBuffers([2])",
+		"DeleteBuffers([3])",
+		"DeleteSync(7)",
+		"DeleteProgram(1)",
+	]);
+}
+
+#[test]
+fn test_persistent_storage_alignment() {
+	mock_gl::install(4, 4);
+	let mut uploader =
+		SsboUploader::<ColoredTriangle>::with_program(SharedProgram::new(ShaderProgram {
+			program_object: 1,
+		}));
+	frame(&mut uploader, &[triangle(0.0)]);
+
+	// the second frame binds the next segment of the storage ring, which
+	// must start at a multiple of the storage buffer offset alignment
+	let calls = frame(&mut uploader, &[triangle(1.0)]);
+	let bind = calls.iter().filter(|call| call.starts_with("BindBufferRange"));
+	assert_eq!(bind.collect::<Vec<_>>(), [
+		"BindBufferRange(SHADER_STORAGE_BUFFER, 0, 2, 256, 16)"
+	]);
+}
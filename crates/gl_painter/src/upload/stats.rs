@@ -0,0 +1,89 @@
+// Copyright (C) 2022 the ITK authors
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/./
+
+//! Statistics of uploaders and their buffers
+//!
+//! Snapshots are taken with `Uploader::stats` and `GpuBuffer::stats`.
+//! Counters accumulate from creation, so per-frame values are obtained
+//! by taking a snapshot every frame and calling `since` on the next one.
+
+use std::time::Duration;
+
+pub use super::buffer::growth::BufferStats;
+
+#[cfg(test)]
+mod test;
+
+/// Draw calls issued by an uploader
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct DrawStats {
+	/// Number of `glDraw*` calls, counting a multi-draw as one call
+	pub draw_calls: u64,
+	/// Number of vertices drawn, or indices for indexed draws
+	pub vertices: u64,
+}
+
+impl DrawStats {
+	/// Count a draw call of `vertices` vertices
+	pub fn record(&mut self, vertices: usize) {
+		self.draw_calls += 1;
+		self.vertices += vertices as u64;
+	}
+
+	/// Counters accumulated since `earlier`
+	pub fn since(&self, earlier: &Self) -> Self {
+		Self {
+			draw_calls: self.draw_calls - earlier.draw_calls,
+			vertices: self.vertices - earlier.vertices,
+		}
+	}
+}
+
+/// Snapshot of an uploader's statistics
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UploaderStats {
+	pub draws: DrawStats,
+	/// Time `prepare_write` spent waiting for frames in flight
+	pub frame_wait: Duration,
+	/// Statistics of each of the uploader's buffers, by name
+	pub buffers: Vec<(&'static str, BufferStats)>,
+}
+
+impl UploaderStats {
+	/// Counters accumulated since `earlier`, a previous snapshot of the same uploader
+	pub fn since(&self, earlier: &Self) -> Self {
+		Self {
+			draws: self.draws.since(&earlier.draws),
+			frame_wait: self.frame_wait.saturating_sub(earlier.frame_wait),
+			buffers: self
+				.buffers
+				.iter()
+				.map(|(name, stats)| {
+					match earlier.buffers.iter().find(|(earlier_name, _)| earlier_name == name) {
+						Some((_, earlier)) => (*name, stats.since(earlier)),
+						None => (*name, *stats),
+					}
+				})
+				.collect(),
+		}
+	}
+
+	/// Sum of every buffer's statistics
+	///
+	/// `capacity` is left as 0, as buffers have different element sizes.
+	pub fn buffer_total(&self) -> BufferStats {
+		self.buffers
+			.iter()
+			.fold(BufferStats::default(), |total, (_, stats)| BufferStats {
+				capacity: 0,
+				size: total.size + stats.size,
+				allocations: total.allocations + stats.allocations,
+				shrinks: total.shrinks + stats.shrinks,
+				bytes_uploaded: total.bytes_uploaded + stats.bytes_uploaded,
+				fence_wait: total.fence_wait + stats.fence_wait,
+			})
+	}
+}
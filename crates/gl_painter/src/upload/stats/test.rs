@@ -0,0 +1,53 @@
+use std::time::Duration;
+
+use super::{BufferStats, DrawStats, UploaderStats};
+
+fn buffer(size: usize, bytes_uploaded: u64, fence_wait_ms: u64) -> BufferStats {
+	BufferStats {
+		capacity: size / 4,
+		size,
+		allocations: 1,
+		shrinks: 0,
+		bytes_uploaded,
+		fence_wait: Duration::from_millis(fence_wait_ms),
+	}
+}
+
+#[test]
+fn test_uploader_stats() {
+	let mut draws = DrawStats::default();
+	draws.record(3);
+
+	let earlier = UploaderStats {
+		draws,
+		frame_wait: Duration::from_millis(4),
+		buffers: vec![("vertex", buffer(64, 48, 1)), ("index", buffer(16, 12, 0))],
+	};
+
+	draws.record(6);
+
+	let now = UploaderStats {
+		draws,
+		frame_wait: Duration::from_millis(5),
+		buffers: vec![("vertex", buffer(64, 96, 3)), ("index", buffer(32, 24, 0))],
+	};
+
+	let frame = now.since(&earlier);
+	assert_eq!(frame.draws, DrawStats {
+		draw_calls: 1,
+		vertices: 6
+	});
+	assert_eq!(frame.frame_wait, Duration::from_millis(1));
+	assert_eq!(frame.buffers[0].0, "vertex");
+	assert_eq!(frame.buffers[0].1.allocations, 0);
+	assert_eq!(frame.buffers[0].1.bytes_uploaded, 48);
+	assert_eq!(frame.buffers[0].1.fence_wait, Duration::from_millis(2));
+	assert_eq!(frame.buffers[1].1.bytes_uploaded, 12);
+	assert_eq!(frame.buffers[1].1.size, 32);
+
+	let total = now.buffer_total();
+	assert_eq!(total.size, 96);
+	assert_eq!(total.bytes_uploaded, 120);
+	assert_eq!(total.allocations, 2);
+	assert_eq!(total.fence_wait, Duration::from_millis(3));
+}
@@ -0,0 +1,154 @@
+// Copyright (C) 2022 the ITK authors
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/./
+
+//! Tracking of frames in flight on the GPU
+//!
+//! Each `Uploader::upload` ends with `finish_use`, which fences the draw
+//! and hands the fence to every buffer it read. The next `prepare_write`
+//! waits until at most the configured frame latency of uploads are still
+//! in flight, and buffers writing directly into GPU visible memory wait on
+//! or rotate away from the fences of their own data.
+
+use std::{
+	collections::VecDeque,
+	rc::Rc,
+	time::{Duration, Instant},
+};
+
+use gl::types::GLsync;
+
+/// Number of frames the CPU may prepare while the GPU is still drawing
+/// earlier ones, used until `Uploader::set_frame_latency` is called
+pub const DEFAULT_FRAME_LATENCY: usize = 2;
+
+/// Timeout of a single `glClientWaitSync` call, in nanoseconds
+const FENCE_WAIT_TIMEOUT: u64 = 1_000_000;
+
+/// Fence signalled once the GPU has finished every command issued before it
+///
+/// Shared between every buffer read by those commands.
+///
+/// !Send to ensure the sync object is deleted on the same thread
+pub struct Fence {
+	sync: GLsync,
+}
+
+impl Fence {
+	/// Fence every command issued so far
+	///
+	/// # SAFETY
+	/// * must be called from GL thread
+	pub unsafe fn insert() -> Rc<Self> {
+		Rc::new(Self {
+			sync: gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0),
+		})
+	}
+
+	/// Check if the fenced commands have finished, without blocking
+	///
+	/// # SAFETY
+	/// * must be called from GL thread
+	pub unsafe fn is_signalled(&self) -> bool {
+		self.sync.is_null()
+			|| matches!(
+				gl::ClientWaitSync(self.sync, gl::SYNC_FLUSH_COMMANDS_BIT, 0),
+				gl::ALREADY_SIGNALED | gl::CONDITION_SATISFIED
+			)
+	}
+
+	/// Block until the fenced commands have finished, returning the time waited
+	///
+	/// # SAFETY
+	/// * must be called from GL thread
+	pub unsafe fn wait(&self) -> Duration {
+		if self.sync.is_null() {
+			return Duration::ZERO
+		}
+
+		let start = Instant::now();
+
+		// flush on the first wait so the fence is guaranteed to be signalled eventually
+		// stops on ALREADY_SIGNALED, CONDITION_SATISFIED or WAIT_FAILED
+		let mut flags = gl::SYNC_FLUSH_COMMANDS_BIT;
+		while gl::ClientWaitSync(self.sync, flags, FENCE_WAIT_TIMEOUT) == gl::TIMEOUT_EXPIRED {
+			flags = 0;
+		}
+
+		start.elapsed()
+	}
+}
+
+impl Drop for Fence {
+	fn drop(&mut self) {
+		if !self.sync.is_null() {
+			unsafe { gl::DeleteSync(self.sync) };
+		}
+	}
+}
+
+/// Fences of an uploader's frames still in flight
+pub struct FrameSync {
+	frame_latency: usize,
+	/// Oldest first
+	in_flight: VecDeque<Rc<Fence>>,
+	wait_time: Duration,
+}
+
+impl FrameSync {
+	pub fn new() -> Self {
+		Self {
+			frame_latency: DEFAULT_FRAME_LATENCY,
+			in_flight: VecDeque::new(),
+			wait_time: Duration::ZERO,
+		}
+	}
+
+	pub fn frame_latency(&self) -> usize {
+		self.frame_latency
+	}
+
+	/// Set the number of frames that may be in flight, at least 1
+	pub fn set_frame_latency(&mut self, frames: usize) {
+		self.frame_latency = frames.max(1);
+	}
+
+	/// Total time spent blocked in `wait_for_frame`
+	pub fn wait_time(&self) -> Duration {
+		self.wait_time
+	}
+
+	/// Wait until fewer than `frame_latency` frames are in flight
+	///
+	/// # SAFETY
+	/// * must be called from GL thread
+	pub unsafe fn wait_for_frame(&mut self) {
+		while self.in_flight.front().is_some_and(|fence| fence.is_signalled()) {
+			self.in_flight.pop_front();
+		}
+
+		while self.in_flight.len() >= self.frame_latency {
+			if let Some(fence) = self.in_flight.pop_front() {
+				self.wait_time += fence.wait();
+			}
+		}
+	}
+
+	/// Fence the commands issued so far as the end of a frame
+	///
+	/// # SAFETY
+	/// * must be called from GL thread
+	pub unsafe fn end_frame(&mut self) -> Rc<Fence> {
+		let fence = Fence::insert();
+		self.in_flight.push_back(fence.clone());
+		fence
+	}
+}
+
+impl Default for FrameSync {
+	fn default() -> Self {
+		Self::new()
+	}
+}